@@ -0,0 +1,7 @@
+DROP TRIGGER comments_search_delete;
+DROP TRIGGER comments_search_update;
+DROP TRIGGER comments_search_insert;
+DROP TRIGGER tickets_search_delete;
+DROP TRIGGER tickets_search_update;
+DROP TRIGGER tickets_search_insert;
+DROP TABLE search_index;
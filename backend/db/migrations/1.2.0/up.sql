@@ -0,0 +1,34 @@
+CREATE VIRTUAL TABLE search_index USING fts5(
+    ticket_id UNINDEXED,
+    comment_id UNINDEXED,
+    title,
+    description,
+    content,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+INSERT INTO search_index(ticket_id, comment_id, title, description, content)
+SELECT id, NULL, title, description, '' FROM tickets;
+INSERT INTO search_index(ticket_id, comment_id, title, description, content)
+SELECT ticket_id, id, '', '', content FROM comments;
+CREATE TRIGGER tickets_search_insert AFTER INSERT ON tickets BEGIN
+    INSERT INTO search_index(ticket_id, comment_id, title, description, content)
+    VALUES (new.id, NULL, new.title, new.description, '');
+END;
+CREATE TRIGGER tickets_search_update AFTER UPDATE ON tickets BEGIN
+    UPDATE search_index SET ticket_id = new.id, title = new.title, description = new.description
+    WHERE ticket_id = old.id AND comment_id IS NULL;
+END;
+CREATE TRIGGER tickets_search_delete AFTER DELETE ON tickets BEGIN
+    DELETE FROM search_index WHERE ticket_id = old.id;
+END;
+CREATE TRIGGER comments_search_insert AFTER INSERT ON comments BEGIN
+    INSERT INTO search_index(ticket_id, comment_id, title, description, content)
+    VALUES (new.ticket_id, new.id, '', '', new.content);
+END;
+CREATE TRIGGER comments_search_update AFTER UPDATE ON comments BEGIN
+    UPDATE search_index SET ticket_id = new.ticket_id, comment_id = new.id, content = new.content
+    WHERE comment_id = old.id;
+END;
+CREATE TRIGGER comments_search_delete AFTER DELETE ON comments BEGIN
+    DELETE FROM search_index WHERE comment_id = old.id;
+END;
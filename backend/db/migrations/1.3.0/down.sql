@@ -0,0 +1,3 @@
+ALTER TABLE tickets ADD COLUMN is_closed BOOLEAN NOT NULL DEFAULT 0;
+UPDATE tickets SET is_closed = status IN ('resolved', 'closed');
+ALTER TABLE tickets DROP COLUMN status;
@@ -0,0 +1,3 @@
+ALTER TABLE tickets ADD COLUMN status VARCHAR NOT NULL DEFAULT 'new';
+UPDATE tickets SET status = 'closed' WHERE is_closed;
+ALTER TABLE tickets DROP COLUMN is_closed;
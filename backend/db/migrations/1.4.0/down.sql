@@ -0,0 +1,2 @@
+ALTER TABLE tickets DROP COLUMN assignee_id;
+ALTER TABLE users DROP COLUMN mail;
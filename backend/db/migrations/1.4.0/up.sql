@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN mail VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE tickets ADD COLUMN assignee_id INTEGER REFERENCES users(id) ON DELETE SET NULL;
@@ -0,0 +1,2 @@
+DROP TABLE sla_notifications;
+ALTER TABLE tickets DROP COLUMN priority;
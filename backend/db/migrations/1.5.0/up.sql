@@ -0,0 +1,8 @@
+ALTER TABLE tickets ADD COLUMN priority VARCHAR NOT NULL DEFAULT 'normal';
+CREATE TABLE sla_notifications (
+    ticket_id INTEGER NOT NULL,
+    kind VARCHAR NOT NULL,
+    time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (ticket_id, kind),
+    FOREIGN KEY(ticket_id) REFERENCES tickets(id) ON DELETE CASCADE
+);
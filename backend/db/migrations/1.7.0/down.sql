@@ -0,0 +1 @@
+DROP TABLE ticket_events;
\ No newline at end of file
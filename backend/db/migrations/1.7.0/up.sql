@@ -0,0 +1,13 @@
+CREATE TABLE ticket_events (
+    id INTEGER PRIMARY KEY NOT NULL,
+    ticket_id INTEGER,
+    entity VARCHAR NOT NULL,
+    entity_id INTEGER NOT NULL,
+    action VARCHAR NOT NULL,
+    actor_id INTEGER,
+    actor VARCHAR NOT NULL,
+    time TIMESTAMP NOT NULL,
+    changes TEXT NOT NULL DEFAULT '[]'
+);
+CREATE INDEX ticket_events_ticket_id ON ticket_events(ticket_id);
+CREATE INDEX ticket_events_entity ON ticket_events(entity, entity_id);
@@ -0,0 +1,12 @@
+CREATE TABLE attachments (
+    id INTEGER PRIMARY KEY NOT NULL,
+    ticket_id INTEGER NOT NULL,
+    comment_id INTEGER,
+    filename VARCHAR NOT NULL,
+    mime_type VARCHAR NOT NULL,
+    size BIGINT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY(ticket_id) REFERENCES tickets(id) ON DELETE CASCADE,
+    FOREIGN KEY(comment_id) REFERENCES comments(id) ON DELETE SET NULL
+);
+CREATE INDEX attachments_ticket_id ON attachments(ticket_id);
@@ -0,0 +1,3 @@
+ALTER TABLE tickets DROP COLUMN version;
+ALTER TABLE assets DROP COLUMN version;
+ALTER TABLE comments DROP COLUMN version;
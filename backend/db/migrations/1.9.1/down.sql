@@ -0,0 +1,2 @@
+DROP INDEX assets_parent_id;
+ALTER TABLE assets DROP COLUMN parent_id;
@@ -0,0 +1,2 @@
+ALTER TABLE assets ADD COLUMN parent_id INTEGER REFERENCES assets(id) ON DELETE CASCADE;
+CREATE INDEX assets_parent_id ON assets(parent_id);
@@ -0,0 +1,5 @@
+DROP INDEX assets_category_id;
+ALTER TABLE assets DROP COLUMN fields;
+ALTER TABLE assets DROP COLUMN serial_number;
+ALTER TABLE assets DROP COLUMN category_id;
+DROP TABLE asset_categories;
@@ -0,0 +1,9 @@
+CREATE TABLE asset_categories (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name VARCHAR NOT NULL UNIQUE,
+    fields TEXT NOT NULL DEFAULT '[]'
+);
+ALTER TABLE assets ADD COLUMN category_id INTEGER REFERENCES asset_categories(id);
+ALTER TABLE assets ADD COLUMN serial_number VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE assets ADD COLUMN fields TEXT NOT NULL DEFAULT '{}';
+CREATE INDEX assets_category_id ON assets(category_id);
@@ -0,0 +1,2 @@
+DROP TABLE maintenance_tickets;
+DROP TABLE maintenance_plans;
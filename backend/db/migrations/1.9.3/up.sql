@@ -0,0 +1,23 @@
+CREATE TABLE maintenance_plans (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    asset_id INTEGER NOT NULL,
+    recurrence VARCHAR NOT NULL,
+    title VARCHAR NOT NULL,
+    description TEXT NOT NULL,
+    priority VARCHAR NOT NULL DEFAULT 'normal',
+    next_due TIMESTAMP NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT 1,
+    FOREIGN KEY(asset_id) REFERENCES assets(id) ON DELETE CASCADE
+);
+CREATE INDEX maintenance_plans_asset_id ON maintenance_plans(asset_id);
+CREATE INDEX maintenance_plans_next_due ON maintenance_plans(next_due);
+CREATE TABLE maintenance_tickets (
+    id INTEGER PRIMARY KEY NOT NULL,
+    plan_id INTEGER NOT NULL,
+    ticket_id INTEGER,
+    due TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    FOREIGN KEY(plan_id) REFERENCES maintenance_plans(id) ON DELETE CASCADE,
+    FOREIGN KEY(ticket_id) REFERENCES tickets(id) ON DELETE SET NULL
+);
+CREATE INDEX maintenance_tickets_plan_id ON maintenance_tickets(plan_id);
@@ -0,0 +1 @@
+ALTER TABLE assets ADD COLUMN archived BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN builtin BOOLEAN NOT NULL DEFAULT 0;
+UPDATE users SET builtin = 1 WHERE name IN ('admin', 'user');
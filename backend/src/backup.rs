@@ -0,0 +1,356 @@
+use std::{
+    fs::{self, File},
+    future::poll_fn,
+    io::{self, ErrorKind, Read},
+    path::{Component, Path},
+    pin::Pin,
+};
+
+use axum::{
+    Router,
+    body::{Body, HttpBody},
+    extract::State,
+    http::{StatusCode, header},
+    response::IntoResponse,
+    routing::{get, post},
+};
+use chrono::Local;
+use diesel::{
+    connection::SimpleConnection,
+    migration::{MigrationSource, MigrationVersion},
+    prelude::*,
+    sql_types::Text,
+    sqlite::Sqlite,
+};
+use diesel_migrations::MigrationHarness;
+use tokio::{io::AsyncWriteExt, task::spawn_blocking};
+use tokio_util::io::ReaderStream;
+use zip::{ZipArchive, ZipWriter, result::ZipResult, write::SimpleFileOptions};
+
+use crate::{
+    config::{AdminToken, AppState, Config, Db, MIGRATIONS, random_string},
+    errors::ErrResponse,
+    models::user::sync_builtin_users,
+};
+
+const DATABASE_ENTRY: &str = "db.sqlite";
+/// Name of the uploaded archive in the restore directory
+const UPLOAD_FILE: &str = "upload.zip";
+/// Names of the directories saved along with the database in the archive
+const FILE_DIRECTORIES: [&str; 2] = ["photos", "attachments"];
+
+/// Gets the directories saved along with the database, with their name in the archive
+fn file_directories(config: &Config) -> [(&'static str, String); 2] {
+    [
+        ("photos", config.photos_path()),
+        ("attachments", config.attachments_path()),
+    ]
+}
+
+/// Tables holding the data, leaving out the migrations and the search index, which is maintained by triggers
+const DATA_TABLES: &str = r"
+SELECT t.name FROM main.sqlite_master t
+WHERE t.type = 'table'
+AND t.name NOT LIKE 'sqlite\_%' ESCAPE '\'
+AND t.name != '__diesel_schema_migrations'
+AND NOT EXISTS (
+    SELECT 1 FROM main.sqlite_master v
+    WHERE v.sql LIKE 'CREATE VIRTUAL TABLE%'
+    AND (t.name = v.name OR t.name LIKE v.name || '\_%' ESCAPE '\')
+)";
+/// Tables kept as they are: the mails queued by the running instance are still due,
+/// whereas the ones of the archive were sent long ago
+const KEPT_TABLES: [&str; 1] = ["outbox"];
+/// Tables emptied rather than restored, the service level alerts being sent again for the restored tickets
+const CLEARED_TABLES: [&str; 1] = ["sla_notifications"];
+
+#[derive(QueryableByName)]
+struct TableName {
+    #[diesel(sql_type = Text)]
+    name: String,
+}
+
+#[derive(QueryableByName)]
+struct IntegrityCheck {
+    #[diesel(sql_type = Text)]
+    integrity_check: String,
+}
+
+/// Removes a work directory when dropped, whether the restoration succeeded or not
+struct WorkDir(String);
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.0)
+            && e.kind() != ErrorKind::NotFound
+        {
+            println!("error removing {}: {}", self.0, e);
+        }
+    }
+}
+
+pub fn build_admin_router() -> Router<AppState> {
+    Router::new()
+        .route("/backup", get(backup))
+        .route("/restore", post(restore))
+}
+
+async fn backup(
+    _: AdminToken,
+    Db(db): Db,
+    State(config): State<Config>,
+) -> Result<impl IntoResponse, ErrResponse> {
+    // The archives are built and restored in the data directory, on the same file system as the files
+    fs::create_dir_all(&config.data_dir)
+        .map_err(|_| ErrResponse::S500("could not create backup directory"))?;
+    let work_dir = WorkDir(format!("{}/backup-{}", config.data_dir, random_string()));
+    fs::create_dir(&work_dir.0)
+        .map_err(|_| ErrResponse::S500("could not create backup directory"))?;
+    let snapshot = format!("{}/{DATABASE_ENTRY}", work_dir.0);
+    let s = snapshot.clone();
+    // VACUUM INTO makes a consistent copy of the database while the other connections keep working
+    db.interact(move |conn| {
+        diesel::sql_query("VACUUM INTO ?")
+            .bind::<Text, _>(s)
+            .execute(conn)
+    })
+    .await??;
+    let archive = format!("{}/backup.zip", work_dir.0);
+    let a = archive.clone();
+    spawn_blocking(move || write_archive(&config, &snapshot, &a))
+        .await
+        .map_err(|_| ErrResponse::S500("error writing backup archive"))?
+        .map_err(|_| ErrResponse::S500("error writing backup archive"))?;
+    let f = tokio::fs::File::open(&archive)
+        .await
+        .map_err(|_| ErrResponse::S500("error reading backup archive"))?;
+    // The opened archive remains readable once its directory is removed
+    drop(work_dir);
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"tinytickets-{}.zip\"",
+                    Local::now().format("%Y%m%d-%H%M%S")
+                ),
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(f)),
+    ))
+}
+
+fn write_archive(config: &Config, snapshot: &str, archive: &str) -> ZipResult<()> {
+    let mut zip = ZipWriter::new(File::create(archive)?);
+    let options = SimpleFileOptions::default();
+    zip.start_file(DATABASE_ENTRY, options)?;
+    io::copy(&mut File::open(snapshot)?, &mut zip)?;
+    for (name, path) in file_directories(config) {
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                zip.start_file(
+                    format!("{name}/{}", entry.file_name().to_string_lossy()),
+                    options,
+                )?;
+                io::copy(&mut File::open(entry.path())?, &mut zip)?;
+            }
+        }
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+async fn restore(
+    _: AdminToken,
+    State(config): State<Config>,
+    Db(db): Db,
+    content: Body,
+) -> Result<StatusCode, ErrResponse> {
+    let work_dir = WorkDir(format!("{}/restore-{}", config.data_dir, random_string()));
+    fs::create_dir_all(&work_dir.0)
+        .map_err(|_| ErrResponse::S500("could not create restore directory"))?;
+    let max_size = config.backup_max_size;
+    save_upload(content, &format!("{}/{UPLOAD_FILE}", work_dir.0), max_size).await?;
+    let w = work_dir.0.clone();
+    spawn_blocking(move || extract_archive(&w, max_size))
+        .await
+        .map_err(|_| ErrResponse::S500("error reading backup archive"))??;
+    let database = format!("{}/{DATABASE_ENTRY}", work_dir.0);
+    let c = config.clone();
+    db.interact(move |conn| replace_database(conn, &database, &c))
+        .await??;
+    // The files are swapped once the database is committed, as renaming them is unlikely to fail
+    let w = work_dir.0.clone();
+    spawn_blocking(move || swap_directories(&config, &w))
+        .await
+        .map_err(|_| ErrResponse::S500("error restoring files"))?
+        .map_err(|_| ErrResponse::S500("error restoring files"))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Writes the uploaded archive to disk as it is received, refusing it once it exceeds the maximum size
+async fn save_upload(mut content: Body, path: &str, max_size: usize) -> Result<(), ErrResponse> {
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .map_err(|_| ErrResponse::S500("error writing backup archive"))?;
+    let mut size = 0;
+    while let Some(frame) = poll_fn(|cx| Pin::new(&mut content).poll_frame(cx)).await {
+        let frame = frame.map_err(|_| ErrResponse::S400("could not read backup archive"))?;
+        if let Ok(data) = frame.into_data() {
+            size += data.len();
+            if size > max_size {
+                return Err(ErrResponse::S413("backup archive is too large"));
+            }
+            file.write_all(&data)
+                .await
+                .map_err(|_| ErrResponse::S500("error writing backup archive"))?;
+        }
+    }
+    file.flush()
+        .await
+        .map_err(|_| ErrResponse::S500("error writing backup archive"))
+}
+
+/// Extracts the database and the files of the uploaded archive, refusing anything else
+/// and stopping once the extracted files exceed the maximum size
+fn extract_archive(work_dir: &str, max_size: usize) -> Result<(), ErrResponse> {
+    let upload = File::open(format!("{work_dir}/{UPLOAD_FILE}"))
+        .map_err(|_| ErrResponse::S500("error reading backup archive"))?;
+    let mut archive =
+        ZipArchive::new(upload).map_err(|_| ErrResponse::S400("invalid backup archive"))?;
+    let mut remaining = max_size as u64;
+    for name in FILE_DIRECTORIES {
+        fs::create_dir_all(format!("{work_dir}/{name}"))
+            .map_err(|_| ErrResponse::S500("could not create restore directory"))?;
+    }
+    let mut has_database = false;
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .map_err(|_| ErrResponse::S400("invalid backup archive"))?;
+        if file.is_dir() {
+            continue;
+        }
+        let path = file
+            .enclosed_name()
+            .ok_or(ErrResponse::S400("unexpected file in backup archive"))?;
+        let components: Vec<Component> = path.components().collect();
+        match components.as_slice() {
+            [Component::Normal(name)] if name.to_str() == Some(DATABASE_ENTRY) => {
+                has_database = true
+            }
+            [Component::Normal(dir), Component::Normal(_)]
+                if FILE_DIRECTORIES
+                    .iter()
+                    .any(|name| dir.to_str() == Some(name)) => {}
+            _ => return Err(ErrResponse::S400("unexpected file in backup archive")),
+        }
+        let mut out = File::create(Path::new(work_dir).join(&path))
+            .map_err(|_| ErrResponse::S500("error extracting backup archive"))?;
+        // The declared sizes of the entries cannot be trusted, so the copy stops past the remaining size
+        let copied = io::copy(&mut (&mut file).take(remaining + 1), &mut out)
+            .map_err(|_| ErrResponse::S400("invalid backup archive"))?;
+        if copied > remaining {
+            return Err(ErrResponse::S413("backup archive is too large"));
+        }
+        remaining -= copied;
+    }
+    if !has_database {
+        return Err(ErrResponse::S400("backup archive has no database"));
+    }
+    check_database(&format!("{work_dir}/{DATABASE_ENTRY}"))
+}
+
+/// Checks that the database is sound and made by this version of the application or an older one,
+/// migrating it so that its tables match the running database
+fn check_database(path: &str) -> Result<(), ErrResponse> {
+    let mut conn = SqliteConnection::establish(path)
+        .map_err(|_| ErrResponse::S400("invalid backup database"))?;
+    let checks: Vec<IntegrityCheck> = diesel::sql_query("PRAGMA integrity_check")
+        .load(&mut conn)
+        .map_err(|_| ErrResponse::S400("invalid backup database"))?;
+    if checks.iter().any(|c| c.integrity_check != "ok") {
+        return Err(ErrResponse::S400("invalid backup database"));
+    }
+    let known: Vec<MigrationVersion> = MigrationSource::<Sqlite>::migrations(&MIGRATIONS)
+        .map_err(|_| ErrResponse::S500("could not read migrations"))?
+        .iter()
+        .map(|m| m.name().version().as_owned())
+        .collect();
+    let applied = conn
+        .applied_migrations()
+        .map_err(|_| ErrResponse::S400("invalid backup database"))?;
+    if applied.is_empty() {
+        return Err(ErrResponse::S400("backup database has no migrations"));
+    }
+    if applied.iter().any(|v| !known.contains(v)) {
+        return Err(ErrResponse::S409(
+            "backup was made by a newer version of the application",
+        ));
+    }
+    conn.run_pending_migrations(MIGRATIONS)
+        .map_err(|_| ErrResponse::S500("could not migrate backup database"))?;
+    Ok(())
+}
+
+/// Replaces every row of the running database by the ones of the backup, in a single transaction
+fn replace_database(
+    conn: &mut SqliteConnection,
+    path: &str,
+    config: &Config,
+) -> Result<(), ErrResponse> {
+    diesel::sql_query("ATTACH DATABASE ? AS restored")
+        .bind::<Text, _>(path)
+        .execute(conn)?;
+    let replaced = conn.immediate_transaction(|conn| {
+        // The rows are checked against the foreign keys once they are all copied
+        conn.batch_execute("PRAGMA defer_foreign_keys = ON;")?;
+        let tables: Vec<TableName> = diesel::sql_query(DATA_TABLES).load(conn)?;
+        let tables: Vec<&str> = tables
+            .iter()
+            .map(|t| t.name.as_str())
+            .filter(|name| !KEPT_TABLES.contains(name))
+            .collect();
+        for table in &tables {
+            conn.batch_execute(&format!("DELETE FROM main.\"{table}\";"))?;
+        }
+        for table in tables.iter().filter(|name| !CLEARED_TABLES.contains(name)) {
+            conn.batch_execute(&format!(
+                "INSERT INTO main.\"{table}\" SELECT * FROM restored.\"{table}\";"
+            ))?;
+        }
+        let kept = KEPT_TABLES.map(|name| format!("'{name}'")).join(", ");
+        conn.batch_execute(&format!(
+            "DELETE FROM main.sqlite_sequence WHERE name NOT IN ({kept});
+            INSERT INTO main.sqlite_sequence SELECT * FROM restored.sqlite_sequence WHERE name NOT IN ({kept});"
+        ))?;
+        // The built-in users keep the tokens of this instance
+        sync_builtin_users(conn, config)
+    });
+    let detached = diesel::sql_query("DETACH DATABASE restored").execute(conn);
+    replaced?;
+    detached?;
+    Ok(())
+}
+
+/// Puts the extracted files in place, the previous ones being removed along with the work directory
+fn swap_directories(config: &Config, work_dir: &str) -> io::Result<()> {
+    for (name, path) in file_directories(config) {
+        match fs::rename(&path, format!("{work_dir}/{name}.old")) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        if let Some(parent) = Path::new(&path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(format!("{work_dir}/{name}"), path)?;
+    }
+    Ok(())
+}
@@ -1,13 +1,25 @@
-use crate::mail::Mailer;
+use crate::errors::ErrResponse;
+use crate::mail::{MailSettings, Mailer, mailboxes};
+use crate::models::schema::users;
+use crate::models::sla::SlaTargets;
+use crate::models::user::{Role, User, hash_token, sync_builtin_users};
+use crate::models::workflow::{Transition, Workflow};
+use crate::outbox::Outbox;
+use crate::scheduler::parse_schedule;
 use axum::extract::{FromRef, FromRequestParts};
-use axum::http::StatusCode;
 use axum::http::request::Parts;
-use deadpool_diesel::sqlite::Manager;
+use deadpool_diesel::sqlite::{Hook, HookError, Manager};
 use deadpool_diesel::{Pool, Runtime};
+use diesel::{connection::SimpleConnection, prelude::*};
 use diesel_migrations::{EmbeddedMigrations, MigrationHarness, embed_migrations};
 use rand::distr::Alphanumeric;
 use rand::{Rng, rng};
-use std::env;
+use serde::Deserialize;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::{env, fs};
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("db/migrations");
 
@@ -15,6 +27,7 @@ pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("db/migrations");
 pub struct AppState {
     pub config: Config,
     mailer: Mailer,
+    outbox: Outbox,
     pool: Pool<Manager>,
 }
 
@@ -30,6 +43,12 @@ impl FromRef<AppState> for Mailer {
     }
 }
 
+impl FromRef<AppState> for Outbox {
+    fn from_ref(state: &AppState) -> Self {
+        state.outbox.clone()
+    }
+}
+
 impl FromRef<AppState> for Pool<Manager> {
     fn from_ref(state: &AppState) -> Self {
         state.pool.clone()
@@ -37,15 +56,31 @@ impl FromRef<AppState> for Pool<Manager> {
 }
 
 impl AppState {
-    pub async fn new(mailer: Option<Mailer>, debug_mode: bool) -> Self {
+    pub async fn new(config: Config, mailer: Option<Mailer>) -> Self {
+        tracing::info!("Admin token is: {}", config.admin_token);
+        tracing::info!("User token is: {}", config.user_token);
+
         // set up connection pool
-        let manager = Manager::new("db/db.sqlite", Runtime::Tokio1);
+        if let Some(parent) = Path::new(&config.database_url).parent() {
+            fs::create_dir_all(parent).expect("could not create database directory");
+        }
+        let manager = Manager::new(&config.database_url, Runtime::Tokio1);
         let pool = Pool::builder(manager)
-            .max_size(8)
+            .max_size(config.pool_size)
+            // The background workers write concurrently to the requests, wait for the lock instead of failing;
+            // transactions reading before writing must be immediate, as SQLite cannot wait to upgrade a read lock
+            .post_create(Hook::async_fn(|conn, _| {
+                Box::pin(async move {
+                    conn.interact(|conn| conn.batch_execute("PRAGMA busy_timeout = 5000;"))
+                        .await
+                        .map_err(|e| HookError::message(e.to_string()))?
+                        .map_err(|e| HookError::message(e.to_string()))
+                })
+            }))
             .build()
             .expect("could not build database connection pool");
 
-        // run the migrations on server startup
+        // run the migrations on server startup, then make sure that the built-in users match the configuration
         {
             let conn: deadpool_diesel::sqlite::Object = pool
                 .get()
@@ -55,16 +90,23 @@ impl AppState {
                 .await
                 .expect("could not run database migrations")
                 .expect("could not run database migrations");
+            let c = config.clone();
+            conn.interact(move |conn| sync_builtin_users(conn, &c))
+                .await
+                .expect("could not create built-in users")
+                .expect("could not create built-in users");
         }
+        let mailer = mailer.unwrap_or(Mailer::new(config.debug_mode, config.mail.clone()));
         Self {
-            config: Config::init(debug_mode),
-            mailer: mailer.unwrap_or(Mailer::new(debug_mode)),
+            config,
+            mailer,
+            outbox: Outbox::new(),
             pool,
         }
     }
 }
 
-fn random_string() -> std::string::String {
+pub(crate) fn random_string() -> std::string::String {
     rng()
         .sample_iter(&Alphanumeric)
         .take(48)
@@ -74,89 +116,387 @@ fn random_string() -> std::string::String {
 
 #[derive(Clone)]
 pub struct Config {
-    admin_token: String,
-    user_token: String,
+    pub(crate) admin_token: String,
+    pub(crate) user_token: String,
     pub debug_mode: bool,
+    /// Path of the SQLite database
+    pub database_url: String,
+    /// Directory holding the photos and the attachments
+    pub data_dir: String,
+    pub listen_addr: SocketAddr,
+    pub pool_size: usize,
+    pub app_title: String,
+    /// Address of the web app as seen by its users, such as `https://tickets.example.com`, required by the QR codes and labels
+    pub public_url: String,
+    pub mail: MailSettings,
     pub ticket_mail_to: String,
     pub comment_mail_to: String,
+    pub workflow: Workflow,
+    pub sla: SlaTargets,
+    pub sla_warning_hours: i64,
+    pub sla_check_minutes: u64,
+    pub maintenance_check_minutes: u64,
+    pub open_tickets_schedule: Option<cron::Schedule>,
+    pub mail_max_attempts: i32,
+    pub mail_retry_seconds: i64,
+    /// Maximum size of an uploaded attachment, in bytes
+    pub attachment_max_size: usize,
+    /// Maximum size of a restored backup archive, and of the files it holds once extracted, in bytes
+    pub backup_max_size: usize,
+}
+
+/// Settings of the configuration file, named as their environment variable in lower case
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    admin_token: Option<String>,
+    user_token: Option<String>,
+    debug_mode: Option<bool>,
+    database_url: Option<String>,
+    data_dir: Option<String>,
+    listen_addr: Option<SocketAddr>,
+    database_pool_size: Option<usize>,
+    app_title: Option<String>,
+    public_url: Option<String>,
+    mail_server: Option<String>,
+    mail_user: Option<String>,
+    mail_password: Option<String>,
+    mail_from: Option<String>,
+    ticket_mail_to: Option<String>,
+    comment_mail_to: Option<String>,
+    ticket_transitions: Option<Vec<Transition>>,
+    sla_targets: Option<SlaTargets>,
+    sla_warning_hours: Option<i64>,
+    sla_check_minutes: Option<u64>,
+    maintenance_check_minutes: Option<u64>,
+    open_tickets_schedule: Option<String>,
+    mail_max_attempts: Option<i32>,
+    mail_retry_seconds: Option<i64>,
+    attachment_max_mb: Option<usize>,
+    backup_max_mb: Option<usize>,
+}
+
+impl FileConfig {
+    /// Reads the file given by `CONFIG_FILE`, or `config.toml` if it exists
+    fn read() -> Result<Self, String> {
+        let (path, required) = match env::var("CONFIG_FILE") {
+            Ok(path) => (path, true),
+            Err(_) => ("config.toml".to_string(), false),
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|e| format!("invalid configuration file {}: {}", path, e)),
+            Err(e) if required || e.kind() != ErrorKind::NotFound => {
+                Err(format!("could not read configuration file {}: {}", path, e))
+            }
+            Err(_) => Ok(FileConfig::default()),
+        }
+    }
+}
+
+/// Gets a setting from its environment variable, which overrides the configuration file
+fn setting<T: FromStr>(name: &str, file: Option<T>, expected: &str) -> Result<Option<T>, String> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{} must be {}, not `{}`", name, expected, value)),
+        Err(_) => Ok(file),
+    }
+}
+
+/// Checks that a number setting is at least `min`, failing with the message of an unparsable one
+fn at_least<T: PartialOrd + std::fmt::Display>(
+    name: &str,
+    value: T,
+    min: T,
+    expected: &str,
+) -> Result<T, String> {
+    if value < min {
+        return Err(format!("{} must be {}, not `{}`", name, expected, value));
+    }
+    Ok(value)
+}
+
+/// Checks that the mail templates sent by the transitions exist, rather than failing when a mail is queued
+fn check_templates(workflow: &Workflow) -> Result<(), String> {
+    for template in workflow
+        .transitions
+        .iter()
+        .filter_map(|t| t.template.as_ref())
+    {
+        let exists = ["body", "subject"]
+            .iter()
+            .all(|part| Path::new(&format!("templates/{template}_{part}.hbs")).is_file());
+        if !exists {
+            return Err(format!(
+                "TICKET_TRANSITIONS must name existing mail templates, not `{}`",
+                template
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks a comma separated list of addresses, which may be empty
+fn check_recipients(name: &str, to: &str) -> Result<(), String> {
+    if to.is_empty() {
+        return Ok(());
+    }
+    mailboxes(to)
+        .map(|_| ())
+        .map_err(|e| format!("{} must be a list of mail addresses: {}", name, e))
 }
 
 impl Config {
-    pub fn init(debug_mode: bool) -> Config {
+    /// Reads the configuration file and the environment, failing with a message naming the invalid setting
+    pub fn init() -> Result<Config, String> {
+        let file = FileConfig::read()?;
+        let text = "a text";
+        let debug_mode = setting("DEBUG_MODE", file.debug_mode, "true or false")?.unwrap_or(false);
         let admin_token = format!(
             "$ADMIN${}",
-            env::var("ADMIN_TOKEN").unwrap_or_else(|_| random_string())
+            setting("ADMIN_TOKEN", file.admin_token, text)?.unwrap_or_else(random_string)
         );
         let user_token = format!(
             "$USER${}",
-            env::var("USER_TOKEN").unwrap_or_else(|_| random_string())
+            setting("USER_TOKEN", file.user_token, text)?.unwrap_or_else(random_string)
         );
 
-        let ticket_mail_to = env::var("TICKET_MAIL_TO").unwrap_or_default();
-        let comment_mail_to = env::var("COMMENT_MAIL_TO").unwrap_or_default();
+        let database_url = setting("DATABASE_URL", file.database_url, text)?
+            .unwrap_or_else(|| "db/db.sqlite".to_string());
+        let data_dir =
+            setting("DATA_DIR", file.data_dir, text)?.unwrap_or_else(|| "data".to_string());
+        let listen_addr = setting(
+            "LISTEN_ADDR",
+            file.listen_addr,
+            "an address such as 0.0.0.0:8000",
+        )?
+        .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 8000)));
+        let pool_size = setting(
+            "DATABASE_POOL_SIZE",
+            file.database_pool_size,
+            "a number of connections",
+        )?
+        .unwrap_or(8);
+        if pool_size == 0 {
+            return Err("DATABASE_POOL_SIZE must be at least 1".to_string());
+        }
+        let app_title = setting("APP_TITLE", file.app_title, text)?
+            .unwrap_or_else(|| String::from("Tiny Tickets"));
+        let public_url = setting("PUBLIC_URL", file.public_url, text)?
+            .unwrap_or_default()
+            .trim_end_matches('/')
+            .to_string();
+
+        let mail_from = setting("MAIL_FROM", file.mail_from, text)?.unwrap_or_default();
+        let mail = MailSettings {
+            server: setting("MAIL_SERVER", file.mail_server, text)?.unwrap_or_default(),
+            user: setting("MAIL_USER", file.mail_user, text)?.unwrap_or_default(),
+            password: setting("MAIL_PASSWORD", file.mail_password, text)?.unwrap_or_default(),
+            from: match mail_from.as_str() {
+                "" => None,
+                from => Some(
+                    from.parse()
+                        .map_err(|e| format!("MAIL_FROM must be a mail address: {}", e))?,
+                ),
+            },
+        };
+        if !mail.server.is_empty() && mail.from.is_none() {
+            return Err("MAIL_FROM must be set to send mails through MAIL_SERVER".to_string());
+        }
+        let ticket_mail_to =
+            setting("TICKET_MAIL_TO", file.ticket_mail_to, text)?.unwrap_or_default();
+        check_recipients("TICKET_MAIL_TO", &ticket_mail_to)?;
+        let comment_mail_to =
+            setting("COMMENT_MAIL_TO", file.comment_mail_to, text)?.unwrap_or_default();
+        check_recipients("COMMENT_MAIL_TO", &comment_mail_to)?;
 
-        tracing::info!("Admin token is: {}", admin_token);
-        tracing::info!("User token is: {}", user_token);
+        let workflow = match env::var("TICKET_TRANSITIONS") {
+            Ok(json) => Workflow::from_json(&json).map_err(|e| {
+                format!(
+                    "TICKET_TRANSITIONS must be a JSON array of transitions: {}",
+                    e
+                )
+            })?,
+            Err(_) => file
+                .ticket_transitions
+                .map(|transitions| Workflow { transitions })
+                .unwrap_or_default(),
+        };
+        check_templates(&workflow)?;
+        let sla = match env::var("SLA_TARGETS") {
+            Ok(json) => SlaTargets::from_json(&json).map_err(|e| {
+                format!(
+                    "SLA_TARGETS must be a JSON object of targets per priority: {}",
+                    e
+                )
+            })?,
+            Err(_) => file.sla_targets.unwrap_or_default(),
+        };
+        for target in [sla.low, sla.normal, sla.high, sla.urgent] {
+            for hours in [target.response_hours, target.resolution_hours] {
+                at_least("SLA_TARGETS", hours, 0, "numbers of hours of at least 0")?;
+            }
+        }
+        let sla_warning_hours = at_least(
+            "SLA_WARNING_HOURS",
+            setting(
+                "SLA_WARNING_HOURS",
+                file.sla_warning_hours,
+                "a number of hours",
+            )?
+            .unwrap_or(2),
+            0,
+            "a number of hours of at least 0",
+        )?;
+        let sla_check_minutes = setting(
+            "SLA_CHECK_MINUTES",
+            file.sla_check_minutes,
+            "a number of minutes",
+        )?
+        .unwrap_or(15);
+        let maintenance_check_minutes = setting(
+            "MAINTENANCE_CHECK_MINUTES",
+            file.maintenance_check_minutes,
+            "a number of minutes",
+        )?
+        .unwrap_or(15);
+        let open_tickets_schedule =
+            setting("OPEN_TICKETS_SCHEDULE", file.open_tickets_schedule, text)?
+                .map(|e| {
+                    parse_schedule(&e).map_err(|err| {
+                        format!(
+                            "OPEN_TICKETS_SCHEDULE must be a valid cron expression, not `{}`: {}",
+                            e, err
+                        )
+                    })
+                })
+                .transpose()?;
+        let mail_max_attempts = at_least(
+            "MAIL_MAX_ATTEMPTS",
+            setting("MAIL_MAX_ATTEMPTS", file.mail_max_attempts, "a number")?.unwrap_or(8),
+            1,
+            "a number of at least 1",
+        )?;
+        let mail_retry_seconds = at_least(
+            "MAIL_RETRY_SECONDS",
+            setting(
+                "MAIL_RETRY_SECONDS",
+                file.mail_retry_seconds,
+                "a number of seconds",
+            )?
+            .unwrap_or(60),
+            1,
+            "a number of seconds of at least 1",
+        )?;
+        let attachment_max_size = setting(
+            "ATTACHMENT_MAX_MB",
+            file.attachment_max_mb,
+            "a number of megabytes",
+        )?
+        .unwrap_or(10)
+            * 1024
+            * 1024;
+        let backup_max_size =
+            setting("BACKUP_MAX_MB", file.backup_max_mb, "a number of megabytes")?.unwrap_or(1024)
+                * 1024
+                * 1024;
 
-        Config {
+        Ok(Config {
             admin_token,
             user_token,
             debug_mode,
+            database_url,
+            data_dir,
+            listen_addr,
+            pool_size,
+            app_title,
+            public_url,
+            mail,
             ticket_mail_to,
             comment_mail_to,
-        }
+            workflow,
+            sla,
+            sla_warning_hours,
+            sla_check_minutes,
+            maintenance_check_minutes,
+            open_tickets_schedule,
+            mail_max_attempts,
+            mail_retry_seconds,
+            attachment_max_size,
+            backup_max_size,
+        })
+    }
+    /// Directory where the photos of the tickets are stored
+    pub(crate) fn photos_path(&self) -> String {
+        format!("{}/tickets/photos", self.data_dir)
+    }
+
+    /// Directory where the attachments of the tickets are stored
+    pub(crate) fn attachments_path(&self) -> String {
+        format!("{}/tickets/attachments", self.data_dir)
     }
 }
-pub struct AdminToken;
+/// Resolves the `X-TOKEN` header to an enabled user
+async fn authenticate<S>(parts: &Parts, state: &S) -> Result<User, ErrResponse>
+where
+    S: Send + Sync,
+    Pool<Manager>: FromRef<S>,
+{
+    let token = parts
+        .headers
+        .get("X-TOKEN")
+        .ok_or(ErrResponse::S401("`X-TOKEN` header is missing"))?
+        .to_str()
+        .map_err(|_| ErrResponse::S401("`X-TOKEN` header is corrupted"))?;
+    let token_hash = hash_token(token);
+    let db = Pool::from_ref(state)
+        .get()
+        .await
+        .map_err(|_| ErrResponse::S500("database is unreachable"))?;
+    db.interact(move |conn| {
+        users::table
+            .filter(users::token_hash.eq(token_hash))
+            .filter(users::enabled.eq(true))
+            .select(User::as_select())
+            .first(conn)
+            .optional()
+    })
+    .await??
+    .ok_or(ErrResponse::S403("access denied"))
+}
+
+pub struct AdminToken(pub User);
 
 impl<S> FromRequestParts<S> for AdminToken
 where
     S: Send + Sync,
-    Config: FromRef<S>,
+    Pool<Manager>: FromRef<S>,
 {
-    type Rejection = (StatusCode, &'static str);
+    type Rejection = ErrResponse;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let config = Config::from_ref(state);
-        if let Some(token) = parts.headers.get("X-TOKEN") {
-            if token
-                .to_str()
-                .map_err(|_| (StatusCode::UNAUTHORIZED, "`X-TOKEN` header is corrupted"))?
-                == config.admin_token
-            {
-                Ok(AdminToken)
-            } else {
-                Err((StatusCode::FORBIDDEN, "access denied"))
-            }
+        let user = authenticate(parts, state).await?;
+        if user.role == Role::Admin {
+            Ok(AdminToken(user))
         } else {
-            Err((StatusCode::UNAUTHORIZED, "`X-TOKEN` header is missing"))
+            Err(ErrResponse::S403("access denied"))
         }
     }
 }
 
-pub struct UserToken;
+pub struct UserToken(pub User);
 
 impl<S> FromRequestParts<S> for UserToken
 where
     S: Send + Sync,
-    Config: FromRef<S>,
+    Pool<Manager>: FromRef<S>,
 {
-    type Rejection = (StatusCode, &'static str);
+    type Rejection = ErrResponse;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let config = Config::from_ref(state);
-        if let Some(token) = parts.headers.get("X-TOKEN") {
-            let token = token
-                .to_str()
-                .map_err(|_| (StatusCode::UNAUTHORIZED, "`X-TOKEN` header is corrupted"))?;
-            if token == config.user_token || token == config.admin_token {
-                Ok(UserToken)
-            } else {
-                Err((StatusCode::FORBIDDEN, "access denied"))
-            }
-        } else {
-            Err((StatusCode::UNAUTHORIZED, "`X-TOKEN` header is missing"))
-        }
+        Ok(UserToken(authenticate(parts, state).await?))
     }
 }
 
@@ -167,12 +507,12 @@ where
     S: Send + Sync,
     Pool<Manager>: FromRef<S>,
 {
-    type Rejection = (StatusCode, &'static str);
+    type Rejection = ErrResponse;
 
     async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         match Pool::from_ref(state).get().await {
             Ok(db) => Ok(Db(db)),
-            Err(_) => Err((StatusCode::INTERNAL_SERVER_ERROR, "database is unreachable")),
+            Err(_) => Err(ErrResponse::S500("database is unreachable")),
         }
     }
 }
@@ -3,31 +3,122 @@ use std::{
     fmt::{self, Display, Formatter},
 };
 
-use axum::http::StatusCode;
+use axum::{
+    Json,
+    http::{StatusCode, header},
+};
 use deadpool_diesel::InteractError;
+use diesel::result::DatabaseErrorKind;
+use serde::{Deserialize, Serialize};
 
 use axum::response::{IntoResponse, Response};
 
 #[derive(Debug)]
 pub enum ErrResponse {
+    S400(&'static str),
+    S401(&'static str),
     S403(&'static str),
     S404(&'static str),
+    S409(&'static str),
+    S412(&'static str),
+    S413(&'static str),
+    S422(&'static str, Vec<FieldError>),
     S500(&'static str),
 }
 
-impl From<ErrResponse> for (StatusCode, &'static str) {
+/// Error on a single field of the request
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Body of the error responses, served as `application/problem+json`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Problem {
+    pub status: u16,
+    /// Machine-readable kind of error, such as `not_found`
+    pub code: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+impl ErrResponse {
+    fn status(&self) -> StatusCode {
+        match self {
+            ErrResponse::S400(_) => StatusCode::BAD_REQUEST,
+            ErrResponse::S401(_) => StatusCode::UNAUTHORIZED,
+            ErrResponse::S403(_) => StatusCode::FORBIDDEN,
+            ErrResponse::S404(_) => StatusCode::NOT_FOUND,
+            ErrResponse::S409(_) => StatusCode::CONFLICT,
+            ErrResponse::S412(_) => StatusCode::PRECONDITION_FAILED,
+            ErrResponse::S413(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrResponse::S422(..) => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrResponse::S500(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ErrResponse::S400(_) => "bad_request",
+            ErrResponse::S401(_) => "unauthorized",
+            ErrResponse::S403(_) => "forbidden",
+            ErrResponse::S404(_) => "not_found",
+            ErrResponse::S409(_) => "conflict",
+            ErrResponse::S412(_) => "precondition_failed",
+            ErrResponse::S413(_) => "payload_too_large",
+            ErrResponse::S422(..) => "validation_failed",
+            ErrResponse::S500(_) => "internal_error",
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            ErrResponse::S400(message)
+            | ErrResponse::S401(message)
+            | ErrResponse::S403(message)
+            | ErrResponse::S404(message)
+            | ErrResponse::S409(message)
+            | ErrResponse::S412(message)
+            | ErrResponse::S413(message)
+            | ErrResponse::S422(message, _)
+            | ErrResponse::S500(message) => message,
+        }
+    }
+}
+
+impl From<ErrResponse> for Problem {
     fn from(err: ErrResponse) -> Self {
-        match err {
-            ErrResponse::S500(message) => (StatusCode::INTERNAL_SERVER_ERROR, message),
-            ErrResponse::S404(message) => (StatusCode::NOT_FOUND, message),
-            ErrResponse::S403(message) => (StatusCode::FORBIDDEN, message),
+        Problem {
+            status: err.status().as_u16(),
+            code: err.code().to_string(),
+            message: err.message().to_string(),
+            errors: match err {
+                ErrResponse::S422(_, errors) => errors,
+                _ => Vec::new(),
+            },
         }
     }
 }
 
 impl IntoResponse for ErrResponse {
     fn into_response(self) -> Response {
-        Into::<(StatusCode, &'static str)>::into(self).into_response()
+        (
+            self.status(),
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(Problem::from(self)),
+        )
+            .into_response()
     }
 }
 
@@ -49,7 +140,36 @@ impl From<diesel::result::Error> for ErrResponse {
     fn from(err: diesel::result::Error) -> ErrResponse {
         match err {
             diesel::result::Error::NotFound => ErrResponse::S404("data not found in database"),
+            diesel::result::Error::DatabaseError(kind, info) => match kind {
+                DatabaseErrorKind::UniqueViolation => {
+                    ErrResponse::S409("data already exists in database")
+                }
+                DatabaseErrorKind::ForeignKeyViolation => {
+                    ErrResponse::S422("data refers to missing data in database", Vec::new())
+                }
+                DatabaseErrorKind::NotNullViolation => ErrResponse::S422(
+                    "required data is missing",
+                    constraint_fields(info.message(), "is required"),
+                ),
+                DatabaseErrorKind::CheckViolation => {
+                    ErrResponse::S422("data is invalid", Vec::new())
+                }
+                _ => ErrResponse::S500("database error"),
+            },
             _ => ErrResponse::S500("database error"),
         }
     }
 }
+
+/// Gets the fields named by a SQLite constraint message, such as `NOT NULL constraint failed: tickets.title`
+fn constraint_fields(message: &str, error: &str) -> Vec<FieldError> {
+    message
+        .split_once(": ")
+        .map(|(_, columns)| {
+            columns
+                .split(", ")
+                .map(|c| FieldError::new(c.rsplit('.').next().unwrap_or(c), error))
+                .collect()
+        })
+        .unwrap_or_default()
+}
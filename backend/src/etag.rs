@@ -0,0 +1,48 @@
+use axum::{
+    extract::FromRequestParts,
+    http::{HeaderValue, header, request::Parts},
+};
+
+use crate::errors::ErrResponse;
+
+/// Entity tag of a version of an object, sent in the `ETag` header
+pub fn etag(version: i32) -> HeaderValue {
+    HeaderValue::try_from(format!("\"{version}\""))
+        .expect("a quoted number is a valid header value")
+}
+
+/// Entity tags of the `If-Match` header, the update being refused if the object is at another version
+#[derive(Debug, Default)]
+pub struct IfMatch(Option<Vec<String>>);
+
+impl<S> FromRequestParts<S> for IfMatch
+where
+    S: Send + Sync,
+{
+    type Rejection = ErrResponse;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let mut tags = None;
+        for value in parts.headers.get_all(header::IF_MATCH) {
+            let value = value
+                .to_str()
+                .map_err(|_| ErrResponse::S400("invalid If-Match header"))?;
+            tags.get_or_insert_with(Vec::new)
+                .extend(value.split(',').map(|t| t.trim().to_string()));
+        }
+        Ok(IfMatch(tags))
+    }
+}
+
+impl IfMatch {
+    /// Checks that the object is still at one of the versions the client read, when it gave some
+    pub fn check(&self, version: i32) -> Result<(), ErrResponse> {
+        let tag = format!("\"{version}\"");
+        match &self.0 {
+            Some(tags) if !tags.iter().any(|t| t == "*" || *t == tag) => {
+                Err(ErrResponse::S412("object changed since it was read"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -1,10 +1,16 @@
+pub mod backup;
 pub mod config;
 pub mod errors;
+pub mod etag;
 pub mod mail;
 pub mod models;
+pub mod outbox;
+pub mod scheduler;
+pub mod validation;
 
 use axum::{
     Router,
+    extract::State,
     routing::{get, get_service},
 };
 use mail::Mailer;
@@ -12,15 +18,24 @@ use tower_http::{cors::CorsLayer, services::ServeDir};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 use crate::{
-    config::AppState,
+    backup::build_admin_router,
+    config::{AppState, Config},
     models::{
-        asset::build_assets_router, comment::build_comments_router, ticket::build_tickets_router,
+        asset::build_assets_router, category::build_categories_router,
+        comment::build_comments_router, maintenance::build_maintenance_router,
+        search::build_search_router, ticket::build_tickets_router, user::build_users_router,
+    },
+    outbox::build_outbox_router,
+    scheduler::{
+        spawn_maintenance_scheduler, spawn_open_tickets_digest, spawn_outbox_worker,
+        spawn_sla_checker,
     },
 };
 
-pub async fn build_router(mailer: Option<Mailer>) -> Router {
-    let debug_mode = std::env::var("DEBUG_MODE").unwrap_or_default() == "true";
-    tracing_subscriber::registry()
+pub async fn build_router(config: Config, mailer: Option<Mailer>) -> Router {
+    let debug_mode = config.debug_mode;
+    // Several routers may be built by the same process, as in the tests, the first one setting up the logs
+    let _ = tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
                 if debug_mode {
@@ -32,19 +47,26 @@ pub async fn build_router(mailer: Option<Mailer>) -> Router {
             }),
         )
         .with(tracing_subscriber::fmt::layer())
-        .init();
-    let state = AppState::new(mailer, debug_mode).await;
-    let debug_mode = state.config.debug_mode;
+        .try_init();
+    let state = AppState::new(config, mailer).await;
+    spawn_outbox_worker(&state);
+    spawn_sla_checker(&state);
+    spawn_maintenance_scheduler(&state);
+    spawn_open_tickets_digest(&state);
     let router = Router::new()
         .route(
             "/api/app-title",
-            get(|| async {
-                std::env::var("APP_TITLE").unwrap_or_else(|_| String::from("Tiny Tickets"))
-            }),
+            get(|State(config): State<Config>| async move { config.app_title }),
         )
+        .nest("/api/admin", build_admin_router())
         .nest("/api/assets", build_assets_router())
+        .nest("/api/categories", build_categories_router())
         .nest("/api/comments", build_comments_router())
+        .nest("/api/maintenance", build_maintenance_router())
+        .nest("/api/outbox", build_outbox_router())
+        .nest("/api/search", build_search_router())
         .nest("/api/tickets", build_tickets_router())
+        .nest("/api/users", build_users_router())
         .fallback_service(get_service(ServeDir::new("web")))
         .with_state(state);
     if debug_mode {
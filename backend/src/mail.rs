@@ -3,9 +3,17 @@ use lettre::message::{Mailbox, Mailboxes};
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::{Address, Message, SmtpTransport, Transport};
 use std::collections::HashSet;
-use std::env;
 use std::sync::{Arc, Mutex};
 
+/// Settings of the mail server the notifications are sent through
+#[derive(Clone, Default)]
+pub struct MailSettings {
+    pub server: String,
+    pub user: String,
+    pub password: String,
+    pub from: Option<Mailbox>,
+}
+
 pub struct Mailer(Arc<Mutex<dyn InMailer + Send + Sync>>);
 impl Clone for Mailer {
     fn clone(&self) -> Self {
@@ -14,19 +22,24 @@ impl Clone for Mailer {
 }
 
 impl Mailer {
-    pub fn new(test_mode: bool) -> Self {
+    pub fn new(test_mode: bool, settings: MailSettings) -> Self {
         if test_mode {
             Mailer(Arc::new(Mutex::new(MockMailer {
                 test_mails: HashSet::new(),
             })))
         } else {
-            Mailer(Arc::new(Mutex::new(RealMailer {})))
+            Mailer(Arc::new(Mutex::new(RealMailer { settings })))
         }
     }
 
-    pub fn send_mail_to(&mut self, subject: String, body: String, to: String) {
+    pub fn send_mail_to(
+        &mut self,
+        subject: String,
+        body: String,
+        to: String,
+    ) -> Result<(), String> {
         let mut this = self.0.lock().unwrap();
-        this.send_mail_to(subject, body, to);
+        this.send_mail_to(subject, body, to)
     }
 
     #[allow(dead_code)]
@@ -36,57 +49,62 @@ impl Mailer {
 }
 
 trait InMailer {
-    fn send_mail_to(&mut self, subject: String, body: String, to: String);
+    fn send_mail_to(&mut self, subject: String, body: String, to: String) -> Result<(), String>;
     fn print_test_mails(&self) -> String {
         String::from("Not implemented...")
     }
 }
 
-struct RealMailer {}
+struct RealMailer {
+    settings: MailSettings,
+}
 
 impl InMailer for RealMailer {
-    fn send_mail_to(&mut self, subject: String, body: String, to: String) {
-        {
-            let server = env::var("MAIL_SERVER").unwrap_or_default();
-            let user = env::var("MAIL_USER").unwrap_or_default();
-            let password = env::var("MAIL_PASSWORD").unwrap_or_default();
-            let from = env::var("MAIL_FROM").unwrap_or_default();
-
-            let mut mailboxes = Mailboxes::new();
-            let addresses = to.split(',');
-
-            for a in addresses {
-                let address = a
-                    .trim()
-                    .parse::<Address>()
-                    .expect("The MAIL_TO environnement variable is not set properly.");
-                mailboxes.push(Mailbox::new(None, address));
-            }
+    fn send_mail_to(&mut self, subject: String, body: String, to: String) -> Result<(), String> {
+        let MailSettings {
+            server,
+            user,
+            password,
+            from,
+        } = self.settings.clone();
+        let from = from.ok_or("no MAIL_FROM address configured")?;
+
+        let email = Message::builder()
+            .from(from.clone())
+            .reply_to(from)
+            .header(To::from(mailboxes(&to)?))
+            .header(header::ContentType::TEXT_HTML)
+            .subject(subject)
+            .body(body)
+            .map_err(|e| format!("could not create the message: {}", e))?;
+
+        let creds = Credentials::new(user, password);
+
+        // Open a remote connection to gmail
+        let mailer = SmtpTransport::relay(&server)
+            .map_err(|e| format!("could not create the mailer: {}", e))?
+            .credentials(creds)
+            .build();
+
+        // Send the email
+        mailer
+            .send(&email)
+            .map(|_| ())
+            .map_err(|e| format!("could not send email: {}", e))
+    }
+}
 
-            let email = Message::builder()
-                .from(from.parse().unwrap())
-                .reply_to(from.parse().unwrap())
-                .header(To::from(mailboxes))
-                .header(header::ContentType::TEXT_HTML)
-                .subject(subject)
-                .body(body)
-                .expect("Could not send email : could not create the message.");
-
-            let creds = Credentials::new(user, password);
-
-            // Open a remote connection to gmail
-            let mailer = SmtpTransport::relay(&server)
-                .expect("Could not send email : could not create the mailer.")
-                .credentials(creds)
-                .build();
-
-            // Send the email
-            match mailer.send(&email) {
-                Ok(_) => println!("Email sent successfully!"),
-                Err(e) => println!("Could not send email: {:?}", e),
-            }
-        };
+/// Parses a comma separated list of addresses
+pub(crate) fn mailboxes(to: &str) -> Result<Mailboxes, String> {
+    let mut mailboxes = Mailboxes::new();
+    for a in to.split(',') {
+        let address = a
+            .trim()
+            .parse::<Address>()
+            .map_err(|e| format!("invalid recipient address `{}`: {}", a.trim(), e))?;
+        mailboxes.push(Mailbox::new(None, address));
     }
+    Ok(mailboxes)
 }
 
 #[derive(Hash, Eq, PartialEq, Debug, Clone)]
@@ -101,15 +119,20 @@ struct MockMailer {
 }
 
 impl InMailer for MockMailer {
-    fn send_mail_to(&mut self, subject: String, body: String, to: String) {
-        {
-            self.test_mails.insert(Mail {
-                to: to.clone(),
-                subject: subject.clone(),
-                body: body.clone(),
-            });
-            tracing::debug!("Test mail to {}, subject: {}, body:{}", to, subject, body);
-        };
+    fn send_mail_to(&mut self, subject: String, body: String, to: String) -> Result<(), String> {
+        // Reject the messages that a real mail server would not accept, or could not deliver
+        for mailbox in mailboxes(&to)?.iter() {
+            if mailbox.email.domain().ends_with(".invalid") {
+                return Err(format!("unknown domain for address `{}`", mailbox.email));
+            }
+        }
+        self.test_mails.insert(Mail {
+            to: to.clone(),
+            subject: subject.clone(),
+            body: body.clone(),
+        });
+        tracing::debug!("Test mail to {}, subject: {}, body:{}", to, subject, body);
+        Ok(())
     }
 
     fn print_test_mails(&self) -> String {
@@ -1,14 +1,17 @@
-use std::net::SocketAddr;
-use tinytickets_backend::build_router;
+use tinytickets_backend::{build_router, config::Config};
 
 #[tokio::main]
 async fn main() {
+    let config = Config::init().unwrap_or_else(|e| {
+        eprintln!("invalid configuration: {}", e);
+        std::process::exit(1)
+    });
+    let addr = config.listen_addr;
     // run it with hyper
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8000));
     let listener = tokio::net::TcpListener::bind(addr)
         .await
         .expect("could not create listener from address");
-    let app = build_router(None).await.into_make_service();
+    let app = build_router(config, None).await.into_make_service();
     tracing::info!("Tiny tickets backend is listening on {}", addr);
     axum::serve(listener, app).await.unwrap();
 }
@@ -1,20 +1,40 @@
 use axum::{
     Json, Router,
-    extract::Path,
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
     response::IntoResponse,
-    routing::{get, patch},
+    routing::{get, patch, post},
+};
+use deadpool_diesel::sqlite::Object;
+use diesel::{
+    dsl::sql,
+    expression::{AsExpression, SqlLiteral, UncheckedBind},
+    prelude::*,
+    sql_types::{Bool, Double, Integer, Text},
 };
-use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_trim::string_trim;
+use std::collections::HashMap;
 
 use crate::{
-    config::{AdminToken, AppState, Db, UserToken},
+    config::{AdminToken, AppState, Config, Db, UserToken},
     errors::ErrResponse,
+    errors::FieldError,
+    etag::{IfMatch, etag},
+    validation::{
+        TEXT_LENGTH, TITLE_LENGTH, ValidJson, Validate, Validator, nullable, option_trim,
+    },
 };
 
-use super::schema::*;
+use super::{
+    attachment::remove_files,
+    category::{FieldValues, check_asset_fields, is_field_name},
+    history::{Event, EventAction, EventEntity},
+    label::{labels, qrcode},
+    schema::*,
+    ticket::{delete_tickets, remove_photos},
+    user::User,
+};
 
 #[derive(
     Identifiable,
@@ -23,8 +43,8 @@ use super::schema::*;
     Deserialize,
     Serialize,
     Queryable,
+    QueryableByName,
     Insertable,
-    AsChangeset,
     PartialEq,
     Selectable,
 )]
@@ -35,34 +55,230 @@ pub struct Asset {
     pub title: String,
     #[serde(deserialize_with = "string_trim")]
     pub description: String,
+    /// Incremented on every change, and sent as the `ETag` of the object
+    pub version: i32,
+    /// Asset this one is part of, such as the room of a machine or the building of a room
+    #[serde(default)]
+    pub parent_id: Option<i32>,
+    /// Category defining the custom fields of the asset
+    #[serde(default)]
+    pub category_id: Option<i32>,
+    #[serde(default, deserialize_with = "string_trim")]
+    pub serial_number: String,
+    /// Values of the custom fields of the category
+    #[serde(default)]
+    pub fields: FieldValues,
+    /// Archived assets are left out of the assets offered for new tickets, but keep their tickets
+    #[serde(default)]
+    pub archived: bool,
 }
 
-#[derive(Clone, Insertable, Deserialize, Serialize, PartialEq, Debug)]
+#[derive(Clone, Default, Insertable, Deserialize, Serialize, PartialEq, Debug)]
 #[diesel(table_name = assets)]
 pub struct InAsset {
     #[serde(deserialize_with = "string_trim")]
     pub title: String,
     #[serde(deserialize_with = "string_trim")]
     pub description: String,
+    #[serde(default)]
+    pub parent_id: Option<i32>,
+    #[serde(default)]
+    pub category_id: Option<i32>,
+    #[serde(default, deserialize_with = "string_trim")]
+    pub serial_number: String,
+    #[serde(default)]
+    pub fields: FieldValues,
 }
 
 impl PartialEq<InAsset> for Asset {
     fn eq(&self, other: &InAsset) -> bool {
-        self.title == other.title && self.description == other.description
+        self.title == other.title
+            && self.description == other.description
+            && self.parent_id == other.parent_id
+            && self.category_id == other.category_id
+            && self.serial_number == other.serial_number
+            && self.fields == other.fields
+    }
+}
+
+fn check_asset(v: &mut Validator, title: &str, description: &str) {
+    v.required("title", title)
+        .max_length("title", title, TITLE_LENGTH)
+        .max_length("description", description, TEXT_LENGTH);
+}
+
+impl Validate for InAsset {
+    fn check(&self, v: &mut Validator) {
+        check_asset(v, &self.title, &self.description);
+        v.max_length("serial_number", &self.serial_number, TITLE_LENGTH);
+    }
+}
+
+/// Changes to an asset, the missing fields being left untouched
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, AsChangeset)]
+#[diesel(table_name = assets)]
+pub struct AssetPatch {
+    #[serde(default, deserialize_with = "option_trim")]
+    pub title: Option<String>,
+    #[serde(default, deserialize_with = "option_trim")]
+    pub description: Option<String>,
+    /// Moves the asset under another one, or to the top level when null
+    #[serde(default, deserialize_with = "nullable")]
+    pub parent_id: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub category_id: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "option_trim")]
+    pub serial_number: Option<String>,
+    /// Replaces all the custom field values of the asset
+    pub fields: Option<FieldValues>,
+}
+
+impl Validate for AssetPatch {
+    fn check(&self, v: &mut Validator) {
+        if let Some(title) = &self.title {
+            v.required("title", title)
+                .max_length("title", title, TITLE_LENGTH);
+        }
+        if let Some(description) = &self.description {
+            v.max_length("description", description, TEXT_LENGTH);
+        }
+        if let Some(serial_number) = &self.serial_number {
+            v.max_length("serial_number", serial_number, TITLE_LENGTH);
+        }
+    }
+}
+
+/// Filters of the assets listing, the custom fields being given as `field.<name>=<value>`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AssetFilter {
+    /// Lists the archived assets as well
+    pub include_archived: bool,
+    pub category_id: Option<i32>,
+    pub serial_number: Option<String>,
+    pub fields: Vec<(String, String)>,
+}
+
+impl AssetFilter {
+    fn from_params(params: HashMap<String, String>) -> Result<Self, ErrResponse> {
+        let mut filter = AssetFilter::default();
+        for (name, value) in params {
+            match name.as_str() {
+                "category_id" => {
+                    filter.category_id = Some(
+                        value
+                            .parse()
+                            .map_err(|_| ErrResponse::S400("category_id must be a number"))?,
+                    )
+                }
+                "serial_number" => filter.serial_number = Some(value),
+                "include_archived" => {
+                    filter.include_archived = value
+                        .parse()
+                        .map_err(|_| ErrResponse::S400("include_archived must be true or false"))?
+                }
+                _ => {
+                    if let Some(field) = name.strip_prefix("field.") {
+                        if field.is_empty() || !is_field_name(field) {
+                            return Err(ErrResponse::S400(
+                                "field filters must be named after a field of the categories",
+                            ));
+                        }
+                        filter.fields.push((field.to_string(), value));
+                    }
+                }
+            }
+        }
+        Ok(filter)
     }
 }
 
+/// An asset along with all the assets below it
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct AssetTree {
+    #[serde(flatten)]
+    pub asset: Asset,
+    pub children: Vec<AssetTree>,
+}
+
+impl AssetTree {
+    /// Builds the tree of an asset from the assets below it, in any order
+    fn new(asset: Asset, descendants: &[Asset]) -> Self {
+        let children = descendants
+            .iter()
+            .filter(|a| a.parent_id == Some(asset.id))
+            .map(|a| AssetTree::new(a.clone(), descendants))
+            .collect();
+        AssetTree { asset, children }
+    }
+}
+
+/// Matches the rows whose `column` refers to an asset or to one of the assets below it
+pub(crate) fn in_subtree(column: &str, id: i32) -> SubtreeFilter {
+    // Diesel puts the bound id right after the first part of the query
+    sql::<Bool>(&format!(
+        "{column} IN (WITH RECURSIVE subtree(id) AS (SELECT id FROM assets WHERE id = "
+    ))
+    .bind::<Integer, _>(id)
+    .sql(
+        " UNION SELECT a.id FROM assets a JOIN subtree s ON a.parent_id = s.id)
+        SELECT id FROM subtree)",
+    )
+}
+
+pub(crate) type SubtreeFilter =
+    SqlLiteral<Bool, UncheckedBind<SqlLiteral<Bool>, <i32 as AsExpression<Integer>>::Expression>>;
+
+/// Gets an asset along with all its ancestors, from the top level one down to the asset
+pub(crate) fn lineage(conn: &mut SqliteConnection, id: i32) -> QueryResult<Vec<Asset>> {
+    // The depth is bounded in case the assets refer to each other
+    diesel::sql_query(
+        "WITH RECURSIVE lineage(id, depth) AS (
+            SELECT id, 0 FROM assets WHERE id = ?
+            UNION ALL
+            SELECT a.parent_id, l.depth + 1 FROM assets a JOIN lineage l ON a.id = l.id
+            WHERE a.parent_id IS NOT NULL AND l.depth < 100
+        )
+        SELECT assets.* FROM assets JOIN lineage ON assets.id = lineage.id
+        ORDER BY lineage.depth DESC",
+    )
+    .bind::<Integer, _>(id)
+    .load(conn)
+}
+
+/// Gets the titles of an asset and of its ancestors, such as `Site / Building / Room`
+pub(crate) fn asset_path(conn: &mut SqliteConnection, id: i32) -> QueryResult<String> {
+    Ok(lineage(conn, id)?
+        .iter()
+        .map(|a| a.title.as_str())
+        .collect::<Vec<_>>()
+        .join(" / "))
+}
+
 async fn create(
-    _: AdminToken,
+    AdminToken(user): AdminToken,
     Db(db): Db,
-    Json(asset): Json<InAsset>,
+    ValidJson(mut asset): ValidJson<InAsset>,
 ) -> Result<(StatusCode, Json<Asset>), ErrResponse> {
+    asset.fields = asset.fields.without_nulls();
     let asset = db
-        .interact(|conn| {
-            diesel::insert_into(assets::table)
-                .values(asset)
-                .returning(Asset::as_returning())
-                .get_result(conn)
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                check_asset_fields(conn, asset.category_id, &asset.fields)?;
+                let asset = diesel::insert_into(assets::table)
+                    .values(asset)
+                    .returning(Asset::as_returning())
+                    .get_result(conn)?;
+                Event {
+                    entity: EventEntity::Asset,
+                    entity_id: asset.id,
+                    ticket_id: None,
+                    action: EventAction::Create,
+                    old: None,
+                    new: Some(&asset),
+                }
+                .record(conn, &user)?;
+                Ok::<_, ErrResponse>(asset)
+            })
         })
         .await??;
     Ok((StatusCode::CREATED, Json(asset)))
@@ -70,73 +286,408 @@ async fn create(
 
 async fn update(
     Path(id): Path<i32>,
-    AdminToken: AdminToken,
+    AdminToken(user): AdminToken,
     Db(db): Db,
-    Json(asset): Json<Asset>,
-) -> Result<StatusCode, ErrResponse> {
-    db.interact(move |conn| {
-        diesel::update(assets::table.filter(assets::id.eq(id)))
-            .set(asset)
-            .execute(conn)
-    })
-    .await??;
-    Ok(StatusCode::NO_CONTENT)
-}
-
-async fn list(UserToken: UserToken, Db(db): Db) -> Result<impl IntoResponse, ErrResponse> {
+    if_match: IfMatch,
+    ValidJson(mut patch): ValidJson<AssetPatch>,
+) -> Result<impl IntoResponse, ErrResponse> {
+    patch.fields = patch.fields.map(FieldValues::without_nulls);
+    let version = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                let previous: Asset = assets::table.find(id).first(conn)?;
+                if_match.check(previous.version)?;
+                // An asset cannot be moved below itself
+                if let Some(Some(parent_id)) = patch.parent_id {
+                    let subtree: Vec<i32> = assets::table
+                        .select(assets::id)
+                        .filter(in_subtree("assets.id", id))
+                        .load(conn)?;
+                    if subtree.contains(&parent_id) {
+                        return Err(ErrResponse::S422(
+                            "invalid data",
+                            vec![FieldError::new(
+                                "parent_id",
+                                "must not be the asset or one of the assets below it",
+                            )],
+                        ));
+                    }
+                }
+                if patch == AssetPatch::default() {
+                    return Ok(previous.version);
+                }
+                // The values are checked against the category the asset will have
+                if patch.category_id.is_some() || patch.fields.is_some() {
+                    check_asset_fields(
+                        conn,
+                        patch.category_id.unwrap_or(previous.category_id),
+                        patch.fields.as_ref().unwrap_or(&previous.fields),
+                    )?;
+                }
+                let asset: Asset = diesel::update(assets::table.filter(assets::id.eq(id)))
+                    .set((patch, assets::version.eq(assets::version + 1)))
+                    .returning(Asset::as_returning())
+                    .get_result(conn)?;
+                Event {
+                    entity: EventEntity::Asset,
+                    entity_id: id,
+                    ticket_id: None,
+                    action: EventAction::Update,
+                    old: Some(&previous),
+                    new: Some(&asset),
+                }
+                .record(conn, &user)?;
+                Ok::<_, ErrResponse>(asset.version)
+            })
+        })
+        .await??;
+    Ok((StatusCode::NO_CONTENT, [(header::ETAG, etag(version))]))
+}
+
+async fn list(_: UserToken, Db(db): Db) -> Result<impl IntoResponse, ErrResponse> {
     let res: Vec<i32> = db
         .interact(|conn| assets::table.select(assets::id).load(conn))
         .await??;
     Ok(Json(res))
 }
 
-async fn list_all(UserToken: UserToken, Db(db): Db) -> Result<impl IntoResponse, ErrResponse> {
+async fn list_all(
+    _: UserToken,
+    Db(db): Db,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<impl IntoResponse, ErrResponse> {
+    let filter = AssetFilter::from_params(params)?;
     let all_assets: Vec<Asset> = db
-        .interact(|conn| assets::table.order(assets::title).load(conn))
+        .interact(move |conn| {
+            let mut q = assets::table.into_boxed();
+            if !filter.include_archived {
+                q = q.filter(assets::archived.eq(false));
+            }
+            if let Some(category_id) = filter.category_id {
+                q = q.filter(assets::category_id.eq(category_id));
+            }
+            if let Some(serial_number) = filter.serial_number {
+                q = q.filter(assets::serial_number.eq(serial_number));
+            }
+            for (name, value) in filter.fields {
+                let path = format!("$.\"{name}\"");
+                q = match value.parse::<f64>() {
+                    // The numbers are compared as numbers, so that `field.count=3` matches 3.0 as well
+                    Ok(number) if number.is_finite() => q.filter(
+                        sql::<Bool>("CASE WHEN json_type(assets.fields, ")
+                            .bind::<Text, _>(path.clone())
+                            .sql(") IN ('integer', 'real') THEN json_extract(assets.fields, ")
+                            .bind::<Text, _>(path.clone())
+                            .sql(") = ")
+                            .bind::<Double, _>(number)
+                            .sql(" ELSE CAST(json_extract(assets.fields, ")
+                            .bind::<Text, _>(path)
+                            .sql(") AS TEXT) = ")
+                            .bind::<Text, _>(value)
+                            .sql(" END"),
+                    ),
+                    // Other values are compared as text, as the text, date and enum fields are stored
+                    _ => q.filter(
+                        sql::<Bool>("CAST(json_extract(assets.fields, ")
+                            .bind::<Text, _>(path)
+                            .sql(") AS TEXT) = ")
+                            .bind::<Text, _>(value),
+                    ),
+                };
+            }
+            q.order(assets::title).load(conn)
+        })
         .await??;
     Ok(Json(all_assets))
 }
 
 async fn read(
     Path(id): Path<i32>,
-    UserToken: UserToken,
+    _: UserToken,
     Db(db): Db,
-) -> Result<Json<Asset>, ErrResponse> {
+) -> Result<impl IntoResponse, ErrResponse> {
     let asset: Asset = db
         .interact(move |conn| assets::table.filter(assets::id.eq(id)).first(conn))
         .await??;
-    Ok(Json(asset))
+    Ok(([(header::ETAG, etag(asset.version))], Json(asset)))
+}
+
+/// Confirmation of a hard delete, which removes the tickets of the assets as well
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub struct DeleteQuery {
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+/// What deleting assets removes, the assets below them, their tickets and their maintenance plans being deleted as well
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub struct DeletionReport {
+    pub assets: i64,
+    pub tickets: i64,
+    pub comments: i64,
+    pub attachments: i64,
+    pub maintenance_plans: i64,
+}
+
+impl DeletionReport {
+    fn new(
+        conn: &mut SqliteConnection,
+        asset_ids: &[i32],
+        ticket_ids: &[i32],
+    ) -> QueryResult<Self> {
+        Ok(DeletionReport {
+            assets: asset_ids.len() as i64,
+            tickets: ticket_ids.len() as i64,
+            comments: comments::table
+                .filter(comments::ticket_id.eq_any(ticket_ids))
+                .count()
+                .get_result(conn)?,
+            attachments: attachments::table
+                .filter(attachments::ticket_id.eq_any(ticket_ids))
+                .count()
+                .get_result(conn)?,
+            maintenance_plans: maintenance_plans::table
+                .filter(maintenance_plans::asset_id.eq_any(asset_ids))
+                .count()
+                .get_result(conn)?,
+        })
+    }
+}
+
+/// Gets the ids of an asset and of the assets below it, or of all the assets, along with the ids of their tickets
+fn deleted_assets(
+    conn: &mut SqliteConnection,
+    id: Option<i32>,
+) -> QueryResult<(Vec<i32>, Vec<i32>)> {
+    let mut q = assets::table.select(assets::id).into_boxed();
+    if let Some(id) = id {
+        q = q.filter(in_subtree("assets.id", id));
+    }
+    let asset_ids: Vec<i32> = q.load(conn)?;
+    let ticket_ids = tickets::table
+        .select(tickets::id)
+        .filter(tickets::asset_id.eq_any(&asset_ids))
+        .load(conn)?;
+    Ok((asset_ids, ticket_ids))
+}
+
+/// Deletes assets along with their tickets and maintenance plans, returning what was deleted, the ids of the deleted tickets
+/// and the ids of their attachments, whose files are to be removed once the transaction is committed
+fn delete_assets(
+    conn: &mut SqliteConnection,
+    actor: &User,
+    (asset_ids, ticket_ids): (Vec<i32>, Vec<i32>),
+) -> Result<(DeletionReport, Vec<i32>, Vec<i32>), diesel::result::Error> {
+    let report = DeletionReport::new(conn, &asset_ids, &ticket_ids)?;
+    let (tickets, attachments) = delete_tickets(conn, actor, &ticket_ids)?;
+    diesel::delete(maintenance_plans::table)
+        .filter(maintenance_plans::asset_id.eq_any(&asset_ids))
+        .execute(conn)?;
+    let deleted: Vec<Asset> = diesel::delete(assets::table)
+        .filter(assets::id.eq_any(asset_ids))
+        .returning(Asset::as_returning())
+        .get_results(conn)?;
+    record_deletions(conn, actor, &deleted)?;
+    Ok((report, tickets, attachments))
+}
+
+async fn deletion(
+    Path(id): Path<i32>,
+    _: AdminToken,
+    Db(db): Db,
+) -> Result<Json<DeletionReport>, ErrResponse> {
+    let report = db
+        .interact(move |conn| {
+            let (asset_ids, ticket_ids) = deleted_assets(conn, Some(id))?;
+            DeletionReport::new(conn, &asset_ids, &ticket_ids)
+        })
+        .await??;
+    if report.assets == 0 {
+        return Err(ErrResponse::S404("object not found in database"));
+    }
+    Ok(Json(report))
 }
 
 async fn delete(
     Path(id): Path<i32>,
-    AdminToken: AdminToken,
+    AdminToken(user): AdminToken,
     Db(db): Db,
-) -> Result<(), ErrResponse> {
-    if db
+    State(config): State<Config>,
+    Query(query): Query<DeleteQuery>,
+) -> Result<Json<DeletionReport>, ErrResponse> {
+    if !query.confirm {
+        return Err(ErrResponse::S400(
+            "deleting an asset deletes its tickets, confirm=true is required",
+        ));
+    }
+    let (report, tickets, attachments) = db
         .interact(move |conn| {
-            diesel::delete(assets::table)
-                .filter(assets::id.eq(id))
-                .execute(conn)
+            conn.immediate_transaction(|conn| {
+                // The assets below are deleted as well
+                let deleted = deleted_assets(conn, Some(id))?;
+                delete_assets(conn, &user, deleted)
+            })
         })
-        .await??
-        == 1
-    {
-        Ok(())
-    } else {
-        Err(ErrResponse::S404("object not found in database"))
+        .await??;
+    remove_files(&config, &attachments);
+    remove_photos(&config, &tickets);
+    if report.assets == 0 {
+        return Err(ErrResponse::S404("object not found in database"));
     }
+    Ok(Json(report))
 }
 
-async fn destroy(AdminToken: AdminToken, Db(db): Db) -> Result<(), ErrResponse> {
-    db.interact(move |conn| diesel::delete(assets::table).execute(conn))
+async fn destroy(
+    AdminToken(user): AdminToken,
+    Db(db): Db,
+    State(config): State<Config>,
+    Query(query): Query<DeleteQuery>,
+) -> Result<Json<DeletionReport>, ErrResponse> {
+    if !query.confirm {
+        return Err(ErrResponse::S400(
+            "deleting the assets deletes all the tickets, confirm=true is required",
+        ));
+    }
+    let (report, tickets, attachments) = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                let deleted = deleted_assets(conn, None)?;
+                delete_assets(conn, &user, deleted)
+            })
+        })
         .await??;
+    remove_files(&config, &attachments);
+    remove_photos(&config, &tickets);
+    Ok(Json(report))
+}
+
+fn record_deletions(
+    conn: &mut SqliteConnection,
+    actor: &User,
+    deleted: &[Asset],
+) -> Result<(), diesel::result::Error> {
+    for asset in deleted {
+        Event {
+            entity: EventEntity::Asset,
+            entity_id: asset.id,
+            ticket_id: None,
+            action: EventAction::Delete,
+            old: Some(asset),
+            new: None,
+        }
+        .record(conn, actor)?;
+    }
     Ok(())
 }
 
+async fn archive(
+    Path(id): Path<i32>,
+    AdminToken(user): AdminToken,
+    Db(db): Db,
+    if_match: IfMatch,
+) -> Result<impl IntoResponse, ErrResponse> {
+    set_archived(db, user, id, if_match, true).await
+}
+
+async fn unarchive(
+    Path(id): Path<i32>,
+    AdminToken(user): AdminToken,
+    Db(db): Db,
+    if_match: IfMatch,
+) -> Result<impl IntoResponse, ErrResponse> {
+    set_archived(db, user, id, if_match, false).await
+}
+
+/// Archives or restores an asset along with the assets below it
+async fn set_archived(
+    db: Object,
+    user: User,
+    id: i32,
+    if_match: IfMatch,
+    archived: bool,
+) -> Result<impl IntoResponse, ErrResponse> {
+    let version = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                let asset: Asset = assets::table.find(id).first(conn)?;
+                if_match.check(asset.version)?;
+                let previous: Vec<Asset> = assets::table
+                    .filter(in_subtree("assets.id", id))
+                    .filter(assets::archived.ne(archived))
+                    .load(conn)?;
+                for previous in previous {
+                    let changed: Asset =
+                        diesel::update(assets::table.filter(assets::id.eq(previous.id)))
+                            .set((
+                                assets::archived.eq(archived),
+                                assets::version.eq(assets::version + 1),
+                            ))
+                            .returning(Asset::as_returning())
+                            .get_result(conn)?;
+                    Event {
+                        entity: EventEntity::Asset,
+                        entity_id: changed.id,
+                        ticket_id: None,
+                        action: EventAction::Update,
+                        old: Some(&previous),
+                        new: Some(&changed),
+                    }
+                    .record(conn, &user)?;
+                }
+                assets::table
+                    .find(id)
+                    .select(assets::version)
+                    .first::<i32>(conn)
+                    .map_err(ErrResponse::from)
+            })
+        })
+        .await??;
+    Ok((StatusCode::NO_CONTENT, [(header::ETAG, etag(version))]))
+}
+
 pub fn build_assets_router() -> Router<AppState> {
     Router::new()
         .route("/", get(list).post(create).delete(destroy))
         .route("/all", get(list_all))
+        .route("/labels", get(labels))
         .route("/{id}", patch(update).delete(delete).get(read))
+        .route("/{id}/deletion", get(deletion))
+        .route("/{id}/archive", post(archive))
+        .route("/{id}/unarchive", post(unarchive))
+        .route("/{id}/subtree", get(subtree))
+        .route("/{id}/ancestors", get(ancestors))
+        .route("/{id}/qrcode", get(qrcode))
+}
+
+async fn subtree(
+    Path(id): Path<i32>,
+    _: UserToken,
+    Db(db): Db,
+) -> Result<Json<AssetTree>, ErrResponse> {
+    let assets: Vec<Asset> = db
+        .interact(move |conn| {
+            assets::table
+                .filter(in_subtree("assets.id", id))
+                .order((assets::title, assets::id))
+                .load(conn)
+        })
+        .await??;
+    let asset = assets
+        .iter()
+        .find(|a| a.id == id)
+        .cloned()
+        .ok_or(ErrResponse::S404("data not found in database"))?;
+    Ok(Json(AssetTree::new(asset, &assets)))
+}
+
+async fn ancestors(
+    Path(id): Path<i32>,
+    _: UserToken,
+    Db(db): Db,
+) -> Result<Json<Vec<Asset>>, ErrResponse> {
+    let mut lineage = db.interact(move |conn| lineage(conn, id)).await??;
+    if lineage.pop().is_none() {
+        return Err(ErrResponse::S404("data not found in database"));
+    }
+    Ok(Json(lineage))
 }
@@ -0,0 +1,322 @@
+use std::{fs, io::Cursor};
+
+use axum::{
+    Json,
+    body::{self, Body},
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+};
+use chrono::Local;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use tokio::{fs::File, task::spawn_blocking};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    config::{Config, Db, UserToken},
+    errors::ErrResponse,
+    models::{
+        history::{Event, EventAction, EventEntity},
+        schema::{attachments, comments, tickets},
+        ticket::{Ticket, shrink},
+        user::User,
+    },
+    validation::{NAME_LENGTH, Validate, Validator},
+};
+
+#[derive(
+    Identifiable,
+    Associations,
+    Debug,
+    Clone,
+    Deserialize,
+    Serialize,
+    Queryable,
+    PartialEq,
+    Selectable,
+)]
+#[diesel(table_name = attachments, belongs_to(Ticket))]
+pub struct Attachment {
+    pub id: i32,
+    pub ticket_id: i32,
+    /// Comment the file was attached with, if any
+    pub comment_id: Option<i32>,
+    pub filename: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = attachments)]
+struct NewAttachment {
+    ticket_id: i32,
+    comment_id: Option<i32>,
+    filename: String,
+    mime_type: String,
+    size: i64,
+    created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct UploadQuery {
+    pub filename: String,
+    pub comment_id: Option<i32>,
+}
+
+impl Validate for UploadQuery {
+    fn check(&self, v: &mut Validator) {
+        v.required("filename", &self.filename)
+            .max_length("filename", &self.filename, NAME_LENGTH);
+    }
+}
+
+fn attachment_filename(config: &Config, id: i32) -> String {
+    format!("{}/{id}", config.attachments_path())
+}
+
+/// Resizes the images as the tickets photos and stores them as JPEG, other files being stored as is
+fn prepare(
+    content: body::Bytes,
+    filename: String,
+    mime_type: String,
+) -> Result<(Vec<u8>, String, String), ErrResponse> {
+    match image::load_from_memory(&content) {
+        Ok(image) => {
+            let mut jpeg = Vec::new();
+            shrink(&image)
+                .to_rgb8()
+                .write_to(&mut Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+                .map_err(|_| ErrResponse::S500("error saving image"))?;
+            let stem = filename
+                .rsplit_once('.')
+                .map_or(filename.as_str(), |(stem, _)| stem);
+            Ok((jpeg, format!("{stem}.jpg"), "image/jpeg".to_string()))
+        }
+        Err(_) => Ok((content.to_vec(), filename, mime_type)),
+    }
+}
+
+pub(crate) async fn upload(
+    Path(ticket_id): Path<i32>,
+    UserToken(user): UserToken,
+    Db(db): Db,
+    State(config): State<Config>,
+    Query(query): Query<UploadQuery>,
+    headers: HeaderMap,
+    content: Body,
+) -> Result<(StatusCode, Json<Attachment>), ErrResponse> {
+    query.validate()?;
+    let content = body::to_bytes(content, config.attachment_max_size)
+        .await
+        .map_err(|_| ErrResponse::S413("attachment is too large"))?;
+    let mime_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|m| m.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let (content, filename, mime_type) =
+        spawn_blocking(move || prepare(content, query.filename, mime_type))
+            .await
+            .map_err(|_| ErrResponse::S500("error processing attachment"))??;
+    fs::create_dir_all(config.attachments_path())
+        .map_err(|_| ErrResponse::S500("could not create attachments directory"))?;
+    let new_attachment = NewAttachment {
+        ticket_id,
+        comment_id: query.comment_id,
+        filename,
+        mime_type,
+        size: content.len() as i64,
+        created_at: Local::now().naive_local(),
+    };
+    let attachment = db
+        .interact(move |conn| {
+            let mut written = None;
+            let attachment = conn.immediate_transaction(|conn| {
+                tickets::table
+                    .find(ticket_id)
+                    .select(tickets::id)
+                    .first::<i32>(conn)
+                    .map_err(|_| {
+                        ErrResponse::S404("cannot attach a file to a non existing ticket")
+                    })?;
+                if let Some(comment_id) = new_attachment.comment_id {
+                    comments::table
+                        .find(comment_id)
+                        .filter(comments::ticket_id.eq(ticket_id))
+                        .select(comments::id)
+                        .first::<i32>(conn)
+                        .map_err(|_| ErrResponse::S404("comment not found for this ticket"))?;
+                }
+                let attachment: Attachment = diesel::insert_into(attachments::table)
+                    .values(new_attachment)
+                    .returning(Attachment::as_returning())
+                    .get_result(conn)?;
+                Event {
+                    entity: EventEntity::Attachment,
+                    entity_id: attachment.id,
+                    ticket_id: Some(ticket_id),
+                    action: EventAction::Create,
+                    old: None,
+                    new: Some(&attachment),
+                }
+                .record(conn, &user)?;
+                // Writing the file within the transaction keeps the table and the files consistent
+                fs::write(attachment_filename(&config, attachment.id), &content)
+                    .map_err(|_| ErrResponse::S500("error saving attachment"))?;
+                written = Some(attachment.id);
+                Ok::<_, ErrResponse>(attachment)
+            });
+            // The file of an attachment that could not be committed would be left orphaned
+            if let (Err(_), Some(id)) = (&attachment, written) {
+                remove_files(&config, &[id]);
+            }
+            attachment
+        })
+        .await??;
+    Ok((StatusCode::CREATED, Json(attachment)))
+}
+
+pub(crate) async fn list(
+    Path(ticket_id): Path<i32>,
+    _: UserToken,
+    Db(db): Db,
+) -> Result<Json<Vec<Attachment>>, ErrResponse> {
+    let attachments = db
+        .interact(move |conn| {
+            attachments::table
+                .filter(attachments::ticket_id.eq(ticket_id))
+                .order((attachments::created_at.asc(), attachments::id.asc()))
+                .load(conn)
+        })
+        .await??;
+    Ok(Json(attachments))
+}
+
+/// Builds a `Content-Disposition` header keeping the original file name, with an ASCII fallback for older clients
+fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let encoded: String = filename
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect();
+    format!("attachment; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+pub(crate) async fn download(
+    Path((ticket_id, id)): Path<(i32, i32)>,
+    _: UserToken,
+    Db(db): Db,
+    State(config): State<Config>,
+) -> Result<impl IntoResponse, ErrResponse> {
+    let attachment: Attachment = db
+        .interact(move |conn| {
+            attachments::table
+                .find(id)
+                .filter(attachments::ticket_id.eq(ticket_id))
+                .first(conn)
+        })
+        .await??;
+    let f = File::open(attachment_filename(&config, attachment.id))
+        .await
+        .map_err(|_| ErrResponse::S404("attachment file is missing"))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, attachment.mime_type),
+            (
+                header::CONTENT_DISPOSITION,
+                content_disposition(&attachment.filename),
+            ),
+            (header::CONTENT_LENGTH, attachment.size.to_string()),
+        ],
+        Body::from_stream(ReaderStream::new(f)),
+    ))
+}
+
+pub(crate) async fn delete(
+    Path((ticket_id, id)): Path<(i32, i32)>,
+    UserToken(user): UserToken,
+    Db(db): Db,
+    State(config): State<Config>,
+) -> Result<StatusCode, ErrResponse> {
+    let deleted: Vec<Attachment> = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                let deleted = diesel::delete(attachments::table)
+                    .filter(attachments::id.eq(id))
+                    .filter(attachments::ticket_id.eq(ticket_id))
+                    .returning(Attachment::as_returning())
+                    .get_results(conn)?;
+                record_deletions(conn, &user, &deleted)?;
+                Ok::<_, diesel::result::Error>(deleted)
+            })
+        })
+        .await??;
+    if deleted.len() == 1 {
+        remove_files(&config, &[id]);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ErrResponse::S404("object not found in database"))
+    }
+}
+
+/// Deletes the attachments of the given tickets, before the tickets themselves as the foreign key cascades,
+/// returning their ids so that their files can be removed once committed
+pub(crate) fn delete_ticket_attachments(
+    conn: &mut SqliteConnection,
+    actor: &User,
+    ticket_ids: &[i32],
+) -> QueryResult<Vec<i32>> {
+    let deleted: Vec<Attachment> = diesel::delete(attachments::table)
+        .filter(attachments::ticket_id.eq_any(ticket_ids))
+        .returning(Attachment::as_returning())
+        .get_results(conn)?;
+    record_deletions(conn, actor, &deleted)?;
+    Ok(deleted.iter().map(|a| a.id).collect())
+}
+
+fn record_deletions(
+    conn: &mut SqliteConnection,
+    actor: &User,
+    deleted: &[Attachment],
+) -> QueryResult<()> {
+    for attachment in deleted {
+        Event {
+            entity: EventEntity::Attachment,
+            entity_id: attachment.id,
+            ticket_id: Some(attachment.ticket_id),
+            action: EventAction::Delete,
+            old: Some(attachment),
+            new: None,
+        }
+        .record(conn, actor)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn remove_files(config: &Config, ids: &[i32]) {
+    for id in ids {
+        match fs::remove_file(attachment_filename(config, *id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                println!("error removing attachment with id {}: {}", id, e);
+            }
+            _ => {}
+        }
+    }
+}
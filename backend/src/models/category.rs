@@ -0,0 +1,388 @@
+use axum::{
+    Json, Router,
+    extract::Path,
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, patch},
+};
+use chrono::NaiveDate;
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    prelude::*,
+    serialize::{self, IsNull, Output, ToSql},
+    sql_types::Text,
+    sqlite::{Sqlite, SqliteValue},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use serde_trim::string_trim;
+
+use crate::{
+    config::{AdminToken, AppState, Db, UserToken},
+    errors::ErrResponse,
+    validation::{NAME_LENGTH, TEXT_LENGTH, ValidJson, Validate, Validator, option_trim},
+};
+
+use super::schema::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldKind {
+    Text,
+    Number,
+    /// A day, such as `2024-12-31`
+    Date,
+    /// One of the options of the field
+    Enum,
+}
+
+/// Custom field of the assets of a category
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FieldDefinition {
+    /// Name of the field in the asset values, made of lower case letters, digits and underscores
+    #[serde(deserialize_with = "string_trim")]
+    pub name: String,
+    pub kind: FieldKind,
+    #[serde(default)]
+    pub required: bool,
+    /// Allowed values of an `enum` field
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+}
+
+/// Custom fields of a category, stored as a JSON array
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+#[serde(transparent)]
+pub struct FieldDefinitions(pub Vec<FieldDefinition>);
+
+impl ToSql<Text, Sqlite> for FieldDefinitions {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        out.set_value(serde_json::to_string(&self.0)?);
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Sqlite> for FieldDefinitions {
+    fn from_sql(bytes: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        let value = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        Ok(FieldDefinitions(serde_json::from_str(&value)?))
+    }
+}
+
+/// Values of the custom fields of an asset by field name, stored as a JSON object
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+#[serde(transparent)]
+pub struct FieldValues(pub Map<String, Value>);
+
+impl ToSql<Text, Sqlite> for FieldValues {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        out.set_value(serde_json::to_string(&self.0)?);
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Sqlite> for FieldValues {
+    fn from_sql(bytes: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        let value = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        Ok(FieldValues(serde_json::from_str(&value)?))
+    }
+}
+
+impl FieldValues {
+    /// Drops the null values, a null value being the same as a missing one
+    pub fn without_nulls(mut self) -> Self {
+        self.0.retain(|_, value| !value.is_null());
+        self
+    }
+
+    /// Formats the values as `name: value` lines, for the exports
+    pub fn to_lines(&self) -> String {
+        self.0
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, display_value(value)))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+/// Displays a value without the quotes of the JSON strings
+pub fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+#[derive(Identifiable, Debug, Clone, Deserialize, Serialize, Queryable, PartialEq, Selectable)]
+#[diesel(table_name = asset_categories)]
+pub struct Category {
+    pub id: i32,
+    pub name: String,
+    pub fields: FieldDefinitions,
+}
+
+#[derive(Clone, Insertable, Deserialize, Serialize, PartialEq, Debug)]
+#[diesel(table_name = asset_categories)]
+pub struct InCategory {
+    #[serde(deserialize_with = "string_trim")]
+    pub name: String,
+    #[serde(default)]
+    pub fields: FieldDefinitions,
+}
+
+impl PartialEq<InCategory> for Category {
+    fn eq(&self, other: &InCategory) -> bool {
+        self.name == other.name && self.fields == other.fields
+    }
+}
+
+impl Validate for InCategory {
+    fn check(&self, v: &mut Validator) {
+        v.required("name", &self.name)
+            .max_length("name", &self.name, NAME_LENGTH);
+        self.fields.check(v);
+    }
+}
+
+/// Changes to a category, the missing fields being left untouched
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, AsChangeset)]
+#[diesel(table_name = asset_categories)]
+pub struct CategoryPatch {
+    #[serde(default, deserialize_with = "option_trim")]
+    pub name: Option<String>,
+    pub fields: Option<FieldDefinitions>,
+}
+
+impl Validate for CategoryPatch {
+    fn check(&self, v: &mut Validator) {
+        if let Some(name) = &self.name {
+            v.required("name", name)
+                .max_length("name", name, NAME_LENGTH);
+        }
+        if let Some(fields) = &self.fields {
+            fields.check(v);
+        }
+    }
+}
+
+/// Tells whether a name is made of lower case letters, digits and underscores, as the names of the fields
+pub(crate) fn is_field_name(name: &str) -> bool {
+    name.chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+impl FieldDefinitions {
+    fn check(&self, v: &mut Validator) {
+        for (i, field) in self.0.iter().enumerate() {
+            let name = format!("fields.{i}.name");
+            v.required(&name, &field.name)
+                .max_length(&name, &field.name, NAME_LENGTH);
+            if !is_field_name(&field.name) {
+                v.fail(
+                    &name,
+                    "must be made of lower case letters, digits and underscores",
+                );
+            }
+            if self.0[..i].iter().any(|f| f.name == field.name) {
+                v.fail(&name, "must be unique");
+            }
+            let options = format!("fields.{i}.options");
+            match field.kind {
+                FieldKind::Enum if field.options.is_empty() => {
+                    v.fail(&options, "are required for an enum field");
+                }
+                FieldKind::Enum => {}
+                _ if !field.options.is_empty() => {
+                    v.fail(&options, "are only allowed for an enum field");
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Checks the custom field values of an asset against the fields of its category
+    pub fn check_values(&self, v: &mut Validator, values: &FieldValues) {
+        for name in values.0.keys() {
+            if !self.0.iter().any(|f| &f.name == name) {
+                v.fail(&format!("fields.{name}"), "is not a field of the category");
+            }
+        }
+        for field in &self.0 {
+            let name = format!("fields.{}", field.name);
+            let value = match values.0.get(&field.name) {
+                Some(value) if !value.is_null() => value,
+                _ => {
+                    if field.required {
+                        v.fail(&name, "is required");
+                    }
+                    continue;
+                }
+            };
+            match field.kind {
+                FieldKind::Text => match value.as_str() {
+                    Some(text) => {
+                        v.max_length(&name, text, TEXT_LENGTH);
+                    }
+                    None => {
+                        v.fail(&name, "must be a text");
+                    }
+                },
+                FieldKind::Number if !value.is_number() => {
+                    v.fail(&name, "must be a number");
+                }
+                FieldKind::Date
+                    if value
+                        .as_str()
+                        .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+                        .is_none() =>
+                {
+                    v.fail(&name, "must be a date such as 2024-12-31");
+                }
+                FieldKind::Enum
+                    if !value
+                        .as_str()
+                        .is_some_and(|o| field.options.iter().any(|option| option == o)) =>
+                {
+                    v.fail(
+                        &name,
+                        format!("must be one of {}", field.options.join(", ")),
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Checks the custom field values of an asset against the fields of its category, if any
+pub(crate) fn check_asset_fields(
+    conn: &mut SqliteConnection,
+    category_id: Option<i32>,
+    values: &FieldValues,
+) -> Result<(), ErrResponse> {
+    let mut v = Validator::default();
+    match category_id {
+        None if !values.0.is_empty() => {
+            v.fail("fields", "require a category");
+        }
+        None => {}
+        Some(id) => match asset_categories::table
+            .find(id)
+            .first::<Category>(conn)
+            .optional()?
+        {
+            Some(category) => category.fields.check_values(&mut v, values),
+            None => {
+                v.fail("category_id", "must be an existing category");
+            }
+        },
+    }
+    v.finish()
+}
+
+pub fn build_categories_router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_all).post(create))
+        .route("/{id}", patch(update).delete(delete).get(read))
+}
+
+async fn create(
+    _: AdminToken,
+    Db(db): Db,
+    ValidJson(category): ValidJson<InCategory>,
+) -> Result<(StatusCode, Json<Category>), ErrResponse> {
+    let category = db
+        .interact(move |conn| {
+            diesel::insert_into(asset_categories::table)
+                .values(category)
+                .returning(Category::as_returning())
+                .get_result(conn)
+        })
+        .await??;
+    Ok((StatusCode::CREATED, Json(category)))
+}
+
+async fn update(
+    Path(id): Path<i32>,
+    _: AdminToken,
+    Db(db): Db,
+    ValidJson(patch): ValidJson<CategoryPatch>,
+) -> Result<Json<Category>, ErrResponse> {
+    let category = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                if patch == CategoryPatch::default() {
+                    return Ok(asset_categories::table.find(id).first(conn)?);
+                }
+                // The assets of the category must still match its fields once changed
+                if let Some(fields) = &patch.fields {
+                    let assets: Vec<(i32, FieldValues)> = assets::table
+                        .filter(assets::category_id.eq(id))
+                        .select((assets::id, assets::fields))
+                        .order(assets::id)
+                        .load(conn)?;
+                    let mut v = Validator::default();
+                    for (asset_id, values) in assets {
+                        let mut asset = Validator::default();
+                        fields.check_values(&mut asset, &values);
+                        v.nested(&format!("assets.{asset_id}"), asset);
+                    }
+                    v.finish()?;
+                }
+                Ok::<_, ErrResponse>(
+                    diesel::update(asset_categories::table.filter(asset_categories::id.eq(id)))
+                        .set(patch)
+                        .returning(Category::as_returning())
+                        .get_result(conn)?,
+                )
+            })
+        })
+        .await??;
+    Ok(Json(category))
+}
+
+async fn list_all(_: UserToken, Db(db): Db) -> Result<impl IntoResponse, ErrResponse> {
+    let categories: Vec<Category> = db
+        .interact(|conn| {
+            asset_categories::table
+                .order(asset_categories::name)
+                .load(conn)
+        })
+        .await??;
+    Ok(Json(categories))
+}
+
+async fn read(
+    Path(id): Path<i32>,
+    _: UserToken,
+    Db(db): Db,
+) -> Result<Json<Category>, ErrResponse> {
+    let category: Category = db
+        .interact(move |conn| asset_categories::table.find(id).first(conn))
+        .await??;
+    Ok(Json(category))
+}
+
+async fn delete(Path(id): Path<i32>, _: AdminToken, Db(db): Db) -> Result<(), ErrResponse> {
+    db.interact(move |conn| {
+        conn.immediate_transaction(|conn| {
+            let used: i64 = assets::table
+                .filter(assets::category_id.eq(id))
+                .count()
+                .get_result(conn)?;
+            if used > 0 {
+                return Err(ErrResponse::S409("category is used by assets"));
+            }
+            match diesel::delete(asset_categories::table.filter(asset_categories::id.eq(id)))
+                .execute(conn)?
+            {
+                1 => Ok(()),
+                _ => Err(ErrResponse::S404("object not found in database")),
+            }
+        })
+    })
+    .await?
+}
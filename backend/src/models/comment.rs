@@ -1,24 +1,28 @@
 use axum::{
     Json, Router,
     extract::{Path, State},
-    http::StatusCode,
+    http::{StatusCode, header},
     response::IntoResponse,
     routing::{get, patch},
 };
+use chrono::Local;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_trim::string_trim;
-use tokio::task::spawn_blocking;
 
 use crate::{
     config::{AdminToken, AppState, Config, Db, UserToken},
     errors::ErrResponse,
-    mail::Mailer,
+    etag::{IfMatch, etag},
+    outbox::{self, Outbox},
+    validation::{NAME_LENGTH, TEXT_LENGTH, ValidJson, Validate, Validator, option_trim},
 };
 
 use super::{
+    history::{Event, EventAction, EventEntity},
     schema::{comments, tickets},
     ticket::Ticket,
+    user::User,
 };
 
 #[derive(
@@ -30,7 +34,6 @@ use super::{
     Serialize,
     Queryable,
     Insertable,
-    AsChangeset,
     PartialEq,
     Selectable,
 )]
@@ -38,19 +41,22 @@ use super::{
 pub struct Comment {
     pub id: i32,
     pub ticket_id: i32,
+    /// Creation time, set by the server
     pub time: chrono::NaiveDateTime,
     #[serde(deserialize_with = "string_trim")]
     pub creator: String,
     #[serde(deserialize_with = "string_trim")]
     pub content: String,
+    /// Incremented on every change, and sent as the `ETag` of the object
+    pub version: i32,
 }
 
 #[derive(Clone, Insertable, Deserialize, Serialize, PartialEq, Debug)]
 #[diesel(table_name = comments)]
 pub struct InComment {
     pub ticket_id: i32,
-    pub time: chrono::NaiveDateTime,
-    #[serde(deserialize_with = "string_trim")]
+    /// Replaced by the name of the authenticated user, unless the comment is created with a shared token
+    #[serde(default, deserialize_with = "string_trim")]
     pub creator: String,
     #[serde(deserialize_with = "string_trim")]
     pub content: String,
@@ -60,11 +66,47 @@ impl PartialEq<InComment> for Comment {
     fn eq(&self, other: &InComment) -> bool {
         self.ticket_id == other.ticket_id
             && self.content == other.content
-            && self.time == other.time
             && self.creator == other.creator
     }
 }
 
+fn check_comment(v: &mut Validator, creator: &str, content: &str) {
+    v.max_length("creator", creator, NAME_LENGTH)
+        .required("content", content)
+        .max_length("content", content, TEXT_LENGTH);
+}
+
+impl Validate for InComment {
+    fn check(&self, v: &mut Validator) {
+        check_comment(v, &self.creator, &self.content);
+    }
+}
+
+/// Changes to a comment, the missing fields being left untouched
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, AsChangeset)]
+#[diesel(table_name = comments)]
+pub struct CommentPatch {
+    #[serde(default)]
+    pub ticket_id: Option<i32>,
+    #[serde(default, deserialize_with = "option_trim")]
+    pub creator: Option<String>,
+    #[serde(default, deserialize_with = "option_trim")]
+    pub content: Option<String>,
+}
+
+impl Validate for CommentPatch {
+    fn check(&self, v: &mut Validator) {
+        if let Some(creator) = &self.creator {
+            v.required("creator", creator)
+                .max_length("creator", creator, NAME_LENGTH);
+        }
+        if let Some(content) = &self.content {
+            v.required("content", content)
+                .max_length("content", content, TEXT_LENGTH);
+        }
+    }
+}
+
 pub fn build_comments_router() -> Router<AppState> {
     Router::new()
         .route("/", get(list).post(create).delete(destroy))
@@ -73,12 +115,15 @@ pub fn build_comments_router() -> Router<AppState> {
 }
 
 async fn create(
-    State(mut mailer): State<Mailer>,
+    State(outbox): State<Outbox>,
     State(config): State<Config>,
-    _: UserToken,
+    UserToken(user): UserToken,
     Db(db): Db,
-    Json(comment): Json<InComment>,
+    ValidJson(mut comment): ValidJson<InComment>,
 ) -> Result<(StatusCode, Json<Comment>), ErrResponse> {
+    if let Some(name) = user.author() {
+        comment.creator = name.to_string();
+    }
     let ticket_id = comment.ticket_id;
     // Check that the ticket we want to create the comment for exists
     match db
@@ -87,23 +132,38 @@ async fn create(
     {
         Ok(ticket) => {
             // ...create the comment if so
-            let c = comment.clone();
             match db
                 .interact(move |conn| {
-                    diesel::insert_into(comments::table)
-                        .values(c)
-                        .returning(Comment::as_returning())
-                        .get_result(conn)
+                    conn.immediate_transaction(|conn| {
+                        let c = diesel::insert_into(comments::table)
+                            .values((
+                                comment.clone(),
+                                comments::time.eq(Local::now().naive_local()),
+                            ))
+                            .returning(Comment::as_returning())
+                            .get_result(conn)?;
+                        Event {
+                            entity: EventEntity::Comment,
+                            entity_id: c.id,
+                            ticket_id: Some(c.ticket_id),
+                            action: EventAction::Create,
+                            old: None,
+                            new: Some(&c),
+                        }
+                        .record(conn, &user)?;
+                        outbox::queue_template(
+                            conn,
+                            (&comment, &ticket),
+                            "new_comment",
+                            &config.comment_mail_to,
+                        )?;
+                        Ok::<_, diesel::result::Error>(c)
+                    })
                 })
                 .await?
             {
                 Ok(c) => {
-                    spawn_blocking(move || {
-                        match crate::models::ticket::template((&comment, &ticket), "new_comment") {
-                            Ok(r) => mailer.send_mail_to(r.0, r.1, config.comment_mail_to),
-                            Err(e) => println!("Handlebars error : {}", e),
-                        }
-                    });
+                    outbox.notify();
                     Ok((StatusCode::CREATED, Json(c)))
                 }
 
@@ -118,52 +178,77 @@ async fn create(
 
 async fn update(
     Path(id): Path<i32>,
-    AdminToken: AdminToken,
+    AdminToken(user): AdminToken,
     Db(db): Db,
-    Json(comment): Json<Comment>,
-) -> Result<StatusCode, ErrResponse> {
-    db.interact(move |conn| {
-        diesel::update(comments::table.filter(comments::id.eq(id)))
-            .set(comment)
-            .execute(conn)
-    })
-    .await??;
-    Ok(StatusCode::NO_CONTENT)
+    if_match: IfMatch,
+    ValidJson(patch): ValidJson<CommentPatch>,
+) -> Result<impl IntoResponse, ErrResponse> {
+    let version = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                let previous: Comment = comments::table.find(id).first(conn)?;
+                if_match.check(previous.version)?;
+                if patch == CommentPatch::default() {
+                    return Ok(previous.version);
+                }
+                let comment: Comment = diesel::update(comments::table.filter(comments::id.eq(id)))
+                    .set((patch, comments::version.eq(comments::version + 1)))
+                    .returning(Comment::as_returning())
+                    .get_result(conn)?;
+                Event {
+                    entity: EventEntity::Comment,
+                    entity_id: id,
+                    ticket_id: Some(comment.ticket_id),
+                    action: EventAction::Update,
+                    old: Some(&previous),
+                    new: Some(&comment),
+                }
+                .record(conn, &user)?;
+                Ok::<_, ErrResponse>(comment.version)
+            })
+        })
+        .await??;
+    Ok((StatusCode::NO_CONTENT, [(header::ETAG, etag(version))]))
 }
 
-async fn list(UserToken: UserToken, Db(db): Db) -> Result<impl IntoResponse, ErrResponse> {
+async fn list(_: UserToken, Db(db): Db) -> Result<impl IntoResponse, ErrResponse> {
     let res: Vec<i32> = db
         .interact(|conn| comments::table.select(comments::id).load(conn))
         .await??;
     Ok(Json(res))
 }
 
-async fn list_all(UserToken: UserToken, Db(db): Db) -> Result<impl IntoResponse, ErrResponse> {
+async fn list_all(_: UserToken, Db(db): Db) -> Result<impl IntoResponse, ErrResponse> {
     let all_comments: Vec<Comment> = db.interact(|conn| comments::table.load(conn)).await??;
     Ok(Json(all_comments))
 }
 
 async fn read(
     Path(id): Path<i32>,
-    UserToken: UserToken,
+    _: UserToken,
     Db(db): Db,
-) -> Result<Json<Comment>, ErrResponse> {
+) -> Result<impl IntoResponse, ErrResponse> {
     let comment: Comment = db
         .interact(move |conn| comments::table.filter(comments::id.eq(id)).first(conn))
         .await??;
-    Ok(Json(comment))
+    Ok(([(header::ETAG, etag(comment.version))], Json(comment)))
 }
 
 async fn delete(
     Path(id): Path<i32>,
-    AdminToken: AdminToken,
+    AdminToken(user): AdminToken,
     Db(db): Db,
 ) -> Result<(), ErrResponse> {
     if db
         .interact(move |conn| {
-            diesel::delete(comments::table)
-                .filter(comments::id.eq(id))
-                .execute(conn)
+            conn.immediate_transaction(|conn| {
+                let deleted: Vec<Comment> = diesel::delete(comments::table)
+                    .filter(comments::id.eq(id))
+                    .returning(Comment::as_returning())
+                    .get_results(conn)?;
+                record_deletions(conn, &user, &deleted)?;
+                Ok::<_, diesel::result::Error>(deleted.len())
+            })
         })
         .await??
         == 1
@@ -174,8 +259,48 @@ async fn delete(
     }
 }
 
-async fn destroy(AdminToken: AdminToken, Db(db): Db) -> Result<(), ErrResponse> {
-    db.interact(move |conn| diesel::delete(comments::table).execute(conn))
-        .await??;
+async fn destroy(AdminToken(user): AdminToken, Db(db): Db) -> Result<(), ErrResponse> {
+    db.interact(move |conn| {
+        conn.immediate_transaction(|conn| {
+            let deleted: Vec<Comment> = diesel::delete(comments::table)
+                .returning(Comment::as_returning())
+                .get_results(conn)?;
+            record_deletions(conn, &user, &deleted)
+        })
+    })
+    .await??;
+    Ok(())
+}
+
+/// Deletes the comments of the given tickets before the tickets themselves,
+/// so that their deletion is recorded rather than left to the foreign key cascade
+pub(crate) fn delete_ticket_comments(
+    conn: &mut SqliteConnection,
+    actor: &User,
+    ticket_ids: &[i32],
+) -> Result<(), diesel::result::Error> {
+    let deleted: Vec<Comment> = diesel::delete(comments::table)
+        .filter(comments::ticket_id.eq_any(ticket_ids))
+        .returning(Comment::as_returning())
+        .get_results(conn)?;
+    record_deletions(conn, actor, &deleted)
+}
+
+fn record_deletions(
+    conn: &mut SqliteConnection,
+    actor: &User,
+    deleted: &[Comment],
+) -> Result<(), diesel::result::Error> {
+    for comment in deleted {
+        Event {
+            entity: EventEntity::Comment,
+            entity_id: comment.id,
+            ticket_id: Some(comment.ticket_id),
+            action: EventAction::Delete,
+            old: Some(comment),
+            new: None,
+        }
+        .record(conn, actor)?;
+    }
     Ok(())
 }
@@ -0,0 +1,376 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::header,
+    response::{Html, IntoResponse, Response},
+};
+use diesel::prelude::*;
+use rust_xlsxwriter::{Format, Workbook, Worksheet, XlsxError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use tokio::task::spawn_blocking;
+
+use crate::{
+    config::{Config, Db, UserToken},
+    errors::ErrResponse,
+    models::{
+        asset::{Asset, asset_path},
+        category::{Category, display_value},
+        comment::Comment,
+        history::{TicketEvent, tickets_history},
+        schema::{asset_categories, assets, comments},
+        ticket::{OutTicket, Ticket, TicketFilter, template},
+        workflow::TicketStatus,
+    },
+};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Html,
+    Csv,
+    Json,
+    Xlsx,
+}
+
+/// Query parameters of the export, the filters being shared by all the formats
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+    pub status: Option<TicketStatus>,
+    pub is_open: Option<bool>,
+    pub asset_id: Option<i32>,
+    pub within_asset_id: Option<i32>,
+    pub from: Option<chrono::NaiveDateTime>,
+    pub to: Option<chrono::NaiveDateTime>,
+}
+
+impl ExportQuery {
+    fn filter(&self) -> TicketFilter {
+        TicketFilter {
+            status: self.status,
+            is_open: self.is_open,
+            asset_id: self.asset_id,
+            within_asset_id: self.within_asset_id,
+            from: self.from,
+            to: self.to,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedTicket {
+    #[serde(flatten)]
+    pub ticket: OutTicket,
+    pub asset: Asset,
+    /// Category of the asset, defining its custom fields
+    #[serde(default)]
+    pub category: Option<Category>,
+    pub history: Vec<TicketEvent>,
+}
+
+/// One row per ticket, with the asset flattened and the comments concatenated
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    id: i32,
+    time: String,
+    title: &'a str,
+    description: &'a str,
+    creator: &'a str,
+    creator_mail: &'a str,
+    creator_phone: &'a str,
+    status: &'a str,
+    priority: &'a str,
+    assignee_id: Option<i32>,
+    response_due: String,
+    resolution_due: String,
+    asset_id: i32,
+    asset_title: &'a str,
+    asset_description: &'a str,
+    asset_path: &'a str,
+    asset_category: &'a str,
+    asset_serial_number: &'a str,
+    asset_fields: String,
+    comment_count: usize,
+    comments: String,
+}
+
+impl<'a> From<&'a ExportedTicket> for CsvRow<'a> {
+    fn from(e: &'a ExportedTicket) -> Self {
+        let t = &e.ticket.ticket;
+        let format = |time: chrono::NaiveDateTime| time.format("%Y-%m-%d %H:%M").to_string();
+        CsvRow {
+            id: t.id,
+            time: format(t.time),
+            title: &t.title,
+            description: &t.description,
+            creator: &t.creator,
+            creator_mail: &t.creator_mail,
+            creator_phone: &t.creator_phone,
+            status: t.status.as_str(),
+            priority: t.priority.as_str(),
+            assignee_id: t.assignee_id,
+            response_due: format(e.ticket.due.response_due),
+            resolution_due: format(e.ticket.due.resolution_due),
+            asset_id: e.asset.id,
+            asset_title: &e.asset.title,
+            asset_description: &e.asset.description,
+            asset_path: &e.ticket.asset_path,
+            asset_category: e.category.as_ref().map_or("", |c| &c.name),
+            asset_serial_number: &e.asset.serial_number,
+            asset_fields: e.asset.fields.to_lines(),
+            comment_count: e.ticket.comments.len(),
+            comments: e
+                .ticket
+                .comments
+                .iter()
+                .map(|c| format!("{} - {} - {}", format(c.time), c.creator, c.content))
+                .collect::<Vec<String>>()
+                .join("\n"),
+        }
+    }
+}
+
+fn to_csv(tickets: &[ExportedTicket]) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for t in tickets {
+        writer.serialize(CsvRow::from(t))?;
+    }
+    writer
+        .into_inner()
+        .map_err(|e| csv::Error::from(e.into_error()))
+}
+
+/// Writes the header row of a sheet, letting every column be filtered
+fn write_header(
+    sheet: &mut Worksheet,
+    columns: &[&str],
+    rows: usize,
+    format: &Format,
+) -> Result<(), XlsxError> {
+    sheet.write_row_with_format(0, 0, columns.iter().copied(), format)?;
+    sheet.autofilter(0, 0, rows as u32, columns.len() as u16 - 1)?;
+    sheet.set_freeze_panes(1, 0)?;
+    Ok(())
+}
+
+/// Builds a workbook with the tickets, their comments and their assets, each on its own sheet
+fn to_xlsx(tickets: &[ExportedTicket]) -> Result<Vec<u8>, XlsxError> {
+    let mut workbook = Workbook::new();
+    let header = Format::new().set_bold();
+    let date = Format::new().set_num_format("yyyy-mm-dd hh:mm");
+
+    let sheet = workbook.add_worksheet().set_name("Tickets")?;
+    write_header(
+        sheet,
+        &[
+            "Id",
+            "Time",
+            "Title",
+            "Description",
+            "Creator",
+            "Creator mail",
+            "Creator phone",
+            "Status",
+            "Priority",
+            "Assignee id",
+            "Response due",
+            "Resolution due",
+            "Asset id",
+            "Asset",
+            "Asset path",
+            "Comments",
+        ],
+        tickets.len(),
+        &header,
+    )?;
+    for (row, e) in (1..).zip(tickets) {
+        let t = &e.ticket.ticket;
+        sheet.write(row, 0, t.id)?;
+        sheet.write_with_format(row, 1, &t.time, &date)?;
+        sheet.write(row, 2, &t.title)?;
+        sheet.write(row, 3, &t.description)?;
+        sheet.write(row, 4, &t.creator)?;
+        sheet.write(row, 5, &t.creator_mail)?;
+        sheet.write(row, 6, &t.creator_phone)?;
+        sheet.write(row, 7, t.status.label())?;
+        sheet.write(row, 8, t.priority.as_str())?;
+        sheet.write(row, 9, t.assignee_id)?;
+        sheet.write_with_format(row, 10, &e.ticket.due.response_due, &date)?;
+        sheet.write_with_format(row, 11, &e.ticket.due.resolution_due, &date)?;
+        sheet.write(row, 12, e.asset.id)?;
+        sheet.write(row, 13, &e.asset.title)?;
+        sheet.write(row, 14, &e.ticket.asset_path)?;
+        sheet.write(row, 15, e.ticket.comments.len() as u32)?;
+    }
+    sheet.autofit();
+
+    let comments: Vec<(&Ticket, &Comment)> = tickets
+        .iter()
+        .flat_map(|e| e.ticket.comments.iter().map(|c| (&e.ticket.ticket, c)))
+        .collect();
+    let sheet = workbook.add_worksheet().set_name("Comments")?;
+    write_header(
+        sheet,
+        &["Id", "Ticket id", "Ticket", "Time", "Creator", "Content"],
+        comments.len(),
+        &header,
+    )?;
+    for (row, (t, c)) in (1..).zip(comments) {
+        sheet.write(row, 0, c.id)?;
+        sheet.write(row, 1, t.id)?;
+        sheet.write(row, 2, &t.title)?;
+        sheet.write_with_format(row, 3, &c.time, &date)?;
+        sheet.write(row, 4, &c.creator)?;
+        sheet.write(row, 5, &c.content)?;
+    }
+    sheet.autofit();
+
+    let mut assets: Vec<&Asset> = tickets.iter().map(|e| &e.asset).collect();
+    assets.sort_by(|a, b| a.title.cmp(&b.title).then(a.id.cmp(&b.id)));
+    assets.dedup_by_key(|a| a.id);
+    // Every custom field of the assets gets its own column
+    let mut fields: Vec<&String> = assets.iter().flat_map(|a| a.fields.0.keys()).collect();
+    fields.sort();
+    fields.dedup();
+    let sheet = workbook.add_worksheet().set_name("Assets")?;
+    let mut columns = vec![
+        "Id",
+        "Title",
+        "Description",
+        "Path",
+        "Category",
+        "Serial number",
+    ];
+    columns.extend(fields.iter().map(|f| f.as_str()));
+    columns.push("Tickets");
+    write_header(sheet, &columns, assets.len(), &header)?;
+    for (row, a) in (1..).zip(assets) {
+        sheet.write(row, 0, a.id)?;
+        sheet.write(row, 1, &a.title)?;
+        sheet.write(row, 2, &a.description)?;
+        let asset_tickets: Vec<&ExportedTicket> =
+            tickets.iter().filter(|e| e.asset.id == a.id).collect();
+        sheet.write(row, 3, &asset_tickets[0].ticket.asset_path)?;
+        if let Some(category) = &asset_tickets[0].category {
+            sheet.write(row, 4, &category.name)?;
+        }
+        sheet.write(row, 5, &a.serial_number)?;
+        for (column, field) in (6..).zip(&fields) {
+            match a.fields.0.get(*field) {
+                Some(Value::Number(n)) => {
+                    sheet.write(row, column, n.as_f64())?;
+                }
+                Some(value) => {
+                    sheet.write(row, column, display_value(value))?;
+                }
+                None => {}
+            }
+        }
+        sheet.write(row, 6 + fields.len() as u16, asset_tickets.len() as u32)?;
+    }
+    sheet.autofit();
+
+    workbook.save_to_buffer()
+}
+
+pub(crate) async fn export(
+    Db(db): Db,
+    _: UserToken,
+    State(config): State<Config>,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, ErrResponse> {
+    let filter = query.filter();
+    let tickets: Vec<ExportedTicket> = db
+        .interact(move |conn| {
+            let tickets: Vec<Ticket> = filter.sorted_page().load(conn)?;
+            let assets: Vec<Asset> = assets::table
+                .filter(assets::id.eq_any(tickets.iter().map(|t| t.asset_id)))
+                .load(conn)?;
+            let categories: Vec<Category> = asset_categories::table
+                .filter(
+                    asset_categories::id
+                        .nullable()
+                        .eq_any(assets.iter().map(|a| a.category_id)),
+                )
+                .load(conn)?;
+            let paths = assets
+                .iter()
+                .map(|a| Ok((a.id, asset_path(conn, a.id)?)))
+                .collect::<QueryResult<HashMap<i32, String>>>()?;
+            let comments = Comment::belonging_to(&tickets)
+                .select(Comment::as_select())
+                .order(comments::time.desc())
+                .load(conn)?;
+            let mut histories = tickets_history(conn, &tickets)?;
+            Ok::<_, diesel::result::Error>(
+                comments
+                    .grouped_by(&tickets)
+                    .into_iter()
+                    .zip(tickets)
+                    .filter_map(|(cmts, t)| {
+                        let asset = assets.iter().find(|a| a.id == t.asset_id)?.clone();
+                        Some((cmts, t, asset))
+                    })
+                    .map(|(cmts, t, asset)| ExportedTicket {
+                        history: histories.remove(&t.id).unwrap_or_default(),
+                        ticket: OutTicket::new(
+                            t,
+                            cmts,
+                            paths.get(&asset.id).cloned().unwrap_or_default(),
+                            &config.sla,
+                        ),
+                        category: categories
+                            .iter()
+                            .find(|c| Some(c.id) == asset.category_id)
+                            .cloned(),
+                        asset,
+                    })
+                    .collect(),
+            )
+        })
+        .await??;
+
+    match query.format {
+        ExportFormat::Html => match template(&tickets, "tickets_with_comments") {
+            Ok(r) => Ok(Html(r.1).into_response()),
+            Err(_) => Err(ErrResponse::S500("could not export data")),
+        },
+        ExportFormat::Csv => match to_csv(&tickets) {
+            Ok(csv) => Ok((
+                [
+                    (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        "attachment; filename=\"tickets.csv\"",
+                    ),
+                ],
+                csv,
+            )
+                .into_response()),
+            Err(_) => Err(ErrResponse::S500("could not export data")),
+        },
+        ExportFormat::Json => Ok(Json(tickets).into_response()),
+        ExportFormat::Xlsx => match spawn_blocking(move || to_xlsx(&tickets)).await {
+            Ok(Ok(xlsx)) => Ok((
+                [
+                    (
+                        header::CONTENT_TYPE,
+                        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+                    ),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        "attachment; filename=\"tickets.xlsx\"",
+                    ),
+                ],
+                xlsx,
+            )
+                .into_response()),
+            _ => Err(ErrResponse::S500("could not export data")),
+        },
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+
+use axum::{Json, extract::Path};
+use chrono::Local;
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    prelude::*,
+    serialize::{self, IsNull, Output, ToSql},
+    sql_types::Text,
+    sqlite::{Sqlite, SqliteValue},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    config::{Db, UserToken},
+    errors::ErrResponse,
+    models::{
+        schema::{ticket_events, tickets},
+        ticket::Ticket,
+        user::User,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum EventEntity {
+    Ticket,
+    Comment,
+    Asset,
+    Attachment,
+}
+
+impl EventEntity {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventEntity::Ticket => "ticket",
+            EventEntity::Comment => "comment",
+            EventEntity::Asset => "asset",
+            EventEntity::Attachment => "attachment",
+        }
+    }
+}
+
+impl ToSql<Text, Sqlite> for EventEntity {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        out.set_value(self.as_str());
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Sqlite> for EventEntity {
+    fn from_sql(bytes: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Sqlite>>::from_sql(bytes)?.as_str() {
+            "ticket" => Ok(EventEntity::Ticket),
+            "comment" => Ok(EventEntity::Comment),
+            "asset" => Ok(EventEntity::Asset),
+            "attachment" => Ok(EventEntity::Attachment),
+            e => Err(format!("unknown event entity: {}", e).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum EventAction {
+    Create,
+    Update,
+    Delete,
+    Close,
+}
+
+impl EventAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventAction::Create => "create",
+            EventAction::Update => "update",
+            EventAction::Delete => "delete",
+            EventAction::Close => "close",
+        }
+    }
+}
+
+impl ToSql<Text, Sqlite> for EventAction {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        out.set_value(self.as_str());
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Sqlite> for EventAction {
+    fn from_sql(bytes: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Sqlite>>::from_sql(bytes)?.as_str() {
+            "create" => Ok(EventAction::Create),
+            "update" => Ok(EventAction::Update),
+            "delete" => Ok(EventAction::Delete),
+            "close" => Ok(EventAction::Close),
+            a => Err(format!("unknown event action: {}", a).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+/// Changed fields of an event, stored as a JSON array
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+#[serde(transparent)]
+pub struct Changes(pub Vec<FieldChange>);
+
+impl ToSql<Text, Sqlite> for Changes {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        out.set_value(serde_json::to_string(&self.0)?);
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Sqlite> for Changes {
+    fn from_sql(bytes: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        let value = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        Ok(Changes(serde_json::from_str(&value)?))
+    }
+}
+
+impl Changes {
+    /// Compares the fields of two versions of an object, a missing version meaning that the object was created or deleted
+    fn between<T: Serialize>(old: Option<&T>, new: Option<&T>) -> Self {
+        let fields = |o: Option<&T>| match o.map(serde_json::to_value) {
+            Some(Ok(Value::Object(fields))) => fields,
+            _ => serde_json::Map::new(),
+        };
+        let (old, new) = (fields(old), fields(new));
+        let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+        names.sort();
+        names.dedup();
+        Changes(
+            names
+                .into_iter()
+                .filter(|name| !matches!(name.as_str(), "id" | "version"))
+                .filter_map(|name| {
+                    let old = old.get(name).cloned().unwrap_or(Value::Null);
+                    let new = new.get(name).cloned().unwrap_or(Value::Null);
+                    (old != new).then(|| FieldChange {
+                        field: name.clone(),
+                        old,
+                        new,
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Queryable, Selectable)]
+#[diesel(table_name = ticket_events)]
+pub struct TicketEvent {
+    pub id: i32,
+    pub ticket_id: Option<i32>,
+    pub entity: EventEntity,
+    pub entity_id: i32,
+    pub action: EventAction,
+    pub actor_id: Option<i32>,
+    pub actor: String,
+    pub time: chrono::NaiveDateTime,
+    pub changes: Changes,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = ticket_events)]
+struct NewTicketEvent {
+    ticket_id: Option<i32>,
+    entity: EventEntity,
+    entity_id: i32,
+    action: EventAction,
+    actor_id: Option<i32>,
+    actor: String,
+    time: chrono::NaiveDateTime,
+    changes: Changes,
+}
+
+/// A change made by a user to a ticket, one of its comments or attachments, or an asset
+pub(crate) struct Event<'a, T> {
+    pub entity: EventEntity,
+    pub entity_id: i32,
+    /// Ticket the event belongs to, none for the assets
+    pub ticket_id: Option<i32>,
+    pub action: EventAction,
+    pub old: Option<&'a T>,
+    pub new: Option<&'a T>,
+}
+
+impl<T: Serialize> Event<'_, T> {
+    /// Records the event in the audit trail, to be called within the transaction making the change
+    pub(crate) fn record(self, conn: &mut SqliteConnection, actor: &User) -> QueryResult<()> {
+        let changes = Changes::between(self.old, self.new);
+        // Updates leaving the object untouched are not worth recording
+        if self.action == EventAction::Update && changes.0.is_empty() {
+            return Ok(());
+        }
+        diesel::insert_into(ticket_events::table)
+            .values(NewTicketEvent {
+                ticket_id: self.ticket_id,
+                entity: self.entity,
+                entity_id: self.entity_id,
+                action: self.action,
+                actor_id: Some(actor.id),
+                actor: actor.name.clone(),
+                time: Local::now().naive_local(),
+                changes,
+            })
+            .execute(conn)?;
+        Ok(())
+    }
+}
+
+/// Gets the events of a ticket, its comments, its attachments and its asset, oldest first
+pub(crate) fn ticket_history(
+    conn: &mut SqliteConnection,
+    ticket_id: i32,
+    asset_id: Option<i32>,
+) -> QueryResult<Vec<TicketEvent>> {
+    let mut query = ticket_events::table
+        .filter(ticket_events::ticket_id.eq(ticket_id))
+        .into_boxed();
+    if let Some(asset_id) = asset_id {
+        query = query.or_filter(
+            ticket_events::entity
+                .eq(EventEntity::Asset)
+                .and(ticket_events::entity_id.eq(asset_id)),
+        );
+    }
+    query
+        .order((ticket_events::time.asc(), ticket_events::id.asc()))
+        .select(TicketEvent::as_select())
+        .load(conn)
+}
+
+/// Gets the events of several tickets, their comments, their attachments and their assets in a single query,
+/// oldest first, by ticket id
+pub(crate) fn tickets_history(
+    conn: &mut SqliteConnection,
+    tickets: &[Ticket],
+) -> QueryResult<HashMap<i32, Vec<TicketEvent>>> {
+    let events: Vec<TicketEvent> = ticket_events::table
+        .filter(ticket_events::ticket_id.eq_any(tickets.iter().map(|t| t.id)))
+        .or_filter(
+            ticket_events::entity
+                .eq(EventEntity::Asset)
+                .and(ticket_events::entity_id.eq_any(tickets.iter().map(|t| t.asset_id))),
+        )
+        .select(TicketEvent::as_select())
+        .load(conn)?;
+    let mut by_ticket: HashMap<i32, Vec<TicketEvent>> = HashMap::new();
+    let mut by_asset: HashMap<i32, Vec<TicketEvent>> = HashMap::new();
+    for event in events {
+        match event.ticket_id {
+            Some(ticket_id) => by_ticket.entry(ticket_id).or_default().push(event),
+            None => by_asset.entry(event.entity_id).or_default().push(event),
+        }
+    }
+    Ok(tickets
+        .iter()
+        .map(|t| {
+            let mut history = by_ticket.remove(&t.id).unwrap_or_default();
+            history.extend(by_asset.get(&t.asset_id).into_iter().flatten().cloned());
+            history.sort_by_key(|e| (e.time, e.id));
+            (t.id, history)
+        })
+        .collect())
+}
+
+pub(crate) async fn history(
+    Path(id): Path<i32>,
+    _: UserToken,
+    Db(db): Db,
+) -> Result<Json<Vec<TicketEvent>>, ErrResponse> {
+    let events = db
+        .interact(move |conn| {
+            // The history of a deleted ticket remains available, without the events of its asset
+            let asset_id: Option<i32> = tickets::table
+                .find(id)
+                .select(tickets::asset_id)
+                .first(conn)
+                .optional()?;
+            ticket_history(conn, id, asset_id)
+        })
+        .await??;
+    if events.is_empty() {
+        return Err(ErrResponse::S404("no history for this ticket"));
+    }
+    Ok(Json(events))
+}
@@ -0,0 +1,168 @@
+use std::io::Cursor;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{Html, IntoResponse, Response},
+};
+use diesel::prelude::*;
+use image::{ImageFormat, Luma};
+use qrcode::{QrCode, render::svg};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Config, Db, UserToken},
+    errors::ErrResponse,
+    models::{
+        asset::{Asset, asset_path, in_subtree},
+        schema::assets,
+        ticket::template,
+    },
+};
+
+/// Minimal width and height of the QR codes, in pixels
+const QRCODE_SIZE: u32 = 200;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum QrCodeFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct QrCodeQuery {
+    #[serde(default)]
+    pub format: QrCodeFormat,
+}
+
+/// Selection of the assets to label, all of them when no parameter is given
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct LabelsQuery {
+    /// Comma separated ids of the assets, such as `1,2,3`
+    pub ids: Option<String>,
+    /// Labels an asset and all the assets below it
+    pub within_asset_id: Option<i32>,
+}
+
+#[derive(Serialize)]
+struct Label {
+    asset: Asset,
+    asset_path: String,
+    url: String,
+    /// Inline SVG image of the QR code
+    qrcode: String,
+}
+
+/// Gets the address of the web app, which must be configured as the printed codes outlive the requests and their host
+fn public_url(config: &Config) -> Result<&str, ErrResponse> {
+    match config.public_url.as_str() {
+        "" => Err(ErrResponse::S500(
+            "PUBLIC_URL must be set to link the QR codes to the web app",
+        )),
+        url => Ok(url),
+    }
+}
+
+/// Gets the address of the web app opening the new ticket form with the asset selected
+fn ticket_url(public_url: &str, asset_id: i32) -> String {
+    format!("{public_url}/?asset_id={asset_id}")
+}
+
+fn qrcode_svg(code: &QrCode) -> String {
+    let svg = code
+        .render::<svg::Color>()
+        .min_dimensions(QRCODE_SIZE, QRCODE_SIZE)
+        .build();
+    // The XML declaration is left out so that the image can be put inside an HTML page
+    match svg.find("<svg") {
+        Some(start) => svg[start..].to_string(),
+        None => svg,
+    }
+}
+
+pub(crate) async fn qrcode(
+    Path(id): Path<i32>,
+    _: UserToken,
+    Db(db): Db,
+    State(config): State<Config>,
+    Query(query): Query<QrCodeQuery>,
+) -> Result<Response, ErrResponse> {
+    let public_url = public_url(&config)?;
+    let asset: Asset = db
+        .interact(move |conn| assets::table.find(id).first(conn))
+        .await??;
+    let code = QrCode::new(ticket_url(public_url, asset.id))
+        .map_err(|_| ErrResponse::S500("could not create QR code"))?;
+    match query.format {
+        QrCodeFormat::Png => {
+            let mut png = Cursor::new(Vec::new());
+            code.render::<Luma<u8>>()
+                .min_dimensions(QRCODE_SIZE, QRCODE_SIZE)
+                .build()
+                .write_to(&mut png, ImageFormat::Png)
+                .map_err(|_| ErrResponse::S500("could not create QR code"))?;
+            Ok(([(header::CONTENT_TYPE, "image/png")], png.into_inner()).into_response())
+        }
+        QrCodeFormat::Svg => {
+            Ok(([(header::CONTENT_TYPE, "image/svg+xml")], qrcode_svg(&code)).into_response())
+        }
+    }
+}
+
+pub(crate) async fn labels(
+    _: UserToken,
+    Db(db): Db,
+    State(config): State<Config>,
+    Query(query): Query<LabelsQuery>,
+) -> Result<Html<String>, ErrResponse> {
+    let public_url = public_url(&config)?;
+    let ids = query
+        .ids
+        .as_deref()
+        .map(|ids| {
+            ids.split(',')
+                .map(|id| id.trim().parse::<i32>())
+                .collect::<Result<Vec<i32>, _>>()
+                .map_err(|_| ErrResponse::S400("ids must be a comma separated list of asset ids"))
+        })
+        .transpose()?;
+    let assets: Vec<(Asset, String)> = db
+        .interact(move |conn| {
+            let mut q = assets::table.into_boxed();
+            if let Some(ids) = ids {
+                q = q.filter(assets::id.eq_any(ids));
+            }
+            if let Some(id) = query.within_asset_id {
+                q = q.filter(in_subtree("assets.id", id));
+            }
+            let assets: Vec<Asset> = q.order((assets::title, assets::id)).load(conn)?;
+            assets
+                .into_iter()
+                .map(|a| {
+                    let path = asset_path(conn, a.id)?;
+                    Ok((a, path))
+                })
+                .collect::<QueryResult<Vec<(Asset, String)>>>()
+        })
+        .await??;
+    let labels = assets
+        .into_iter()
+        .map(|(asset, asset_path)| {
+            let url = ticket_url(public_url, asset.id);
+            let code =
+                QrCode::new(&url).map_err(|_| ErrResponse::S500("could not create QR code"))?;
+            Ok(Label {
+                qrcode: qrcode_svg(&code),
+                asset,
+                asset_path,
+                url,
+            })
+        })
+        .collect::<Result<Vec<Label>, ErrResponse>>()?;
+    match template(&labels, "asset_labels") {
+        Ok(r) => Ok(Html(r.1)),
+        Err(_) => Err(ErrResponse::S500("could not create labels")),
+    }
+}
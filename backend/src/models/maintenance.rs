@@ -0,0 +1,401 @@
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, patch, post},
+};
+use chrono::{Local, NaiveDateTime, TimeZone};
+use deadpool_diesel::sqlite::Object;
+use diesel::prelude::*;
+use handlebars::{Handlebars, Template};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_trim::string_trim;
+
+use crate::{
+    config::{AdminToken, AppState, Config, Db, UserToken},
+    errors::ErrResponse,
+    models::{
+        asset::{Asset, asset_path},
+        history::{Event, EventAction, EventEntity},
+        schema::{assets, maintenance_plans, maintenance_tickets, tickets, users},
+        sla::Priority,
+        ticket::{InTicket, OutTicket, Ticket},
+        user::{Role, User},
+        workflow::TicketStatus,
+    },
+    outbox::{self, Outbox},
+    scheduler::parse_schedule,
+    validation::{TEXT_LENGTH, TITLE_LENGTH, ValidJson, Validate, Validator, option_trim},
+};
+
+/// Creator of the tickets opened by the maintenance plans
+const MAINTENANCE_CREATOR: &str = "Preventive maintenance";
+
+/// Schedule of the preventive maintenance of an asset, opening a ticket whenever it comes due
+#[derive(Identifiable, Debug, Clone, Deserialize, Serialize, Queryable, PartialEq, Selectable)]
+#[diesel(table_name = maintenance_plans)]
+pub struct MaintenancePlan {
+    pub id: i32,
+    pub asset_id: i32,
+    /// Cron expression of the due dates, such as `0 8 1 * *` (on the first day of every month at 08:00)
+    pub recurrence: String,
+    /// Handlebars template of the title of the tickets, given the `asset`, its `asset_path` and the `due` date
+    pub title: String,
+    /// Handlebars template of the description of the tickets, given the same values as the title
+    pub description: String,
+    pub priority: Priority,
+    /// Next time a ticket is opened, server local time
+    pub next_due: NaiveDateTime,
+    pub enabled: bool,
+}
+
+#[derive(Clone, Insertable, Deserialize, Serialize, PartialEq, Debug)]
+#[diesel(table_name = maintenance_plans)]
+pub struct InMaintenancePlan {
+    pub asset_id: i32,
+    #[serde(deserialize_with = "string_trim")]
+    pub recurrence: String,
+    #[serde(deserialize_with = "string_trim")]
+    pub title: String,
+    #[serde(deserialize_with = "string_trim")]
+    pub description: String,
+    #[serde(default)]
+    pub priority: Priority,
+    /// Defaults to the first due date of the recurrence
+    #[serde(default)]
+    pub next_due: Option<NaiveDateTime>,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl PartialEq<InMaintenancePlan> for MaintenancePlan {
+    fn eq(&self, other: &InMaintenancePlan) -> bool {
+        self.asset_id == other.asset_id
+            && self.recurrence == other.recurrence
+            && self.title == other.title
+            && self.description == other.description
+            && self.priority == other.priority
+            && other.next_due.is_none_or(|due| due == self.next_due)
+            && self.enabled == other.enabled
+    }
+}
+
+fn check_recurrence(v: &mut Validator, recurrence: &str) {
+    let upcoming = parse_schedule(recurrence)
+        .ok()
+        .and_then(|s| s.upcoming(Local).next());
+    if upcoming.is_none() {
+        v.fail(
+            "recurrence",
+            "must be a cron expression with upcoming dates, such as `0 8 1 * *`",
+        );
+    }
+}
+
+fn check_template(v: &mut Validator, field: &str, template: &str, max: usize) {
+    v.required(field, template).max_length(field, template, max);
+    if Template::compile(template).is_err() {
+        v.fail(field, "must be a valid template");
+    }
+}
+
+impl Validate for InMaintenancePlan {
+    fn check(&self, v: &mut Validator) {
+        check_recurrence(v, &self.recurrence);
+        check_template(v, "title", &self.title, TITLE_LENGTH);
+        check_template(v, "description", &self.description, TEXT_LENGTH);
+    }
+}
+
+/// Changes to a maintenance plan, the missing fields being left untouched
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, AsChangeset)]
+#[diesel(table_name = maintenance_plans)]
+pub struct MaintenancePlanPatch {
+    pub asset_id: Option<i32>,
+    #[serde(default, deserialize_with = "option_trim")]
+    pub recurrence: Option<String>,
+    #[serde(default, deserialize_with = "option_trim")]
+    pub title: Option<String>,
+    #[serde(default, deserialize_with = "option_trim")]
+    pub description: Option<String>,
+    pub priority: Option<Priority>,
+    /// Defaults to the first due date of the new recurrence, when the recurrence is changed
+    pub next_due: Option<NaiveDateTime>,
+    pub enabled: Option<bool>,
+}
+
+impl Validate for MaintenancePlanPatch {
+    fn check(&self, v: &mut Validator) {
+        if let Some(recurrence) = &self.recurrence {
+            check_recurrence(v, recurrence);
+        }
+        if let Some(title) = &self.title {
+            check_template(v, "title", title, TITLE_LENGTH);
+        }
+        if let Some(description) = &self.description {
+            check_template(v, "description", description, TEXT_LENGTH);
+        }
+    }
+}
+
+/// A ticket opened by a maintenance plan, kept when the ticket is deleted
+#[derive(Debug, Clone, Deserialize, Serialize, Queryable, PartialEq, Selectable)]
+#[diesel(table_name = maintenance_tickets)]
+pub struct MaintenanceTicket {
+    pub id: i32,
+    pub plan_id: i32,
+    pub ticket_id: Option<i32>,
+    /// Due date the ticket was opened for
+    pub due: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+}
+
+/// Gets the first due date of a recurrence strictly after the given time
+fn due_after(recurrence: &str, time: NaiveDateTime) -> Option<NaiveDateTime> {
+    let time = Local.from_local_datetime(&time).earliest()?;
+    parse_schedule(recurrence)
+        .ok()?
+        .after(&time)
+        .next()
+        .map(|due| due.naive_local())
+}
+
+fn render(template: &str, asset: &Asset, path: &str, due: NaiveDateTime) -> String {
+    let mut handlebars = Handlebars::new();
+    // The tickets are plain text
+    handlebars.register_escape_fn(handlebars::no_escape);
+    let values = json!({
+        "asset": asset,
+        "asset_path": path,
+        "due": due.format("%Y-%m-%d").to_string(),
+    });
+    handlebars
+        .render_template(template, &values)
+        .unwrap_or_else(|_| template.to_string())
+}
+
+pub fn build_maintenance_router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_all).post(create))
+        .route("/generate", post(generate))
+        .route("/{id}", patch(update).delete(delete).get(read))
+        .route("/{id}/tickets", get(plan_tickets))
+}
+
+async fn create(
+    _: AdminToken,
+    Db(db): Db,
+    ValidJson(mut plan): ValidJson<InMaintenancePlan>,
+) -> Result<(StatusCode, Json<MaintenancePlan>), ErrResponse> {
+    if plan.next_due.is_none() {
+        plan.next_due = due_after(&plan.recurrence, Local::now().naive_local());
+    }
+    let plan = db
+        .interact(move |conn| {
+            diesel::insert_into(maintenance_plans::table)
+                .values(plan)
+                .returning(MaintenancePlan::as_returning())
+                .get_result(conn)
+        })
+        .await??;
+    Ok((StatusCode::CREATED, Json(plan)))
+}
+
+async fn update(
+    Path(id): Path<i32>,
+    _: AdminToken,
+    Db(db): Db,
+    ValidJson(mut patch): ValidJson<MaintenancePlanPatch>,
+) -> Result<Json<MaintenancePlan>, ErrResponse> {
+    if let Some(recurrence) = &patch.recurrence
+        && patch.next_due.is_none()
+    {
+        patch.next_due = due_after(recurrence, Local::now().naive_local());
+    }
+    let plan = db
+        .interact(move |conn| {
+            if patch == MaintenancePlanPatch::default() {
+                return maintenance_plans::table.find(id).first(conn);
+            }
+            diesel::update(maintenance_plans::table.filter(maintenance_plans::id.eq(id)))
+                .set(patch)
+                .returning(MaintenancePlan::as_returning())
+                .get_result(conn)
+        })
+        .await??;
+    Ok(Json(plan))
+}
+
+async fn list_all(_: UserToken, Db(db): Db) -> Result<impl IntoResponse, ErrResponse> {
+    let plans: Vec<MaintenancePlan> = db
+        .interact(|conn| {
+            maintenance_plans::table
+                .order((maintenance_plans::next_due, maintenance_plans::id))
+                .load(conn)
+        })
+        .await??;
+    Ok(Json(plans))
+}
+
+async fn read(
+    Path(id): Path<i32>,
+    _: UserToken,
+    Db(db): Db,
+) -> Result<Json<MaintenancePlan>, ErrResponse> {
+    let plan: MaintenancePlan = db
+        .interact(move |conn| maintenance_plans::table.find(id).first(conn))
+        .await??;
+    Ok(Json(plan))
+}
+
+async fn delete(Path(id): Path<i32>, _: AdminToken, Db(db): Db) -> Result<(), ErrResponse> {
+    if db
+        .interact(move |conn| {
+            diesel::delete(maintenance_plans::table.filter(maintenance_plans::id.eq(id)))
+                .execute(conn)
+        })
+        .await??
+        == 1
+    {
+        Ok(())
+    } else {
+        Err(ErrResponse::S404("object not found in database"))
+    }
+}
+
+/// Lists the tickets opened by a plan, newest first
+async fn plan_tickets(
+    Path(id): Path<i32>,
+    _: UserToken,
+    Db(db): Db,
+) -> Result<Json<Vec<MaintenanceTicket>>, ErrResponse> {
+    let generated = db
+        .interact(move |conn| {
+            maintenance_plans::table
+                .find(id)
+                .select(maintenance_plans::id)
+                .first::<i32>(conn)?;
+            maintenance_tickets::table
+                .filter(maintenance_tickets::plan_id.eq(id))
+                .order((
+                    maintenance_tickets::created_at.desc(),
+                    maintenance_tickets::id.desc(),
+                ))
+                .select(MaintenanceTicket::as_select())
+                .load(conn)
+        })
+        .await??;
+    Ok(Json(generated))
+}
+
+async fn generate(
+    _: AdminToken,
+    Db(db): Db,
+    State(outbox): State<Outbox>,
+    State(config): State<Config>,
+) -> Result<Json<Vec<Ticket>>, ErrResponse> {
+    Ok(Json(open_due_tickets(db, outbox, config).await?))
+}
+
+/// Opens a ticket for every enabled plan come due, and mails them to the desk as the other new tickets
+pub(crate) async fn open_due_tickets(
+    db: Object,
+    outbox: Outbox,
+    config: Config,
+) -> Result<Vec<Ticket>, ErrResponse> {
+    let now = Local::now().naive_local();
+    let sla = config.sla.clone();
+    let ticket_mail_to = config.ticket_mail_to.clone();
+    let opened: Vec<Ticket> = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                let plans: Vec<MaintenancePlan> = maintenance_plans::table
+                    .filter(maintenance_plans::enabled.eq(true))
+                    .filter(maintenance_plans::next_due.le(now))
+                    // The plans of the archived assets are kept, but open no ticket
+                    .filter(
+                        maintenance_plans::asset_id.eq_any(
+                            assets::table
+                                .filter(assets::archived.eq(false))
+                                .select(assets::id),
+                        ),
+                    )
+                    .order(maintenance_plans::next_due)
+                    .load(conn)?;
+                if plans.is_empty() {
+                    return Ok(Vec::new());
+                }
+                // The tickets are opened on behalf of the built-in admin account
+                let actor: User = users::table
+                    .filter(users::builtin.eq(true))
+                    .filter(users::role.eq(Role::Admin))
+                    .select(User::as_select())
+                    .first(conn)?;
+                let mut opened = Vec::new();
+                for plan in plans {
+                    let asset: Asset = assets::table.find(plan.asset_id).first(conn)?;
+                    let path = asset_path(conn, asset.id)?;
+                    let ticket = InTicket {
+                        asset_id: asset.id,
+                        title: render(&plan.title, &asset, &path, plan.next_due),
+                        creator: MAINTENANCE_CREATOR.to_string(),
+                        creator_mail: String::new(),
+                        creator_phone: String::new(),
+                        description: render(&plan.description, &asset, &path, plan.next_due),
+                        priority: plan.priority,
+                    };
+                    let t: Ticket = diesel::insert_into(tickets::table)
+                        .values((
+                            ticket,
+                            tickets::time.eq(now),
+                            tickets::status.eq(TicketStatus::New),
+                        ))
+                        .returning(Ticket::as_returning())
+                        .get_result(conn)?;
+                    Event {
+                        entity: EventEntity::Ticket,
+                        entity_id: t.id,
+                        ticket_id: Some(t.id),
+                        action: EventAction::Create,
+                        old: None,
+                        new: Some(&t),
+                    }
+                    .record(conn, &actor)?;
+                    diesel::insert_into(maintenance_tickets::table)
+                        .values((
+                            maintenance_tickets::plan_id.eq(plan.id),
+                            maintenance_tickets::ticket_id.eq(t.id),
+                            maintenance_tickets::due.eq(plan.next_due),
+                            maintenance_tickets::created_at.eq(now),
+                        ))
+                        .execute(conn)?;
+                    // The due dates missed while the server was down are skipped, a single ticket being opened
+                    let next_plan = diesel::update(maintenance_plans::table.find(plan.id));
+                    match due_after(&plan.recurrence, now.max(plan.next_due)) {
+                        Some(next_due) => next_plan
+                            .set(maintenance_plans::next_due.eq(next_due))
+                            .execute(conn)?,
+                        // A recurrence without upcoming dates, such as a past year, ends the plan
+                        None => next_plan
+                            .set(maintenance_plans::enabled.eq(false))
+                            .execute(conn)?,
+                    };
+                    let out = OutTicket::new(t, Vec::new(), path, &sla);
+                    outbox::queue_template(conn, (asset, &out), "new_ticket", &ticket_mail_to)?;
+                    opened.push(out.ticket);
+                }
+                Ok::<_, diesel::result::Error>(opened)
+            })
+        })
+        .await??;
+    if !opened.is_empty() {
+        outbox.notify();
+    }
+    Ok(opened)
+}
@@ -1,4 +1,14 @@
 pub mod asset;
+pub mod attachment;
+pub mod category;
 pub mod comment;
+pub mod export;
+pub mod history;
+pub mod label;
+pub mod maintenance;
 pub mod schema;
+pub mod search;
+pub mod sla;
 pub mod ticket;
+pub mod user;
+pub mod workflow;
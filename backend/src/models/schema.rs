@@ -5,6 +5,32 @@ table! {
         id -> Integer,
         title -> Text,
         description -> Text,
+        version -> Integer,
+        parent_id -> Nullable<Integer>,
+        category_id -> Nullable<Integer>,
+        serial_number -> Text,
+        fields -> Text,
+        archived -> Bool,
+    }
+}
+
+table! {
+    asset_categories (id) {
+        id -> Integer,
+        name -> Text,
+        fields -> Text,
+    }
+}
+
+table! {
+    attachments (id) {
+        id -> Integer,
+        ticket_id -> Integer,
+        comment_id -> Nullable<Integer>,
+        filename -> Text,
+        mime_type -> Text,
+        size -> BigInt,
+        created_at -> Timestamp,
     }
 }
 
@@ -15,6 +41,67 @@ table! {
         time -> Timestamp,
         creator -> Text,
         content -> Text,
+        version -> Integer,
+    }
+}
+
+table! {
+    maintenance_plans (id) {
+        id -> Integer,
+        asset_id -> Integer,
+        recurrence -> Text,
+        title -> Text,
+        description -> Text,
+        priority -> Text,
+        next_due -> Timestamp,
+        enabled -> Bool,
+    }
+}
+
+table! {
+    maintenance_tickets (id) {
+        id -> Integer,
+        plan_id -> Integer,
+        ticket_id -> Nullable<Integer>,
+        due -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    outbox (id) {
+        id -> Integer,
+        recipients -> Text,
+        subject -> Text,
+        body -> Text,
+        status -> Text,
+        attempts -> Integer,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamp,
+        next_attempt_at -> Timestamp,
+        sent_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    sla_notifications (ticket_id, kind) {
+        ticket_id -> Integer,
+        kind -> Text,
+        time -> Timestamp,
+    }
+}
+
+table! {
+    ticket_events (id) {
+        id -> Integer,
+        ticket_id -> Nullable<Integer>,
+        entity -> Text,
+        entity_id -> Integer,
+        action -> Text,
+        actor_id -> Nullable<Integer>,
+        actor -> Text,
+        time -> Timestamp,
+        changes -> Text,
     }
 }
 
@@ -28,11 +115,46 @@ table! {
         creator_phone -> Text,
         description -> Text,
         time -> Timestamp,
-        is_closed -> Bool,
+        status -> Text,
+        assignee_id -> Nullable<Integer>,
+        priority -> Text,
+        version -> Integer,
+    }
+}
+
+table! {
+    users (id) {
+        id -> Integer,
+        name -> Text,
+        role -> Text,
+        token_hash -> Text,
+        enabled -> Bool,
+        mail -> Text,
+        builtin -> Bool,
     }
 }
 
+joinable!(assets -> asset_categories (category_id));
+joinable!(attachments -> comments (comment_id));
+joinable!(attachments -> tickets (ticket_id));
 joinable!(comments -> tickets (ticket_id));
+joinable!(maintenance_plans -> assets (asset_id));
+joinable!(maintenance_tickets -> maintenance_plans (plan_id));
+joinable!(maintenance_tickets -> tickets (ticket_id));
+joinable!(sla_notifications -> tickets (ticket_id));
 joinable!(tickets -> assets (asset_id));
+joinable!(tickets -> users (assignee_id));
 
-allow_tables_to_appear_in_same_query!(assets, comments, tickets,);
+allow_tables_to_appear_in_same_query!(
+    asset_categories,
+    assets,
+    attachments,
+    comments,
+    maintenance_plans,
+    maintenance_tickets,
+    outbox,
+    sla_notifications,
+    ticket_events,
+    tickets,
+    users,
+);
@@ -0,0 +1,104 @@
+use axum::{Json, Router, extract::Query, routing::get};
+use diesel::{
+    prelude::*,
+    sql_types::{BigInt, Double, Integer, Text},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{AppState, Db, UserToken},
+    errors::ErrResponse,
+    models::{schema::tickets, ticket::Ticket},
+};
+
+// Markers put around the matched terms by SQLite, replaced by HTML tags once the snippet is escaped
+const MATCH_START: &str = "\u{1}";
+const MATCH_END: &str = "\u{2}";
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub ticket: Ticket,
+    pub rank: f64,
+    /// Excerpt of the best matching field, HTML escaped, with the matched terms wrapped in `<mark>` tags
+    pub snippet: String,
+}
+
+#[derive(QueryableByName)]
+struct RankedTicket {
+    #[diesel(sql_type = Integer)]
+    ticket_id: i32,
+    #[diesel(sql_type = Text)]
+    snippet: String,
+    #[diesel(sql_type = Double)]
+    rank: f64,
+}
+
+pub fn build_search_router() -> Router<AppState> {
+    Router::new().route("/", get(search))
+}
+
+/// Turns the user input into a FTS5 query matching every term as a prefix, so that FTS5 syntax is never interpreted
+fn match_expression(q: &str) -> String {
+    q.split_whitespace()
+        .map(|term| format!("\"{}\"*", term.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn highlight(snippet: &str) -> String {
+    handlebars::html_escape(snippet)
+        .replace(MATCH_START, "<mark>")
+        .replace(MATCH_END, "</mark>")
+}
+
+async fn search(
+    _: UserToken,
+    Db(db): Db,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<Vec<SearchHit>>, ErrResponse> {
+    let expression = match_expression(&query.q);
+    if expression.is_empty() {
+        return Ok(Json(vec![]));
+    }
+    let limit = query.limit.unwrap_or(50);
+    let hits = db
+        .interact(move |conn| {
+            // Each ticket and each comment is a row of the index: keep the best ranked row of every ticket,
+            // the snippets being computed before grouping as FTS5 functions cannot be used in aggregates
+            let ranked: Vec<RankedTicket> = diesel::sql_query(format!(
+                "WITH hits AS MATERIALIZED (
+                    SELECT ticket_id, snippet(search_index, -1, '{MATCH_START}', '{MATCH_END}', '…', 16) AS snippet, rank
+                    FROM search_index WHERE search_index MATCH ?
+                )
+                SELECT ticket_id, snippet, min(rank) AS rank FROM hits GROUP BY ticket_id ORDER BY rank LIMIT ?"
+            ))
+            .bind::<Text, _>(expression)
+            .bind::<BigInt, _>(limit)
+            .load(conn)?;
+            let ids: Vec<i32> = ranked.iter().map(|r| r.ticket_id).collect();
+            let mut tickets: Vec<Ticket> = tickets::table
+                .filter(tickets::id.eq_any(ids))
+                .load(conn)?;
+            let hits = ranked
+                .into_iter()
+                .filter_map(|r| {
+                    let index = tickets.iter().position(|t| t.id == r.ticket_id)?;
+                    Some(SearchHit {
+                        ticket: tickets.swap_remove(index),
+                        rank: r.rank,
+                        snippet: highlight(&r.snippet),
+                    })
+                })
+                .collect::<Vec<SearchHit>>();
+            Ok::<_, diesel::result::Error>(hits)
+        })
+        .await??;
+    Ok(Json(hits))
+}
@@ -0,0 +1,268 @@
+use axum::{Json, extract::State};
+use chrono::{Local, NaiveDateTime, TimeDelta};
+use deadpool_diesel::sqlite::Object;
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    prelude::*,
+    serialize::{self, IsNull, Output, ToSql},
+    sql_types::Text,
+    sqlite::{Sqlite, SqliteValue},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Config, Db, UserToken},
+    errors::ErrResponse,
+    models::{
+        schema::{sla_notifications, tickets},
+        ticket::Ticket,
+        workflow::TicketStatus,
+    },
+    outbox::{self, Outbox},
+};
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    Deserialize,
+    Serialize,
+    AsExpression,
+    FromSqlRow,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 4] = [
+        Priority::Low,
+        Priority::Normal,
+        Priority::High,
+        Priority::Urgent,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+}
+
+impl ToSql<Text, Sqlite> for Priority {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        out.set_value(self.as_str());
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Sqlite> for Priority {
+    fn from_sql(bytes: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        let value = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        Priority::ALL
+            .into_iter()
+            .find(|p| p.as_str() == value)
+            .ok_or_else(|| format!("unknown ticket priority: {}", value).into())
+    }
+}
+
+/// Delays, from the ticket creation, within which the desk must respond to and resolve a ticket
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct SlaTarget {
+    pub response_hours: i64,
+    pub resolution_hours: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SlaTargets {
+    pub low: SlaTarget,
+    pub normal: SlaTarget,
+    pub high: SlaTarget,
+    pub urgent: SlaTarget,
+}
+
+impl Default for SlaTargets {
+    fn default() -> Self {
+        SlaTargets {
+            low: SlaTarget {
+                response_hours: 72,
+                resolution_hours: 240,
+            },
+            normal: SlaTarget {
+                response_hours: 24,
+                resolution_hours: 120,
+            },
+            high: SlaTarget {
+                response_hours: 8,
+                resolution_hours: 48,
+            },
+            urgent: SlaTarget {
+                response_hours: 1,
+                resolution_hours: 8,
+            },
+        }
+    }
+}
+
+impl SlaTargets {
+    /// Reads the targets from a JSON object, such as `{"low": {"response_hours": 72, "resolution_hours": 240}, "normal": ...}`
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    pub fn target(&self, priority: Priority) -> SlaTarget {
+        match priority {
+            Priority::Low => self.low,
+            Priority::Normal => self.normal,
+            Priority::High => self.high,
+            Priority::Urgent => self.urgent,
+        }
+    }
+
+    pub fn due(&self, ticket: &Ticket) -> SlaDue {
+        let target = self.target(ticket.priority);
+        SlaDue {
+            response_due: ticket.time + TimeDelta::hours(target.response_hours),
+            resolution_due: ticket.time + TimeDelta::hours(target.resolution_hours),
+        }
+    }
+
+    /// Gets the most severe alert for an open ticket: a response is expected while the ticket is new,
+    /// and a resolution while it is open
+    fn alert(&self, ticket: &Ticket, now: NaiveDateTime, warning: TimeDelta) -> Option<SlaAlert> {
+        let due = self.due(ticket);
+        let mut alerts = vec![];
+        if ticket.status == TicketStatus::New {
+            if now >= due.response_due {
+                alerts.push((SlaAlertKind::ResponseBreach, due.response_due));
+            } else if now >= due.response_due - warning {
+                alerts.push((SlaAlertKind::ResponseWarning, due.response_due));
+            }
+        }
+        if now >= due.resolution_due {
+            alerts.push((SlaAlertKind::ResolutionBreach, due.resolution_due));
+        } else if now >= due.resolution_due - warning {
+            alerts.push((SlaAlertKind::ResolutionWarning, due.resolution_due));
+        }
+        alerts
+            .into_iter()
+            .max_by_key(|a| a.0)
+            .map(|(kind, due)| SlaAlert {
+                kind,
+                due,
+                ticket: ticket.clone(),
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct SlaDue {
+    pub response_due: NaiveDateTime,
+    pub resolution_due: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlaAlertKind {
+    ResponseWarning,
+    ResolutionWarning,
+    ResponseBreach,
+    ResolutionBreach,
+}
+
+impl SlaAlertKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SlaAlertKind::ResponseWarning => "response_warning",
+            SlaAlertKind::ResolutionWarning => "resolution_warning",
+            SlaAlertKind::ResponseBreach => "response_breach",
+            SlaAlertKind::ResolutionBreach => "resolution_breach",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SlaAlert {
+    pub kind: SlaAlertKind,
+    pub due: NaiveDateTime,
+    pub ticket: Ticket,
+}
+
+/// Detects the open tickets about to breach or in breach of their targets, and mails the ones not notified yet to the desk
+pub(crate) async fn notify_sla_alerts(
+    db: Object,
+    outbox: Outbox,
+    config: Config,
+) -> Result<Vec<SlaAlert>, ErrResponse> {
+    let now = Local::now().naive_local();
+    let sla = config.sla.clone();
+    let warning = TimeDelta::hours(config.sla_warning_hours);
+    let alerts = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                let open_tickets: Vec<Ticket> = tickets::table
+                    .filter(tickets::status.eq_any(TicketStatus::OPEN))
+                    .order(tickets::time.asc())
+                    .load(conn)?;
+                let mut alerts = vec![];
+                for alert in open_tickets
+                    .iter()
+                    .filter_map(|t| sla.alert(t, now, warning))
+                {
+                    // Every kind of alert is only sent once per ticket
+                    let inserted = diesel::insert_into(sla_notifications::table)
+                        .values((
+                            sla_notifications::ticket_id.eq(alert.ticket.id),
+                            sla_notifications::kind.eq(alert.kind.as_str()),
+                            sla_notifications::time.eq(now),
+                        ))
+                        .on_conflict_do_nothing()
+                        .execute(conn)?;
+                    if inserted == 1 {
+                        alerts.push(alert);
+                    }
+                }
+                if !alerts.is_empty() {
+                    outbox::queue_template(conn, &alerts, "sla_tickets", &config.ticket_mail_to)?;
+                }
+                Ok::<_, diesel::result::Error>(alerts)
+            })
+        })
+        .await??;
+    if !alerts.is_empty() {
+        outbox.notify();
+    }
+    Ok(alerts)
+}
+
+/// Forgets the alerts sent about a ticket, so that it is notified again once reopened
+pub(crate) fn clear_sla_notifications(
+    conn: &mut SqliteConnection,
+    ticket_id: i32,
+) -> QueryResult<usize> {
+    diesel::delete(sla_notifications::table.filter(sla_notifications::ticket_id.eq(ticket_id)))
+        .execute(conn)
+}
+
+pub(crate) async fn mail_sla(
+    Db(db): Db,
+    _: UserToken,
+    State(outbox): State<Outbox>,
+    State(config): State<Config>,
+) -> Result<Json<Vec<SlaAlert>>, ErrResponse> {
+    Ok(Json(notify_sla_alerts(db, outbox, config).await?))
+}
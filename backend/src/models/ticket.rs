@@ -1,24 +1,39 @@
 use crate::{
     config::{AdminToken, AppState, Config, Db, UserToken},
     errors::ErrResponse,
-    mail::Mailer,
-    models::{asset::Asset, comment::Comment, schema::*},
+    etag::{IfMatch, etag},
+    models::{
+        asset::{Asset, asset_path, in_subtree},
+        attachment::{self, delete_ticket_attachments, remove_files},
+        comment::{Comment, delete_ticket_comments},
+        export::export,
+        history::{Event, EventAction, EventEntity, history},
+        schema::*,
+        sla::{Priority, SlaDue, SlaTargets, clear_sla_notifications, mail_sla},
+        user::{Role, User},
+        workflow::{TicketStatus, get_workflow},
+    },
+    outbox::{self, Outbox},
+    validation::{
+        NAME_LENGTH, TEXT_LENGTH, TITLE_LENGTH, ValidJson, Validate, Validator, option_trim,
+    },
 };
 use axum::{
     Json, Router,
     body::{Body, Bytes},
-    extract::{Path, State},
-    http::StatusCode,
-    response::{Html, IntoResponse},
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::IntoResponse,
     routing::{get, patch, post},
 };
+use chrono::Local;
 use deadpool_diesel::sqlite::Object;
-use diesel::prelude::*;
+use diesel::{prelude::*, sqlite::Sqlite};
 use handlebars::{
     Context, DirectorySourceOptions, Handlebars, Helper, HelperResult, Output, RenderContext,
     RenderError,
 };
-use image::{GenericImageView, imageops::FilterType::Lanczos3};
+use image::{DynamicImage, GenericImageView, imageops::FilterType::Lanczos3};
 use serde::{Deserialize, Serialize};
 use serde_trim::string_trim;
 use tokio::{fs::File, task::spawn_blocking};
@@ -36,7 +51,6 @@ use std::{fmt::Debug, fs};
     Serialize,
     Queryable,
     Insertable,
-    AsChangeset,
     PartialEq,
 )]
 #[diesel(table_name = tickets, belongs_to(Asset))]
@@ -53,8 +67,16 @@ pub struct Ticket {
     pub creator_phone: String,
     #[serde(deserialize_with = "string_trim")]
     pub description: String,
+    /// Creation time, set by the server
     pub time: chrono::NaiveDateTime,
-    pub is_closed: bool,
+    pub status: TicketStatus,
+    /// Desk member handling the ticket, only changed through the assignment endpoints
+    #[serde(default)]
+    pub assignee_id: Option<i32>,
+    #[serde(default)]
+    pub priority: Priority,
+    /// Incremented on every change, and sent as the `ETag` of the object
+    pub version: i32,
 }
 
 #[derive(Clone, Insertable, Deserialize, Serialize, PartialEq, Debug)]
@@ -63,7 +85,8 @@ pub struct InTicket {
     pub asset_id: i32,
     #[serde(deserialize_with = "string_trim")]
     pub title: String,
-    #[serde(deserialize_with = "string_trim")]
+    /// Replaced by the name of the authenticated user, unless the ticket is created with a shared token
+    #[serde(default, deserialize_with = "string_trim")]
     pub creator: String,
     #[serde(deserialize_with = "string_trim")]
     pub creator_mail: String,
@@ -71,8 +94,8 @@ pub struct InTicket {
     pub creator_phone: String,
     #[serde(deserialize_with = "string_trim")]
     pub description: String,
-    pub time: chrono::NaiveDateTime,
-    pub is_closed: bool,
+    #[serde(default)]
+    pub priority: Priority,
 }
 
 impl PartialEq<InTicket> for Ticket {
@@ -83,15 +106,236 @@ impl PartialEq<InTicket> for Ticket {
             && self.creator_mail == other.creator_mail
             && self.creator_phone == other.creator_phone
             && self.description == other.description
-            && self.time == other.time
+            && self.priority == other.priority
+    }
+}
+
+fn check_ticket(
+    v: &mut Validator,
+    title: &str,
+    creator: &str,
+    creator_mail: &str,
+    creator_phone: &str,
+    description: &str,
+) {
+    v.required("title", title)
+        .max_length("title", title, TITLE_LENGTH)
+        .max_length("creator", creator, NAME_LENGTH)
+        .mail("creator_mail", creator_mail)
+        .phone("creator_phone", creator_phone)
+        .max_length("description", description, TEXT_LENGTH);
+}
+
+impl Validate for InTicket {
+    fn check(&self, v: &mut Validator) {
+        check_ticket(
+            v,
+            &self.title,
+            &self.creator,
+            &self.creator_mail,
+            &self.creator_phone,
+            &self.description,
+        );
+    }
+}
+
+/// Changes to a ticket, the missing fields being left untouched
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, AsChangeset)]
+#[diesel(table_name = tickets)]
+pub struct TicketPatch {
+    #[serde(default)]
+    pub asset_id: Option<i32>,
+    #[serde(default, deserialize_with = "option_trim")]
+    pub title: Option<String>,
+    #[serde(default, deserialize_with = "option_trim")]
+    pub creator: Option<String>,
+    #[serde(default, deserialize_with = "option_trim")]
+    pub creator_mail: Option<String>,
+    #[serde(default, deserialize_with = "option_trim")]
+    pub creator_phone: Option<String>,
+    #[serde(default, deserialize_with = "option_trim")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub status: Option<TicketStatus>,
+    #[serde(default)]
+    pub priority: Option<Priority>,
+}
+
+impl TicketPatch {
+    /// Whether applying the patch would change the ticket
+    fn changes(&self, ticket: &Ticket) -> bool {
+        fn differs<T: PartialEq>(patched: &Option<T>, current: &T) -> bool {
+            patched.as_ref().is_some_and(|value| value != current)
+        }
+        differs(&self.asset_id, &ticket.asset_id)
+            || differs(&self.title, &ticket.title)
+            || differs(&self.creator, &ticket.creator)
+            || differs(&self.creator_mail, &ticket.creator_mail)
+            || differs(&self.creator_phone, &ticket.creator_phone)
+            || differs(&self.description, &ticket.description)
+            || differs(&self.status, &ticket.status)
+            || differs(&self.priority, &ticket.priority)
     }
 }
 
-#[derive(Serialize)]
-struct OutTicket {
+impl Validate for TicketPatch {
+    fn check(&self, v: &mut Validator) {
+        if let Some(title) = &self.title {
+            v.required("title", title)
+                .max_length("title", title, TITLE_LENGTH);
+        }
+        if let Some(creator) = &self.creator {
+            v.max_length("creator", creator, NAME_LENGTH);
+        }
+        if let Some(creator_mail) = &self.creator_mail {
+            v.mail("creator_mail", creator_mail);
+        }
+        if let Some(creator_phone) = &self.creator_phone {
+            v.phone("creator_phone", creator_phone);
+        }
+        if let Some(description) = &self.description {
+            v.max_length("description", description, TEXT_LENGTH);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OutTicket {
+    #[serde(flatten)]
+    pub ticket: Ticket,
+    pub comments: Vec<Comment>,
     #[serde(flatten)]
-    ticket: Ticket,
-    comments: Vec<Comment>,
+    pub due: SlaDue,
+    /// Titles of the asset of the ticket and of its ancestors, such as `Site / Building / Room`
+    #[serde(default)]
+    pub asset_path: String,
+}
+
+impl OutTicket {
+    pub(crate) fn new(
+        ticket: Ticket,
+        comments: Vec<Comment>,
+        asset_path: String,
+        sla: &SlaTargets,
+    ) -> Self {
+        OutTicket {
+            due: sla.due(&ticket),
+            ticket,
+            comments,
+            asset_path,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    Id,
+    AssetId,
+    Title,
+    Creator,
+    #[default]
+    Time,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SortDirection {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Query parameters used to filter, sort and paginate ticket lists
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct TicketFilter {
+    pub status: Option<TicketStatus>,
+    pub is_open: Option<bool>,
+    pub asset_id: Option<i32>,
+    /// Id of an asset, matching the tickets of this asset and of all the assets below it
+    pub within_asset_id: Option<i32>,
+    pub creator: Option<String>,
+    pub priority: Option<Priority>,
+    pub assignee_id: Option<i32>,
+    pub is_assigned: Option<bool>,
+    pub from: Option<chrono::NaiveDateTime>,
+    pub to: Option<chrono::NaiveDateTime>,
+    #[serde(default)]
+    pub sort: SortField,
+    #[serde(default)]
+    pub direction: SortDirection,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+impl TicketFilter {
+    /// Builds the query selecting the tickets matching the filter, without sorting nor pagination
+    fn query(&self) -> tickets::BoxedQuery<'static, Sqlite> {
+        let mut query = tickets::table.into_boxed();
+        if let Some(status) = self.status {
+            query = query.filter(tickets::status.eq(status));
+        }
+        match self.is_open {
+            Some(true) => query = query.filter(tickets::status.eq_any(TicketStatus::OPEN)),
+            Some(false) => query = query.filter(tickets::status.ne_all(TicketStatus::OPEN)),
+            None => (),
+        }
+        if let Some(asset_id) = self.asset_id {
+            query = query.filter(tickets::asset_id.eq(asset_id));
+        }
+        if let Some(asset_id) = self.within_asset_id {
+            query = query.filter(in_subtree("tickets.asset_id", asset_id));
+        }
+        if let Some(creator) = &self.creator {
+            query = query.filter(tickets::creator.eq(creator.clone()));
+        }
+        if let Some(priority) = self.priority {
+            query = query.filter(tickets::priority.eq(priority));
+        }
+        if let Some(assignee_id) = self.assignee_id {
+            query = query.filter(tickets::assignee_id.eq(assignee_id));
+        }
+        match self.is_assigned {
+            Some(true) => query = query.filter(tickets::assignee_id.is_not_null()),
+            Some(false) => query = query.filter(tickets::assignee_id.is_null()),
+            None => (),
+        }
+        if let Some(from) = self.from {
+            query = query.filter(tickets::time.ge(from));
+        }
+        if let Some(to) = self.to {
+            query = query.filter(tickets::time.le(to));
+        }
+        query
+    }
+
+    /// Builds the query selecting the requested page of sorted tickets matching the filter
+    pub(crate) fn sorted_page(&self) -> tickets::BoxedQuery<'static, Sqlite> {
+        let query = self.query();
+        let query = match (self.sort, self.direction) {
+            (SortField::Id, SortDirection::Asc) => query.order(tickets::id.asc()),
+            (SortField::Id, SortDirection::Desc) => query.order(tickets::id.desc()),
+            (SortField::AssetId, SortDirection::Asc) => query.order(tickets::asset_id.asc()),
+            (SortField::AssetId, SortDirection::Desc) => query.order(tickets::asset_id.desc()),
+            (SortField::Title, SortDirection::Asc) => query.order(tickets::title.asc()),
+            (SortField::Title, SortDirection::Desc) => query.order(tickets::title.desc()),
+            (SortField::Creator, SortDirection::Asc) => query.order(tickets::creator.asc()),
+            (SortField::Creator, SortDirection::Desc) => query.order(tickets::creator.desc()),
+            (SortField::Time, SortDirection::Asc) => query.order(tickets::time.asc()),
+            (SortField::Time, SortDirection::Desc) => query.order(tickets::time.desc()),
+        };
+        // Sort by id as well so that pages are stable when the sort field has duplicates
+        let query = match self.direction {
+            SortDirection::Asc => query.then_order_by(tickets::id.asc()),
+            SortDirection::Desc => query.then_order_by(tickets::id.desc()),
+        };
+        match (self.limit, self.offset) {
+            (Some(limit), offset) => query.limit(limit).offset(offset.unwrap_or(0)),
+            // SQLite does not support OFFSET without LIMIT, -1 meaning no limit
+            (None, Some(offset)) => query.limit(-1).offset(offset),
+            (None, None) => query,
+        }
+    }
 }
 
 pub fn build_tickets_router() -> Router<AppState> {
@@ -99,46 +343,86 @@ pub fn build_tickets_router() -> Router<AppState> {
         .route("/", get(list).post(create).delete(destroy))
         .route("/all", get(list_all))
         .route("/{id}", patch(update).delete(delete).get(read))
+        .route("/{id}/history", get(history))
+        .route(
+            "/{id}/attachments",
+            get(attachment::list).post(attachment::upload),
+        )
+        .route(
+            "/{id}/attachments/{attachment_id}",
+            get(attachment::download).delete(attachment::delete),
+        )
+        .route("/{id}/assign", post(assign))
+        .route("/{id}/self_assign", post(self_assign))
+        .route("/{id}/unassign", post(unassign))
+        .route("/{id}/close", post(close))
+        .route("/{id}/reopen", post(reopen))
         .route(
             "/photos/{id}",
             post(upload).get(retrieve).delete(delete_photo),
         )
         .route("/mail_open", get(mail_open))
+        .route("/mail_sla", get(mail_sla))
+        .route("/workflow", get(get_workflow))
         .route("/export", get(export))
 }
 
-const PHOTOS_PATH: &str = "data/tickets/photos";
-
 async fn create(
-    State(mut mailer): State<Mailer>,
+    State(outbox): State<Outbox>,
     State(config): State<Config>,
-    _: UserToken,
+    UserToken(user): UserToken,
     Db(db): Db,
-    Json(ticket): Json<InTicket>,
+    ValidJson(mut ticket): ValidJson<InTicket>,
 ) -> Result<(StatusCode, Json<Ticket>), ErrResponse> {
+    if let Some(name) = user.author() {
+        ticket.creator = name.to_string();
+    }
     let asset_id = ticket.asset_id;
     // Check that the asset that we want to create the ticket for exists...
     match db
         .interact(move |conn| assets::table.find(asset_id).get_result::<Asset>(conn))
         .await?
     {
+        Ok(asset) if asset.archived => Err(ErrResponse::S409(
+            "cannot create ticket related to archived asset",
+        )),
         Ok(asset) => {
             // ...create the ticket if so, and return the created ticket
             let t = db
-                .interact(|conn| {
-                    diesel::insert_into(tickets::table)
-                        .values(ticket)
-                        .returning(Ticket::as_returning())
-                        .get_result(conn)
+                .interact(move |conn| {
+                    conn.immediate_transaction(|conn| {
+                        let t = diesel::insert_into(tickets::table)
+                            // New tickets always start the workflow, the status being then changed by its transitions
+                            .values((
+                                ticket,
+                                tickets::time.eq(Local::now().naive_local()),
+                                tickets::status.eq(TicketStatus::New),
+                            ))
+                            .returning(Ticket::as_returning())
+                            .get_result(conn)?;
+                        Event {
+                            entity: EventEntity::Ticket,
+                            entity_id: t.id,
+                            ticket_id: Some(t.id),
+                            action: EventAction::Create,
+                            old: None,
+                            new: Some(&t),
+                        }
+                        .record(conn, &user)?;
+                        let path = asset_path(conn, t.asset_id)?;
+                        let out = OutTicket::new(t.clone(), Vec::new(), path, &config.sla);
+                        outbox::queue_template(
+                            conn,
+                            (asset, &out),
+                            "new_ticket",
+                            &config.ticket_mail_to,
+                        )?;
+                        Ok::<_, diesel::result::Error>(t)
+                    })
                 })
                 .await??;
-
-            let t2 = t.clone();
-            spawn_blocking(move || match template((asset, &t), "new_ticket") {
-                Ok(r) => mailer.send_mail_to(r.0, r.1, config.ticket_mail_to),
-                Err(e) => println!("Handlebars error : {}", e),
-            });
-            Ok((StatusCode::CREATED, Json(t2)))
+            outbox.notify();
+            Ok((StatusCode::CREATED, Json(t)))
         }
         Err(..) => Err(ErrResponse::S404(
             "cannot create ticket related to non existing asset",
@@ -147,200 +431,474 @@ async fn create(
 }
 
 async fn update(
-    State(mut mailer): State<Mailer>,
+    State(outbox): State<Outbox>,
+    State(config): State<Config>,
     Path(id): Path<i32>,
-    AdminToken: AdminToken,
+    AdminToken(user): AdminToken,
     Db(db): Db,
-    Json(ticket): Json<Ticket>,
-) -> Result<StatusCode, ErrResponse> {
-    let ticket = db
+    if_match: IfMatch,
+    ValidJson(patch): ValidJson<TicketPatch>,
+) -> Result<impl IntoResponse, ErrResponse> {
+    let condition = Condition {
+        if_match,
+        status: None,
+    };
+    change_ticket(outbox, config, db, id, user, patch, condition).await
+}
+
+async fn close(
+    State(outbox): State<Outbox>,
+    State(config): State<Config>,
+    Path(id): Path<i32>,
+    AdminToken(user): AdminToken,
+    Db(db): Db,
+    if_match: IfMatch,
+) -> Result<impl IntoResponse, ErrResponse> {
+    let patch = TicketPatch {
+        status: Some(TicketStatus::Closed),
+        ..Default::default()
+    };
+    let condition = Condition {
+        if_match,
+        status: None,
+    };
+    change_ticket(outbox, config, db, id, user, patch, condition).await
+}
+
+async fn reopen(
+    State(outbox): State<Outbox>,
+    State(config): State<Config>,
+    Path(id): Path<i32>,
+    AdminToken(user): AdminToken,
+    Db(db): Db,
+    if_match: IfMatch,
+) -> Result<impl IntoResponse, ErrResponse> {
+    let patch = TicketPatch {
+        status: Some(TicketStatus::New),
+        ..Default::default()
+    };
+    let condition = Condition {
+        if_match,
+        status: Some(TicketStatus::Closed),
+    };
+    change_ticket(outbox, config, db, id, user, patch, condition).await
+}
+
+/// Conditions the ticket must meet for a change to apply
+struct Condition {
+    if_match: IfMatch,
+    /// Status the ticket must be in, for the actions that only apply to some tickets
+    status: Option<TicketStatus>,
+}
+
+/// Applies the changes to a ticket, the status following the workflow,
+/// and lets the creator know when the transition has a mail template
+async fn change_ticket(
+    outbox: Outbox,
+    config: Config,
+    db: Object,
+    id: i32,
+    user: User,
+    patch: TicketPatch,
+    condition: Condition,
+) -> Result<impl IntoResponse, ErrResponse> {
+    let workflow = config.workflow;
+    let sla = config.sla;
+    let (ticket, queued) = db
         .interact(move |conn| {
-            diesel::update(tickets::table.filter(tickets::id.eq(id)))
-                .set(ticket)
-                .returning(Ticket::as_returning())
-                .get_result(conn)
+            conn.immediate_transaction(|conn| {
+                let previous: Ticket = tickets::table.find(id).first(conn)?;
+                condition.if_match.check(previous.version)?;
+                if condition.status.is_some_and(|s| s != previous.status) {
+                    return Err(ErrResponse::S409(
+                        "status change not allowed by the workflow",
+                    ));
+                }
+                // A status change must be an allowed transition of the workflow
+                let template_name = match patch.status {
+                    Some(status) if status != previous.status => {
+                        match workflow.transition(previous.status, status) {
+                            Some(t) => t.template.clone(),
+                            None => {
+                                return Err(ErrResponse::S409(
+                                    "status change not allowed by the workflow",
+                                ));
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+                // A ticket stays on its asset once archived, but cannot be moved to an archived one
+                if let Some(asset_id) = patch.asset_id.filter(|a| *a != previous.asset_id) {
+                    let archived: Option<bool> = assets::table
+                        .find(asset_id)
+                        .select(assets::archived)
+                        .first(conn)
+                        .optional()?;
+                    if archived == Some(true) {
+                        return Err(ErrResponse::S409("cannot move ticket to archived asset"));
+                    }
+                }
+                // Nothing is saved nor recorded when nothing changes, such as when closing a closed ticket,
+                // so that the version the other clients hold stays valid
+                if !patch.changes(&previous) {
+                    return Ok((previous, false));
+                }
+                let ticket: Ticket = diesel::update(tickets::table.filter(tickets::id.eq(id)))
+                    .set((patch, tickets::version.eq(tickets::version + 1)))
+                    .returning(Ticket::as_returning())
+                    .get_result(conn)?;
+                if previous.status == TicketStatus::Closed && ticket.status != TicketStatus::Closed
+                {
+                    clear_sla_notifications(conn, id)?;
+                }
+                let action = if ticket.status == TicketStatus::Closed
+                    && previous.status != TicketStatus::Closed
+                {
+                    EventAction::Close
+                } else {
+                    EventAction::Update
+                };
+                Event {
+                    entity: EventEntity::Ticket,
+                    entity_id: id,
+                    ticket_id: Some(id),
+                    action,
+                    old: Some(&previous),
+                    new: Some(&ticket),
+                }
+                .record(conn, &user)?;
+                // If the transition has a mail template, send it to the creator
+                let queued = match template_name {
+                    Some(template_name) if !ticket.creator_mail.is_empty() => {
+                        let t = ticket_with_comments(conn, id, &sla)?;
+                        outbox::queue_template(conn, &t, &template_name, &t.ticket.creator_mail)?;
+                        true
+                    }
+                    _ => false,
+                };
+                Ok((ticket, queued))
+            })
         })
         .await??;
-    // If the ticket is closed, send a mail to the creator
-    if ticket.is_closed && !ticket.creator_mail.is_empty() {
-        match ticket_with_comments(db, ticket.id).await {
-            Ok(t) => {
-                spawn_blocking(move || match template(&t, "closed_ticket") {
-                    Ok(r) => mailer.send_mail_to(r.0, r.1, t.ticket.creator_mail),
-                    Err(e) => println!("Handlebars error : {}", e),
-                });
+    if queued {
+        outbox.notify();
+    }
+    Ok((
+        StatusCode::NO_CONTENT,
+        [(header::ETAG, etag(ticket.version))],
+    ))
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Assignment {
+    pub assignee_id: i32,
+}
+
+/// The assignee is checked against the users when assigning
+impl Validate for Assignment {
+    fn check(&self, _: &mut Validator) {}
+}
+
+async fn assign(
+    State(outbox): State<Outbox>,
+    State(config): State<Config>,
+    Path(id): Path<i32>,
+    AdminToken(caller): AdminToken,
+    Db(db): Db,
+    ValidJson(assignment): ValidJson<Assignment>,
+) -> Result<StatusCode, ErrResponse> {
+    set_assignee(outbox, config, db, id, caller, assignment.assignee_id).await
+}
+
+async fn self_assign(
+    State(outbox): State<Outbox>,
+    State(config): State<Config>,
+    Path(id): Path<i32>,
+    AdminToken(caller): AdminToken,
+    Db(db): Db,
+) -> Result<StatusCode, ErrResponse> {
+    let assignee_id = caller.id;
+    set_assignee(outbox, config, db, id, caller, assignee_id).await
+}
+
+async fn set_assignee(
+    outbox: Outbox,
+    config: Config,
+    db: Object,
+    id: i32,
+    caller: User,
+    assignee_id: i32,
+) -> Result<StatusCode, ErrResponse> {
+    let assignee = db
+        .interact(move |conn| {
+            users::table
+                .find(assignee_id)
+                .filter(users::enabled.eq(true))
+                .filter(users::role.eq(Role::Admin))
+                .select(User::as_select())
+                .first(conn)
+                .optional()
+        })
+        .await??
+        .ok_or(ErrResponse::S409(
+            "tickets can only be assigned to enabled desk members",
+        ))?;
+    // Let the assignee know, unless the desk member assigned the ticket to itself
+    let queued = assignee.id != caller.id && !assignee.mail.is_empty();
+    db.interact(move |conn| {
+        conn.immediate_transaction(|conn| {
+            set_assignee_id(conn, &caller, id, Some(assignee_id))?;
+            if queued {
+                let t = ticket_with_comments(conn, id, &config.sla)?;
+                outbox::queue_template(conn, (&t, &assignee), "assigned_ticket", &assignee.mail)?;
             }
-            Err(e) => println!("{}", e),
-        }
+            Ok::<_, ErrResponse>(())
+        })
+    })
+    .await??;
+    if queued {
+        outbox.notify();
     }
     Ok(StatusCode::NO_CONTENT)
 }
 
-async fn list(UserToken: UserToken, Db(db): Db) -> Result<impl IntoResponse, ErrResponse> {
+async fn unassign(
+    Path(id): Path<i32>,
+    AdminToken(user): AdminToken,
+    Db(db): Db,
+) -> Result<StatusCode, ErrResponse> {
+    db.interact(move |conn| {
+        conn.immediate_transaction(|conn| set_assignee_id(conn, &user, id, None))
+    })
+    .await??;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Changes the assignee of a ticket, in a transaction opened by the caller
+fn set_assignee_id(
+    conn: &mut SqliteConnection,
+    actor: &User,
+    id: i32,
+    assignee_id: Option<i32>,
+) -> Result<(), ErrResponse> {
+    let previous: Ticket = tickets::table
+        .find(id)
+        .first(conn)
+        .map_err(|_| ErrResponse::S404("object not found in database"))?;
+    let ticket = diesel::update(tickets::table.find(id))
+        .set((
+            tickets::assignee_id.eq(assignee_id),
+            tickets::version.eq(tickets::version + 1),
+        ))
+        .returning(Ticket::as_returning())
+        .get_result(conn)?;
+    Event {
+        entity: EventEntity::Ticket,
+        entity_id: id,
+        ticket_id: Some(id),
+        action: EventAction::Update,
+        old: Some(&previous),
+        new: Some(&ticket),
+    }
+    .record(conn, actor)?;
+    Ok(())
+}
+
+async fn list(_: UserToken, Db(db): Db) -> Result<impl IntoResponse, ErrResponse> {
     let res: Vec<i32> = db
         .interact(|conn| tickets::table.select(tickets::id).load(conn))
         .await??;
     Ok(Json(res))
 }
 
-async fn list_all(UserToken: UserToken, Db(db): Db) -> Result<impl IntoResponse, ErrResponse> {
-    let all_tickets: Vec<Ticket> = db
-        .interact(|conn| tickets::table.order(tickets::time.desc()).load(conn))
+async fn list_all(
+    _: UserToken,
+    Db(db): Db,
+    Query(filter): Query<TicketFilter>,
+) -> Result<impl IntoResponse, ErrResponse> {
+    let (total, tickets): (i64, Vec<Ticket>) = db
+        .interact(move |conn| {
+            let total = filter.query().count().get_result(conn)?;
+            let tickets = filter.sorted_page().load(conn)?;
+            Ok::<_, diesel::result::Error>((total, tickets))
+        })
         .await??;
-    Ok(Json(all_tickets))
+    Ok(([("X-Total-Count", total.to_string())], Json(tickets)))
 }
 
 async fn mail_open(
     Db(db): Db,
-    UserToken: UserToken,
-    State(mut mailer): State<Mailer>,
+    _: UserToken,
+    State(outbox): State<Outbox>,
     State(config): State<Config>,
 ) -> Result<impl IntoResponse, ErrResponse> {
+    Ok(Json(notify_open_tickets(db, outbox, config).await?))
+}
+
+/// Sends the digest of the open tickets to the desk, if any
+pub(crate) async fn notify_open_tickets(
+    db: Object,
+    outbox: Outbox,
+    config: Config,
+) -> Result<Vec<Ticket>, ErrResponse> {
     let open_tickets: Vec<Ticket> = db
-        .interact(|conn| {
-            tickets::table
-                .filter(tickets::is_closed.eq(false))
-                .load(conn)
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                let open_tickets: Vec<Ticket> = tickets::table
+                    .filter(tickets::status.eq_any(TicketStatus::OPEN))
+                    .order(tickets::time.asc())
+                    .load(conn)?;
+                if !open_tickets.is_empty() {
+                    outbox::queue_template(
+                        conn,
+                        &open_tickets,
+                        "open_tickets",
+                        &config.ticket_mail_to,
+                    )?;
+                }
+                Ok::<_, diesel::result::Error>(open_tickets)
+            })
         })
         .await??;
     if !open_tickets.is_empty() {
-        match template(&open_tickets, "open_tickets") {
-            Ok(r) => mailer.send_mail_to(r.0, r.1, config.ticket_mail_to),
-            Err(e) => println!("Handlebars error : {}", e),
-        };
-    };
-    Ok(Json(open_tickets))
-}
-
-async fn export(Db(db): Db, UserToken: UserToken) -> Result<impl IntoResponse, ErrResponse> {
-    let tickets_with_comments: Vec<(OutTicket, Asset)> = db
-        .interact(|conn| -> Result<Vec<(OutTicket, Asset)>, ErrResponse> {
-            let tickets_with_assets = tickets::table
-                .inner_join(assets::table)
-                .order(tickets::time.desc())
-                .select((Ticket::as_select(), Asset::as_select()))
-                .load::<(Ticket, Asset)>(conn)
-                .map_err(|_| ErrResponse::S404("No tickets found"))?;
-
-            let tickets = &tickets_with_assets
-                .iter()
-                .map(|e| &e.0)
-                .collect::<Vec<&Ticket>>();
-
-            let comments = Comment::belonging_to(tickets)
-                .select(Comment::as_select())
-                .order(comments::time.desc())
-                .load(conn)
-                .map_err(|_| ErrResponse::S404("No comments found"))?;
-
-            let result = comments
-                .grouped_by(tickets)
-                .into_iter()
-                .zip(tickets_with_assets)
-                .map(|(cmts, t)| {
-                    (
-                        OutTicket {
-                            ticket: t.0,
-                            comments: cmts,
-                        },
-                        t.1,
-                    )
-                })
-                .collect::<Vec<(OutTicket, Asset)>>();
-            Ok(result)
-        })
-        .await??;
-
-    match template(&tickets_with_comments, "tickets_with_comments") {
-        Ok(r) => Ok(Html(r.1)),
-        Err(_) => Err(ErrResponse::S500("could not export data")),
+        outbox.notify();
     }
+    Ok(open_tickets)
 }
 
-async fn read(Db(db): Db, Path(id): Path<i32>, UserToken: UserToken) -> impl IntoResponse {
-    match ticket_with_comments(db, id).await {
-        Ok(e) => Ok(Json(e)),
+async fn read(
+    Db(db): Db,
+    Path(id): Path<i32>,
+    _: UserToken,
+    State(config): State<Config>,
+) -> impl IntoResponse {
+    match db
+        .interact(move |conn| ticket_with_comments(conn, id, &config.sla))
+        .await?
+    {
+        Ok(e) => Ok(([(header::ETAG, etag(e.ticket.version))], Json(e))),
         Err(e) => Err(e),
     }
 }
 
-async fn ticket_with_comments(db: Object, id: i32) -> Result<OutTicket, ErrResponse> {
-    db.interact(move |conn| {
-        let t: Result<Ticket, diesel::result::Error> =
-            tickets::table.filter(tickets::id.eq(id)).first(conn);
-        let t = match t {
-            Ok(r) => r,
-            Err(..) => {
-                return Err(ErrResponse::S404("could not get ticket"));
-            }
-        };
-        let cs = <Comment>::belonging_to(&t)
-            .order(comments::time.desc())
-            .load(conn);
-        let cs = match cs {
-            Ok(r) => r,
-            Err(..) => {
-                return Err(ErrResponse::S404("could not get comments for ticket"));
-            }
-        };
-        Ok(OutTicket {
-            ticket: t,
-            comments: cs,
-        })
-    })
-    .await?
+fn ticket_with_comments(
+    conn: &mut SqliteConnection,
+    id: i32,
+    sla: &SlaTargets,
+) -> Result<OutTicket, ErrResponse> {
+    let t: Result<Ticket, diesel::result::Error> =
+        tickets::table.filter(tickets::id.eq(id)).first(conn);
+    let t = match t {
+        Ok(r) => r,
+        Err(..) => {
+            return Err(ErrResponse::S404("could not get ticket"));
+        }
+    };
+    let cs = <Comment>::belonging_to(&t)
+        .order(comments::time.desc())
+        .load(conn);
+    let cs = match cs {
+        Ok(r) => r,
+        Err(..) => {
+            return Err(ErrResponse::S404("could not get comments for ticket"));
+        }
+    };
+    let asset_path = asset_path(conn, t.asset_id)?;
+    Ok(OutTicket::new(t, cs, asset_path, sla))
 }
 
 async fn delete(
     Path(id): Path<i32>,
-    AdminToken: AdminToken,
+    AdminToken(user): AdminToken,
     Db(db): Db,
+    State(config): State<Config>,
 ) -> Result<(), ErrResponse> {
-    if db
-        .interact(move |conn| {
-            diesel::delete(tickets::table)
-                .filter(tickets::id.eq(id))
-                .execute(conn)
-        })
-        .await??
-        == 1
-    {
-        if let Err(e) = fs::remove_file(photo_filename(id)) {
-            println!("error removing photo with id {}: {}", id, e);
-        }
+    let (deleted, attachments) = db
+        .interact(move |conn| conn.immediate_transaction(|conn| delete_tickets(conn, &user, &[id])))
+        .await??;
+    remove_files(&config, &attachments);
+    remove_photos(&config, &deleted);
+    if deleted.len() == 1 {
         Ok(())
     } else {
         Err(ErrResponse::S404("object not found in database"))
     }
 }
 
-async fn destroy(AdminToken: AdminToken, Db(db): Db) -> Result<(), ErrResponse> {
-    db.interact(move |conn| diesel::delete(tickets::table).execute(conn))
+async fn destroy(
+    AdminToken(user): AdminToken,
+    Db(db): Db,
+    State(config): State<Config>,
+) -> Result<(), ErrResponse> {
+    let (deleted, attachments) = db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                let ids: Vec<i32> = tickets::table.select(tickets::id).load(conn)?;
+                delete_tickets(conn, &user, &ids)
+            })
+        })
         .await??;
+    remove_files(&config, &attachments);
+    remove_photos(&config, &deleted);
     Ok(())
 }
 
+/// Deletes tickets along with their comments and attachments, returning the ids of the deleted tickets
+/// and of their attachments, whose files are to be removed once the transaction is committed
+pub(crate) fn delete_tickets(
+    conn: &mut SqliteConnection,
+    actor: &User,
+    ids: &[i32],
+) -> Result<(Vec<i32>, Vec<i32>), diesel::result::Error> {
+    let attachments = delete_ticket_attachments(conn, actor, ids)?;
+    delete_ticket_comments(conn, actor, ids)?;
+    let deleted: Vec<Ticket> = diesel::delete(tickets::table)
+        .filter(tickets::id.eq_any(ids))
+        .returning(Ticket::as_returning())
+        .get_results(conn)?;
+    for ticket in &deleted {
+        Event {
+            entity: EventEntity::Ticket,
+            entity_id: ticket.id,
+            ticket_id: Some(ticket.id),
+            action: EventAction::Delete,
+            old: Some(ticket),
+            new: None,
+        }
+        .record(conn, actor)?;
+    }
+    Ok((deleted.iter().map(|t| t.id).collect(), attachments))
+}
+
+/// Removes the photos of deleted tickets, most tickets having none
+pub(crate) fn remove_photos(config: &Config, ids: &[i32]) {
+    for id in ids {
+        match fs::remove_file(photo_filename(config, *id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                println!("error removing photo with id {}: {}", id, e);
+            }
+            _ => {}
+        }
+    }
+}
+
 async fn upload(
-    UserToken: UserToken,
+    _: UserToken,
     Path(id): Path<i32>,
+    State(config): State<Config>,
     image: Bytes,
 ) -> Result<String, ErrResponse> {
-    fs::create_dir_all(PHOTOS_PATH)
+    fs::create_dir_all(config.photos_path())
         .map_err(|_| ErrResponse::S500("could not create images directory"))?;
-    let filename = photo_filename(id);
+    let filename = photo_filename(&config, id);
     match spawn_blocking(move || image::load_from_memory(&image)).await {
         Ok(r) => match r {
             Ok(r) => {
-                match r
-                    .resize(
-                        std::cmp::min(1280, r.dimensions().0),
-                        std::cmp::min(1280, r.dimensions().1),
-                        Lanczos3,
-                    )
-                    .save_with_format(
-                        &filename,
-                        image::ImageFormat::from_extension("jpg").unwrap(),
-                    ) {
+                match shrink(&r).save_with_format(
+                    &filename,
+                    image::ImageFormat::from_extension("jpg").unwrap(),
+                ) {
                     Ok(_) => Ok(filename),
                     Err(_) => Err(ErrResponse::S500("error saving image")),
                 }
@@ -353,9 +911,10 @@ async fn upload(
 
 async fn retrieve(
     Path(id): Path<i32>,
-    UserToken: UserToken,
+    _: UserToken,
+    State(config): State<Config>,
 ) -> Result<impl IntoResponse, ErrResponse> {
-    let f = match File::open(photo_filename(id)).await {
+    let f = match File::open(photo_filename(&config, id)).await {
         Ok(f) => f,
         Err(..) => {
             return Err(ErrResponse::S404("no image available"));
@@ -365,15 +924,28 @@ async fn retrieve(
     Ok(Body::from_stream(stream))
 }
 
-async fn delete_photo(Path(id): Path<i32>, UserToken: UserToken) -> impl IntoResponse {
-    match spawn_blocking(move || fs::remove_file(photo_filename(id))).await {
+async fn delete_photo(
+    Path(id): Path<i32>,
+    _: UserToken,
+    State(config): State<Config>,
+) -> impl IntoResponse {
+    match spawn_blocking(move || fs::remove_file(photo_filename(&config, id))).await {
         Ok(..) => Ok("File deleted".to_string()),
-        Err(..) => Err((StatusCode::NOT_FOUND, "no image available")),
+        Err(..) => Err(ErrResponse::S404("no image available")),
     }
 }
 
-fn photo_filename(id: i32) -> String {
-    format!("{path}/{id}.jpg", path = PHOTOS_PATH, id = id)
+/// Resizes an image to fit within 1280x1280 pixels
+pub(crate) fn shrink(image: &DynamicImage) -> DynamicImage {
+    image.resize(
+        std::cmp::min(1280, image.dimensions().0),
+        std::cmp::min(1280, image.dimensions().1),
+        Lanczos3,
+    )
+}
+
+fn photo_filename(config: &Config, id: i32) -> String {
+    format!("{path}/{id}.jpg", path = config.photos_path(), id = id)
 }
 
 pub fn template<T>(o: T, template: &str) -> Result<(String, String), RenderError>
@@ -397,6 +969,9 @@ where
         let t: Result<Ticket, serde_json::Error> = serde_json::from_value(value);
         let time = if let Ok(t) = t {
             Some(t.time.format("%Y-%m-%d").to_string())
+        } else if let Ok(t) = serde_json::from_value::<chrono::NaiveDateTime>(param.value().clone())
+        {
+            Some(t.format("%Y-%m-%d %H:%M").to_string())
         } else {
             let t: Result<Comment, serde_json::Error> =
                 serde_json::from_value(param.value().clone());
@@ -409,13 +984,30 @@ where
         if let Some(time) = time {
             out.write(&time)?;
         } else {
-            out.write("[NOT A TICKET NOR A COMMENT: CANNOT GET TIME]")?;
+            out.write("[NOT A TICKET, A COMMENT NOR A TIME: CANNOT GET TIME]")?;
         }
         Ok(())
     }
 
     handlebars.register_helper("formattime", Box::new(formattime));
 
+    fn formatstatus(
+        h: &Helper,
+        _: &Handlebars,
+        _: &Context,
+        _rc: &mut RenderContext,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let param = h.param(0).unwrap();
+        match serde_json::from_value::<TicketStatus>(param.value().clone()) {
+            Ok(status) => out.write(status.label())?,
+            Err(_) => out.write("[NOT A TICKET STATUS]")?,
+        }
+        Ok(())
+    }
+
+    handlebars.register_helper("formatstatus", Box::new(formatstatus));
+
     match handlebars.render(format!("{}{}", template, "_body").as_str(), &o) {
         Ok(body) => {
             handlebars.register_escape_fn(handlebars::no_escape);
@@ -0,0 +1,341 @@
+use axum::{
+    Json, Router,
+    extract::Path,
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post},
+};
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    prelude::*,
+    serialize::{self, IsNull, Output, ToSql},
+    sql_types::Text,
+    sqlite::{Sqlite, SqliteValue},
+};
+use serde::{Deserialize, Serialize};
+use serde_trim::string_trim;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::{AdminToken, AppState, Config, Db, UserToken, random_string},
+    errors::ErrResponse,
+    validation::{NAME_LENGTH, ValidJson, Validate, Validator},
+};
+
+use super::schema::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    User,
+}
+
+impl Role {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::User => "user",
+        }
+    }
+
+    /// Prefix of the tokens generated for this role, used by the frontend to adapt its interface
+    fn token_prefix(&self) -> &'static str {
+        match self {
+            Role::Admin => "$ADMIN$",
+            Role::User => "$USER$",
+        }
+    }
+}
+
+impl ToSql<Text, Sqlite> for Role {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        out.set_value(self.as_str());
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Sqlite> for Role {
+    fn from_sql(bytes: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Sqlite>>::from_sql(bytes)?.as_str() {
+            "admin" => Ok(Role::Admin),
+            "user" => Ok(Role::User),
+            r => Err(format!("unknown role: {}", r).into()),
+        }
+    }
+}
+
+#[derive(
+    Identifiable,
+    Debug,
+    Clone,
+    Deserialize,
+    Serialize,
+    Queryable,
+    AsChangeset,
+    PartialEq,
+    Selectable,
+)]
+#[diesel(table_name = users)]
+pub struct User {
+    pub id: i32,
+    #[serde(deserialize_with = "string_trim")]
+    pub name: String,
+    pub role: Role,
+    pub enabled: bool,
+    /// Address the mails about the tickets assigned to the user are sent to
+    #[serde(default, deserialize_with = "string_trim")]
+    pub mail: String,
+    /// Whether the user is one of the built-in `admin` and `user` accounts, whose name and role the clients cannot change
+    #[serde(default)]
+    #[diesel(skip_update)]
+    pub builtin: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+pub struct InUser {
+    #[serde(deserialize_with = "string_trim")]
+    pub name: String,
+    pub role: Role,
+    #[serde(default, deserialize_with = "string_trim")]
+    pub mail: String,
+}
+
+impl PartialEq<InUser> for User {
+    fn eq(&self, other: &InUser) -> bool {
+        self.name == other.name && self.role == other.role && self.mail == other.mail
+    }
+}
+
+impl User {
+    /// Name to record as the creator of tickets and comments, none for the built-in accounts,
+    /// whose tokens are shared and which let the clients name the creator
+    pub fn author(&self) -> Option<&str> {
+        (!self.builtin).then_some(self.name.as_str())
+    }
+}
+
+impl Validate for InUser {
+    fn check(&self, v: &mut Validator) {
+        v.required("name", &self.name)
+            .max_length("name", &self.name, NAME_LENGTH)
+            .mail("mail", &self.mail);
+    }
+}
+
+impl Validate for User {
+    fn check(&self, v: &mut Validator) {
+        v.required("name", &self.name)
+            .max_length("name", &self.name, NAME_LENGTH)
+            .mail("mail", &self.mail);
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = users)]
+struct NewUser {
+    name: String,
+    role: Role,
+    token_hash: String,
+    enabled: bool,
+    mail: String,
+    builtin: bool,
+}
+
+/// A user along with its clear token, which is only ever returned on creation or regeneration
+#[derive(Deserialize, Serialize, Debug)]
+pub struct UserWithToken {
+    #[serde(flatten)]
+    pub user: User,
+    pub token: String,
+}
+
+pub fn build_users_router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_all).post(create))
+        .route("/me", get(me))
+        .route("/{id}", get(read).patch(update).delete(delete))
+        .route("/{id}/token", post(regenerate_token))
+}
+
+pub(crate) fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn generate_token(role: Role) -> String {
+    format!("{}{}", role.token_prefix(), random_string())
+}
+
+/// Creates the built-in `admin` and `user` accounts, or refreshes their tokens from the configuration,
+/// so that a fresh install can always be administrated; the accounts are found by their `builtin` flag and role,
+/// whatever their name, and whether they are enabled is left as stored
+pub(crate) fn sync_builtin_users(
+    conn: &mut SqliteConnection,
+    config: &Config,
+) -> Result<(), diesel::result::Error> {
+    for (name, role, token) in [
+        ("admin", Role::Admin, &config.admin_token),
+        ("user", Role::User, &config.user_token),
+    ] {
+        let token_hash = hash_token(token);
+        let updated = diesel::update(
+            users::table
+                .filter(users::builtin.eq(true))
+                .filter(users::role.eq(role)),
+        )
+        .set(users::token_hash.eq(&token_hash))
+        .execute(conn)?;
+        if updated == 0 {
+            diesel::insert_into(users::table)
+                .values(NewUser {
+                    name: name.to_string(),
+                    role,
+                    token_hash,
+                    enabled: true,
+                    mail: String::new(),
+                    builtin: true,
+                })
+                .execute(conn)?;
+        }
+    }
+    Ok(())
+}
+
+async fn create(
+    _: AdminToken,
+    Db(db): Db,
+    ValidJson(user): ValidJson<InUser>,
+) -> Result<(StatusCode, Json<UserWithToken>), ErrResponse> {
+    let token = generate_token(user.role);
+    let new_user = NewUser {
+        name: user.name,
+        role: user.role,
+        token_hash: hash_token(&token),
+        enabled: true,
+        mail: user.mail,
+        builtin: false,
+    };
+    let user = db
+        .interact(|conn| {
+            diesel::insert_into(users::table)
+                .values(new_user)
+                .returning(User::as_returning())
+                .get_result(conn)
+        })
+        .await??;
+    Ok((StatusCode::CREATED, Json(UserWithToken { user, token })))
+}
+
+async fn update(
+    Path(id): Path<i32>,
+    AdminToken(caller): AdminToken,
+    Db(db): Db,
+    ValidJson(user): ValidJson<User>,
+) -> Result<StatusCode, ErrResponse> {
+    if id == caller.id && (!user.enabled || user.role != Role::Admin) {
+        return Err(ErrResponse::S403(
+            "an admin cannot disable or demote itself",
+        ));
+    }
+    db.interact(move |conn| {
+        conn.immediate_transaction(|conn| {
+            let stored: User = users::table
+                .filter(users::id.eq(id))
+                .select(User::as_select())
+                .first(conn)
+                .optional()?
+                .ok_or(ErrResponse::S404("object not found in database"))?;
+            if stored.builtin && (stored.name != user.name || stored.role != user.role) {
+                return Err(ErrResponse::S409(
+                    "the name and role of a built-in account cannot be changed",
+                ));
+            }
+            diesel::update(users::table.filter(users::id.eq(id)))
+                .set(user)
+                .execute(conn)?;
+            Ok(())
+        })
+    })
+    .await??;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn regenerate_token(
+    Path(id): Path<i32>,
+    _: AdminToken,
+    Db(db): Db,
+) -> Result<Json<UserWithToken>, ErrResponse> {
+    let user = db
+        .interact(move |conn| {
+            let user: User = users::table
+                .filter(users::id.eq(id))
+                .select(User::as_select())
+                .first(conn)?;
+            let token = generate_token(user.role);
+            diesel::update(users::table.filter(users::id.eq(id)))
+                .set(users::token_hash.eq(hash_token(&token)))
+                .execute(conn)?;
+            Ok::<_, diesel::result::Error>(UserWithToken { user, token })
+        })
+        .await??;
+    Ok(Json(user))
+}
+
+async fn list_all(_: AdminToken, Db(db): Db) -> Result<impl IntoResponse, ErrResponse> {
+    let all_users: Vec<User> = db
+        .interact(|conn| {
+            users::table
+                .order(users::name)
+                .select(User::as_select())
+                .load(conn)
+        })
+        .await??;
+    Ok(Json(all_users))
+}
+
+async fn read(Path(id): Path<i32>, _: AdminToken, Db(db): Db) -> Result<Json<User>, ErrResponse> {
+    let user: User = db
+        .interact(move |conn| {
+            users::table
+                .filter(users::id.eq(id))
+                .select(User::as_select())
+                .first(conn)
+        })
+        .await??;
+    Ok(Json(user))
+}
+
+async fn me(UserToken(user): UserToken) -> Json<User> {
+    Json(user)
+}
+
+async fn delete(
+    Path(id): Path<i32>,
+    AdminToken(caller): AdminToken,
+    Db(db): Db,
+) -> Result<(), ErrResponse> {
+    if id == caller.id {
+        return Err(ErrResponse::S403("an admin cannot delete itself"));
+    }
+    if db
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                // Release the tickets assigned to the user
+                diesel::update(tickets::table.filter(tickets::assignee_id.eq(id)))
+                    .set(tickets::assignee_id.eq(None::<i32>))
+                    .execute(conn)?;
+                diesel::delete(users::table)
+                    .filter(users::id.eq(id))
+                    .execute(conn)
+            })
+        })
+        .await??
+        == 1
+    {
+        Ok(())
+    } else {
+        Err(ErrResponse::S404("object not found in database"))
+    }
+}
@@ -0,0 +1,163 @@
+use axum::{Json, extract::State};
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    serialize::{self, IsNull, Output, ToSql},
+    sql_types::Text,
+    sqlite::{Sqlite, SqliteValue},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, UserToken};
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    Deserialize,
+    Serialize,
+    AsExpression,
+    FromSqlRow,
+)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum TicketStatus {
+    #[default]
+    New,
+    Acknowledged,
+    InProgress,
+    WaitingOnRequester,
+    Resolved,
+    Closed,
+}
+
+impl TicketStatus {
+    pub const ALL: [TicketStatus; 6] = [
+        TicketStatus::New,
+        TicketStatus::Acknowledged,
+        TicketStatus::InProgress,
+        TicketStatus::WaitingOnRequester,
+        TicketStatus::Resolved,
+        TicketStatus::Closed,
+    ];
+
+    /// Statuses of the tickets that still require some work from the desk
+    pub const OPEN: [TicketStatus; 4] = [
+        TicketStatus::New,
+        TicketStatus::Acknowledged,
+        TicketStatus::InProgress,
+        TicketStatus::WaitingOnRequester,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TicketStatus::New => "new",
+            TicketStatus::Acknowledged => "acknowledged",
+            TicketStatus::InProgress => "in_progress",
+            TicketStatus::WaitingOnRequester => "waiting_on_requester",
+            TicketStatus::Resolved => "resolved",
+            TicketStatus::Closed => "closed",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TicketStatus::New => "New",
+            TicketStatus::Acknowledged => "Acknowledged",
+            TicketStatus::InProgress => "In progress",
+            TicketStatus::WaitingOnRequester => "Waiting on requester",
+            TicketStatus::Resolved => "Resolved",
+            TicketStatus::Closed => "Closed",
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        TicketStatus::OPEN.contains(self)
+    }
+}
+
+impl ToSql<Text, Sqlite> for TicketStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        out.set_value(self.as_str());
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Sqlite> for TicketStatus {
+    fn from_sql(bytes: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        let value = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+        TicketStatus::ALL
+            .into_iter()
+            .find(|s| s.as_str() == value)
+            .ok_or_else(|| format!("unknown ticket status: {}", value).into())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Transition {
+    pub from: TicketStatus,
+    pub to: TicketStatus,
+    /// Name of the mail template sent to the ticket creator when the transition occurs
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Workflow {
+    pub transitions: Vec<Transition>,
+}
+
+impl Default for Workflow {
+    fn default() -> Self {
+        use TicketStatus::*;
+        let mut transitions = Vec::new();
+        let mut allow = |from: &[TicketStatus], to: TicketStatus, template: Option<&str>| {
+            for &from in from {
+                transitions.push(Transition {
+                    from,
+                    to,
+                    template: template.map(String::from),
+                });
+            }
+        };
+        allow(&[New], Acknowledged, None);
+        allow(
+            &[New, Acknowledged, WaitingOnRequester, Resolved],
+            InProgress,
+            None,
+        );
+        allow(&[New, Acknowledged, InProgress], WaitingOnRequester, None);
+        allow(&TicketStatus::OPEN, Resolved, Some("resolved_ticket"));
+        allow(
+            &[New, Acknowledged, InProgress, WaitingOnRequester, Resolved],
+            Closed,
+            Some("closed_ticket"),
+        );
+        allow(&[Closed], New, None);
+        Workflow { transitions }
+    }
+}
+
+impl Workflow {
+    /// Reads the workflow from a JSON array of transitions, such as `[{"from": "new", "to": "closed", "template": "closed_ticket"}]`
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        Ok(Workflow {
+            transitions: serde_json::from_str(json)?,
+        })
+    }
+
+    /// Gets the transition between two statuses, if allowed
+    pub fn transition(&self, from: TicketStatus, to: TicketStatus) -> Option<&Transition> {
+        self.transitions
+            .iter()
+            .find(|t| t.from == from && t.to == to)
+    }
+}
+
+pub(crate) async fn get_workflow(_: UserToken, State(config): State<Config>) -> Json<Workflow> {
+    Json(config.workflow)
+}
@@ -0,0 +1,316 @@
+use std::sync::Arc;
+
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    routing::{get, post},
+};
+use chrono::{Local, NaiveDateTime, TimeDelta};
+use deadpool_diesel::sqlite::Object;
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    prelude::*,
+    serialize::{self, IsNull, Output, ToSql},
+    sql_types::Text,
+    sqlite::{Sqlite, SqliteValue},
+};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Notify, task::spawn_blocking};
+
+use crate::{
+    config::{AdminToken, AppState, Config, Db},
+    errors::ErrResponse,
+    mail::Mailer,
+    models::{schema::outbox, ticket::template},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+#[serde(rename_all = "snake_case")]
+pub enum MailStatus {
+    Pending,
+    Sent,
+    Failed,
+}
+
+impl MailStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MailStatus::Pending => "pending",
+            MailStatus::Sent => "sent",
+            MailStatus::Failed => "failed",
+        }
+    }
+}
+
+impl ToSql<Text, Sqlite> for MailStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+        out.set_value(self.as_str());
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Sqlite> for MailStatus {
+    fn from_sql(bytes: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Sqlite>>::from_sql(bytes)?.as_str() {
+            "pending" => Ok(MailStatus::Pending),
+            "sent" => Ok(MailStatus::Sent),
+            "failed" => Ok(MailStatus::Failed),
+            s => Err(format!("unknown mail status: {}", s).into()),
+        }
+    }
+}
+
+#[derive(Identifiable, Debug, Clone, Deserialize, Serialize, Queryable, Selectable)]
+#[diesel(table_name = outbox)]
+pub struct OutboxMail {
+    pub id: i32,
+    pub recipients: String,
+    pub subject: String,
+    pub body: String,
+    pub status: MailStatus,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub next_attempt_at: NaiveDateTime,
+    pub sent_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = outbox)]
+struct NewOutboxMail {
+    recipients: String,
+    subject: String,
+    body: String,
+    status: MailStatus,
+    created_at: NaiveDateTime,
+    next_attempt_at: NaiveDateTime,
+}
+
+/// Outgoing mails, persisted in the database and delivered in the background
+#[derive(Clone)]
+pub struct Outbox {
+    pub(crate) notify: Arc<Notify>,
+}
+
+impl Outbox {
+    pub(crate) fn new() -> Self {
+        Outbox {
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Wakes the worker up, once the change queuing mails is committed, so that they are delivered at once
+    pub fn notify(&self) {
+        self.notify.notify_one();
+    }
+}
+
+/// Queues a mail to a comma separated list of recipients, mails without recipients being dropped;
+/// the mail is inserted with the connection of the change it notifies, so that both are committed together
+pub fn queue(
+    conn: &mut SqliteConnection,
+    subject: String,
+    body: String,
+    to: &str,
+) -> QueryResult<()> {
+    if to.trim().is_empty() {
+        return Ok(());
+    }
+    let now = Local::now().naive_local();
+    diesel::insert_into(outbox::table)
+        .values(NewOutboxMail {
+            recipients: to.to_string(),
+            subject,
+            body,
+            status: MailStatus::Pending,
+            created_at: now,
+            next_attempt_at: now,
+        })
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Renders a mail template and queues the result, a template error being logged as it must not fail the change
+pub fn queue_template<T>(
+    conn: &mut SqliteConnection,
+    o: T,
+    template_name: &str,
+    to: &str,
+) -> QueryResult<()>
+where
+    T: Serialize,
+{
+    match template(o, template_name) {
+        Ok((subject, body)) => queue(conn, subject, body, to),
+        Err(e) => {
+            tracing::error!("Could not render mail template {}: {}", template_name, e);
+            Ok(())
+        }
+    }
+}
+
+/// Number of mails delivered at once
+const DELIVERY_BATCH: usize = 50;
+
+/// Delivers a batch of due pending mails, scheduling a new attempt with an exponential backoff on failure;
+/// returns whether more mails may be due, which is only worth trying if some of the batch could be sent
+pub(crate) async fn deliver_due_mails(
+    db: &Object,
+    mailer: &Mailer,
+    config: &Config,
+) -> Result<bool, ErrResponse> {
+    let now = Local::now().naive_local();
+    let due_mails: Vec<OutboxMail> = db
+        .interact(move |conn| {
+            outbox::table
+                .filter(outbox::status.eq(MailStatus::Pending))
+                .filter(outbox::next_attempt_at.le(now))
+                .order(outbox::next_attempt_at.asc())
+                .limit(DELIVERY_BATCH as i64)
+                .load(conn)
+        })
+        .await??;
+    let full = due_mails.len() == DELIVERY_BATCH;
+    let mut sent = 0;
+    for mail in due_mails {
+        let mut m = mailer.clone();
+        let (subject, body, to) = (mail.subject, mail.body, mail.recipients.clone());
+        let result = spawn_blocking(move || m.send_mail_to(subject, body, to))
+            .await
+            .unwrap_or_else(|e| Err(format!("mailer panicked: {}", e)));
+        let attempts = mail.attempts + 1;
+        let now = Local::now().naive_local();
+        match result {
+            Ok(()) => {
+                tracing::info!("Mail {} sent to {}", mail.id, mail.recipients);
+                sent += 1;
+                db.interact(move |conn| {
+                    diesel::update(outbox::table.find(mail.id))
+                        .set((
+                            outbox::status.eq(MailStatus::Sent),
+                            outbox::attempts.eq(attempts),
+                            outbox::sent_at.eq(now),
+                        ))
+                        .execute(conn)
+                })
+                .await??;
+            }
+            Err(error) => {
+                let status = if attempts >= config.mail_max_attempts {
+                    tracing::error!(
+                        "Mail {} to {} failed permanently: {}",
+                        mail.id,
+                        mail.recipients,
+                        error
+                    );
+                    MailStatus::Failed
+                } else {
+                    tracing::warn!(
+                        "Mail {} to {} failed, will be retried: {}",
+                        mail.id,
+                        mail.recipients,
+                        error
+                    );
+                    MailStatus::Pending
+                };
+                let delay = config
+                    .mail_retry_seconds
+                    .saturating_mul(1 << (attempts - 1).min(16))
+                    .min(24 * 3600);
+                let next_attempt_at = now + TimeDelta::seconds(delay);
+                db.interact(move |conn| {
+                    diesel::update(outbox::table.find(mail.id))
+                        .set((
+                            outbox::status.eq(status),
+                            outbox::attempts.eq(attempts),
+                            outbox::last_error.eq(error),
+                            outbox::next_attempt_at.eq(next_attempt_at),
+                        ))
+                        .execute(conn)
+                })
+                .await??;
+            }
+        }
+    }
+    Ok(full && sent > 0)
+}
+
+#[derive(Deserialize)]
+pub struct OutboxFilter {
+    pub status: Option<MailStatus>,
+}
+
+pub fn build_outbox_router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_all))
+        .route("/retry", post(retry_failed))
+        .route("/{id}/retry", post(retry))
+}
+
+async fn list_all(
+    _: AdminToken,
+    Db(db): Db,
+    Query(filter): Query<OutboxFilter>,
+) -> Result<Json<Vec<OutboxMail>>, ErrResponse> {
+    let mails = db
+        .interact(move |conn| {
+            let mut query = outbox::table.into_boxed();
+            if let Some(status) = filter.status {
+                query = query.filter(outbox::status.eq(status));
+            }
+            query.order(outbox::id.desc()).load(conn)
+        })
+        .await??;
+    Ok(Json(mails))
+}
+
+/// Makes failed mails pending again, for a new series of attempts
+fn reset_failed(
+    conn: &mut SqliteConnection,
+    id: Option<i32>,
+) -> Result<usize, diesel::result::Error> {
+    let mut query = diesel::update(outbox::table)
+        .filter(outbox::status.eq(MailStatus::Failed))
+        .into_boxed();
+    if let Some(id) = id {
+        query = query.filter(outbox::id.eq(id));
+    }
+    query
+        .set((
+            outbox::status.eq(MailStatus::Pending),
+            outbox::attempts.eq(0),
+            outbox::next_attempt_at.eq(Local::now().naive_local()),
+        ))
+        .execute(conn)
+}
+
+async fn retry(
+    Path(id): Path<i32>,
+    _: AdminToken,
+    Db(db): Db,
+    State(outbox): State<Outbox>,
+) -> Result<StatusCode, ErrResponse> {
+    if db
+        .interact(move |conn| reset_failed(conn, Some(id)))
+        .await??
+        == 1
+    {
+        outbox.notify();
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ErrResponse::S404("failed mail not found in database"))
+    }
+}
+
+async fn retry_failed(
+    _: AdminToken,
+    Db(db): Db,
+    State(outbox): State<Outbox>,
+) -> Result<Json<usize>, ErrResponse> {
+    let count = db.interact(move |conn| reset_failed(conn, None)).await??;
+    outbox.notify();
+    Ok(Json(count))
+}
@@ -0,0 +1,197 @@
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use axum::extract::FromRef;
+use chrono::Local;
+use cron::{Schedule, error::ErrorKind};
+use deadpool_diesel::{
+    Pool,
+    sqlite::{Manager, Object},
+};
+use tokio::sync::Notify;
+
+use crate::{
+    config::{AppState, Config},
+    errors::ErrResponse,
+    mail::Mailer,
+    models::{maintenance::open_due_tickets, sla::notify_sla_alerts, ticket::notify_open_tickets},
+    outbox::{Outbox, deliver_due_mails},
+};
+
+/// Parses a cron expression with seconds (`sec min hour day month weekday [year]`),
+/// standard five fields expressions (`min hour day month weekday`) being accepted as well
+pub fn parse_schedule(expression: &str) -> Result<Schedule, cron::error::Error> {
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    if let [minute, hour, day, month, weekday] = fields[..] {
+        Schedule::from_str(&format!(
+            "0 {minute} {hour} {day} {month} {}",
+            standard_weekdays(weekday)?
+        ))
+    } else {
+        Schedule::from_str(expression)
+    }
+}
+
+/// Translates the weekdays of a standard cron expression, numbered from 0 (or 7) for Sunday to 6 for Saturday,
+/// to the numbering of the cron crate, from 1 for Sunday to 7 for Saturday
+fn standard_weekdays(field: &str) -> Result<String, cron::error::Error> {
+    let invalid = || {
+        cron::error::Error::from(ErrorKind::Expression(format!(
+            "invalid weekday `{field}`, expected numbers from 0 to 7 or names such as `Mon-Fri`"
+        )))
+    };
+    let mut elements = Vec::new();
+    for element in field.split(',') {
+        // The names and the steps over every day do not depend on the numbering
+        if !element.contains(|c: char| c.is_ascii_digit()) || element.starts_with("*/") {
+            elements.push(element.to_string());
+            continue;
+        }
+        let (range, step) = match element.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u8>().map_err(|_| invalid())?),
+            None => (element, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (start, end),
+            // A start with a step runs until the end of the week
+            None if element.contains('/') => (range, "6"),
+            None => (range, range),
+        };
+        let (start, end) = (
+            start.parse::<u8>().map_err(|_| invalid())?,
+            end.parse::<u8>().map_err(|_| invalid())?,
+        );
+        if end > 7 || start > end || step == 0 {
+            return Err(invalid());
+        }
+        // The ranges are expanded, as a range ending on Sunday (7) would wrap around the week
+        for day in (start..=end).step_by(step.into()) {
+            elements.push((day % 7 + 1).to_string());
+        }
+    }
+    Ok(elements.join(","))
+}
+
+/// Runs a background job with a connection to the database, logging its failure
+async fn run_job<F, Fut>(pool: &Pool<Manager>, name: &str, job: &F)
+where
+    F: Fn(Object) -> Fut,
+    Fut: Future<Output = Result<(), ErrResponse>>,
+{
+    match pool.get().await {
+        Ok(db) => {
+            if let Err(e) = job(db).await {
+                tracing::error!("{} failed: {}", name, e);
+            }
+        }
+        Err(e) => tracing::error!("{}: could not get database connection: {}", name, e),
+    }
+}
+
+/// Runs a job in the background every period, and at once whenever `wake` is notified if given
+fn spawn_periodic<F, Fut>(
+    state: &AppState,
+    name: &'static str,
+    period: Duration,
+    wake: Option<Arc<Notify>>,
+    job: F,
+) where
+    F: Fn(Object) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), ErrResponse>> + Send,
+{
+    let pool = Pool::<Manager>::from_ref(state);
+    tokio::spawn(async move {
+        loop {
+            run_job(&pool, name, &job).await;
+            match &wake {
+                Some(wake) => tokio::select! {
+                    _ = wake.notified() => (),
+                    _ = tokio::time::sleep(period) => (),
+                },
+                None => tokio::time::sleep(period).await,
+            }
+        }
+    });
+}
+
+/// Delivers the queued mails in the background, as soon as they are queued or due for a retry
+pub fn spawn_outbox_worker(state: &AppState) {
+    let config = Config::from_ref(state);
+    let mailer = Mailer::from_ref(state);
+    let wake = Outbox::from_ref(state).notify;
+    spawn_periodic(
+        state,
+        "Outbox delivery",
+        Duration::from_secs(10),
+        Some(wake),
+        move |db| {
+            let (mailer, config) = (mailer.clone(), config.clone());
+            async move {
+                // Keep going while there may be more due mails, but not over mails that keep failing
+                while deliver_due_mails(&db, &mailer, &config).await? {}
+                Ok(())
+            }
+        },
+    );
+}
+
+/// Periodically checks the tickets targets in the background
+pub fn spawn_sla_checker(state: &AppState) {
+    let config = Config::from_ref(state);
+    if config.sla_check_minutes == 0 {
+        return;
+    }
+    let outbox = Outbox::from_ref(state);
+    let period = Duration::from_secs(60 * config.sla_check_minutes);
+    spawn_periodic(state, "SLA check", period, None, move |db| {
+        let (outbox, config) = (outbox.clone(), config.clone());
+        async move {
+            let alerts = notify_sla_alerts(db, outbox, config).await?;
+            tracing::info!("SLA check: {} new alert(s)", alerts.len());
+            Ok(())
+        }
+    });
+}
+
+/// Periodically opens the tickets of the maintenance plans come due in the background
+pub fn spawn_maintenance_scheduler(state: &AppState) {
+    let config = Config::from_ref(state);
+    if config.maintenance_check_minutes == 0 {
+        return;
+    }
+    let outbox = Outbox::from_ref(state);
+    let period = Duration::from_secs(60 * config.maintenance_check_minutes);
+    spawn_periodic(state, "Maintenance check", period, None, move |db| {
+        let (outbox, config) = (outbox.clone(), config.clone());
+        async move {
+            let tickets = open_due_tickets(db, outbox, config).await?;
+            tracing::info!("Maintenance check: {} ticket(s) opened", tickets.len());
+            Ok(())
+        }
+    });
+}
+
+/// Sends the open tickets digest to the desk according to the configured schedule
+pub fn spawn_open_tickets_digest(state: &AppState) {
+    let config = Config::from_ref(state);
+    let Some(schedule) = config.open_tickets_schedule.clone() else {
+        return;
+    };
+    let pool = Pool::<Manager>::from_ref(state);
+    let outbox = Outbox::from_ref(state);
+    tracing::info!("Open tickets digest scheduled with `{}`", schedule.source());
+    let job = move |db| {
+        let (outbox, config) = (outbox.clone(), config.clone());
+        async move {
+            let tickets = notify_open_tickets(db, outbox, config).await?;
+            tracing::info!("Open tickets digest: {} open ticket(s) sent", tickets.len());
+            Ok(())
+        }
+    };
+    tokio::spawn(async move {
+        for next in schedule.upcoming_owned(Local) {
+            let delay = (next - Local::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(delay).await;
+            run_job(&pool, "Open tickets digest", &job).await;
+        }
+    });
+}
@@ -0,0 +1,145 @@
+use axum::{
+    Json,
+    extract::{FromRequest, Request, rejection::JsonRejection},
+};
+use lettre::Address;
+use serde::{
+    Deserialize,
+    de::{DeserializeOwned, Deserializer},
+};
+
+use crate::errors::{ErrResponse, FieldError};
+
+pub const TITLE_LENGTH: usize = 200;
+pub const NAME_LENGTH: usize = 100;
+pub const MAIL_LENGTH: usize = 254;
+pub const PHONE_LENGTH: usize = 30;
+pub const TEXT_LENGTH: usize = 10_000;
+
+/// Trims an optional string, keeping it when empty so that a patch can clear a field
+pub fn option_trim<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
+    Ok(Option::<String>::deserialize(d)?.map(|s| s.trim().to_string()))
+}
+
+/// Tells a missing field, left untouched by a patch, from a null one, which clears the field
+pub fn nullable<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    d: D,
+) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(d).map(Some)
+}
+
+/// Checks the fields of an object received from a client
+pub trait Validate {
+    fn check(&self, v: &mut Validator);
+
+    fn validate(&self) -> Result<(), ErrResponse> {
+        let mut v = Validator::default();
+        self.check(&mut v);
+        v.finish()
+    }
+}
+
+/// Collects the errors of every field, so that they are all reported at once
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn fail(&mut self, field: &str, message: impl Into<String>) -> &mut Self {
+        self.errors.push(FieldError::new(field, message));
+        self
+    }
+
+    pub fn required(&mut self, field: &str, value: &str) -> &mut Self {
+        if value.is_empty() {
+            self.fail(field, "is required");
+        }
+        self
+    }
+
+    pub fn max_length(&mut self, field: &str, value: &str, max: usize) -> &mut Self {
+        if value.chars().count() > max {
+            self.fail(field, format!("must be at most {} characters long", max));
+        }
+        self
+    }
+
+    /// Checks an optional mail address, which must be accepted by the mailer
+    pub fn mail(&mut self, field: &str, value: &str) -> &mut Self {
+        if !value.is_empty() && value.parse::<Address>().is_err() {
+            self.fail(field, "must be a mail address");
+        }
+        self.max_length(field, value, MAIL_LENGTH)
+    }
+
+    /// Checks an optional phone number, made of digits with an optional leading `+` and separators
+    pub fn phone(&mut self, field: &str, value: &str) -> &mut Self {
+        let digits = value.chars().filter(char::is_ascii_digit).count();
+        let valid = value
+            .strip_prefix('+')
+            .unwrap_or(value)
+            .chars()
+            .all(|c| c.is_ascii_digit() || " .-()".contains(c));
+        if !value.is_empty() && (!valid || digits < 3) {
+            self.fail(field, "must be a phone number");
+        }
+        self.max_length(field, value, PHONE_LENGTH)
+    }
+
+    /// Adds the errors of a nested object, their field being prefixed by the one of the object
+    pub fn nested(&mut self, prefix: &str, other: Validator) -> &mut Self {
+        for error in other.errors {
+            self.fail(&format!("{prefix}.{}", error.field), error.message);
+        }
+        self
+    }
+
+    pub fn finish(self) -> Result<(), ErrResponse> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ErrResponse::S422("invalid data", self.errors))
+        }
+    }
+}
+
+/// JSON body extractor validating the received object, and reporting the malformed bodies as problems
+pub struct ValidJson<T>(pub T);
+
+impl<S, T> FromRequest<S> for ValidJson<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + Validate,
+{
+    type Rejection = ErrResponse;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(rejection)?;
+        value.validate()?;
+        Ok(ValidJson(value))
+    }
+}
+
+fn rejection(rejection: JsonRejection) -> ErrResponse {
+    match rejection {
+        JsonRejection::JsonDataError(e) => {
+            let text = e.body_text();
+            // Serde reports the missing fields as "missing field `name`"
+            let error = match text.split_once("missing field `") {
+                Some((_, rest)) => {
+                    FieldError::new(rest.split('`').next().unwrap_or_default(), "is required")
+                }
+                None => FieldError::new("body", text),
+            };
+            ErrResponse::S422("invalid data", vec![error])
+        }
+        JsonRejection::JsonSyntaxError(_) => ErrResponse::S400("malformed JSON body"),
+        JsonRejection::MissingJsonContentType(_) => {
+            ErrResponse::S400("expected a `Content-Type: application/json` body")
+        }
+        _ => ErrResponse::S400("could not read the request body"),
+    }
+}
@@ -1,38 +1,73 @@
 use std::env;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use http::HeaderMap;
 use http::StatusCode;
 
-use chrono::NaiveDateTime;
+use chrono::{Datelike, Local, NaiveDateTime, TimeDelta, Timelike, Weekday};
 use tinytickets_backend::{
     build_router,
+    config::Config,
+    errors::{FieldError, Problem},
     mail::Mailer,
     models::{
-        asset::{Asset, InAsset},
+        asset::{Asset, AssetTree, DeletionReport, InAsset},
+        attachment::Attachment,
+        category::{
+            Category, FieldDefinition, FieldDefinitions, FieldKind, FieldValues, InCategory,
+        },
         comment::{Comment, InComment},
-        ticket::{InTicket, Ticket},
+        export::ExportedTicket,
+        history::{EventAction, EventEntity, FieldChange, TicketEvent},
+        maintenance::{InMaintenancePlan, MaintenancePlan, MaintenanceTicket},
+        search::SearchHit,
+        sla::{Priority, SlaAlert, SlaAlertKind},
+        ticket::{Assignment, InTicket, Ticket},
+        user::{InUser, Role, User, UserWithToken},
+        workflow::{TicketStatus, Workflow},
     },
+    outbox::{MailStatus, OutboxMail},
+    scheduler::parse_schedule,
 };
 
 use std::convert::TryFrom;
 
+/// Address of the web app in the QR codes of the assets
+const PUBLIC_URL: &str = "https://tickets.test.com";
+
 #[tokio::test]
 async fn tests_endtoend() {
-    // Remove the db to start fresh
-    if Path::new("db/db.sqlite").exists() {
-        if let Err(e) = fs::remove_file("db/db.sqlite") {
-            panic!("error removing db: {}", e);
-        }
+    // Remove the db and the files left by an earlier run with the same process id to start fresh
+    if test_dir().exists()
+        && let Err(e) = fs::remove_dir_all(test_dir())
+    {
+        panic!("error removing test directory: {}", e);
     }
     // TODO: Audit that the environment access only happens in single-threaded code.
     unsafe { env::set_var("ADMIN_TOKEN", "development_admin_token") };
     // TODO: Audit that the environment access only happens in single-threaded code.
     unsafe { env::set_var("USER_TOKEN", "development_user_token") };
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::set_var("OPEN_TICKETS_SCHEDULE", "* * * * * *") };
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::set_var("TICKET_MAIL_TO", "tickets@test.com") };
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::set_var("COMMENT_MAIL_TO", "comments@test.com") };
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::set_var("MAIL_MAX_ATTEMPTS", "1") };
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::set_var("ATTACHMENT_MAX_MB", "1") };
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::set_var("BACKUP_MAX_MB", "2") };
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::set_var("PUBLIC_URL", format!("{PUBLIC_URL}/")) };
+    test_config();
     // NOTE: If we had more than one test running concurrently that dispatches
     // DB-accessing requests, we'd need transactions or to serialize all tests.
-    let mailer = Mailer::new(true);
+    let mut config = Config::init().unwrap();
+    let mailer = Mailer::new(true, config.mail.clone());
     let client = reqwest::Client::builder().build().unwrap();
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
@@ -41,7 +76,11 @@ async fn tests_endtoend() {
     let addr = (listener).local_addr().unwrap();
     let port = addr.port();
 
-    let app = build_router(Some(mailer.clone())).await.into_make_service();
+    config.database_url = test_dir().join("db.sqlite").to_string_lossy().to_string();
+    config.data_dir = data_dir();
+    let app = build_router(config, Some(mailer.clone()))
+        .await
+        .into_make_service();
 
     tokio::spawn(async move {
         axum::serve(listener, app).await.unwrap();
@@ -51,131 +90,708 @@ async fn tests_endtoend() {
 
     let base = &format!("http://localhost:{port}");
 
+    test_schedules();
     test_title(base, &client).await;
+    test_users(base, &client).await;
     test_assets(base, &client).await;
     test_tickets(base, &client).await;
     test_comments(base, &client).await;
+    test_asset_tree(base, &client).await;
+    test_asset_categories(base, &client).await;
+    test_maintenance(base, &client).await;
+    test_outbox(base, &client).await;
+    test_backup(base, &client).await;
     assert_eq!(
         client.get(base).send().await.unwrap().status(),
         StatusCode::OK
     );
+
+    // The open tickets digest can be triggered manually, and is sent every second by the scheduler
+    let (_, user_header) = headers();
+    let open_tickets = client
+        .get(format!("{base}/api/tickets/mail_open"))
+        .headers(user_header)
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<Ticket>>()
+        .await
+        .unwrap();
+    assert_eq!(open_tickets.len(), 1);
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    assert!(
+        mailer
+            .print_test_mails()
+            .contains("to: \"tickets@test.com\", subject: \"Summary of open tickets\"")
+    );
     assert!(
         mailer
             .print_test_mails()
             .contains("Ticket created by patched creator: patched title has been closed")
     );
-}
+    assert!(
+        mailer
+            .print_test_mails()
+            .contains("Ticket created by patched creator: patched title has been resolved")
+    );
+    assert!(mailer.print_test_mails().contains(
+        "to: \"desk@test.com\", subject: \"Ticket created by patched creator: patched title has been assigned to you\""
+    ));
+    assert!(
+        mailer
+            .print_test_mails()
+            .contains("Tickets about to breach or in breach of their service level")
+    );
+    assert!(mailer.print_test_mails().contains(
+        "New ticket created by Tree creator: Broken on asset Site / Building / Room / Machine"
+    ));
+    assert!(
+        mailer
+            .print_test_mails()
+            .contains("New ticket created by Preventive maintenance: Service Boiler")
+    );
 
-async fn test_title(base: &str, client: &reqwest::Client) {
-    let resp = client
-        .get(&format!("{base}/api/app-title"))
-        .send()
-        .await
-        .unwrap();
-    assert_eq!(resp.status(), StatusCode::OK);
-    assert_eq!(resp.text().await.unwrap(), "Tiny Tickets");
+    // The directory of a failed run is left for inspection
+    fs::remove_dir_all(test_dir()).unwrap();
 }
 
-async fn test_assets(base: &str, client: &reqwest::Client) {
-    // Number of assets we're going to create/read/delete.
-    const N: usize = 20;
-    let (admin_header, user_header) = headers();
-
-    let api = &format!("{base}/api/assets");
+fn test_config() {
+    // The settings of the configuration file are overridden by the environment
+    fs::create_dir_all(test_dir()).unwrap();
+    let config_file = test_dir().join("config.toml");
+    fs::write(
+        &config_file,
+        r#"
+app_title = "Configured Tickets"
+mail_max_attempts = 5
+sla_warning_hours = 4
+
+[[ticket_transitions]]
+from = "new"
+to = "closed"
+"#,
+    )
+    .unwrap();
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::set_var("CONFIG_FILE", &config_file) };
+    let config = Config::init().unwrap();
+    assert_eq!(config.app_title, "Configured Tickets");
+    assert_eq!(config.sla_warning_hours, 4);
+    assert_eq!(config.mail_max_attempts, 1);
+    assert_eq!(config.workflow.transitions.len(), 1);
+
+    // Unknown settings are refused
+    fs::write(&config_file, "ticket_mail = \"tickets@test.com\"").unwrap();
+    assert!(
+        Config::init()
+            .err()
+            .unwrap()
+            .contains("unknown field `ticket_mail`")
+    );
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::remove_var("CONFIG_FILE") };
 
-    // Clear everything from the database.
+    // Invalid values are refused at startup, naming the setting
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::set_var("TICKET_MAIL_TO", "tickets@test.com, not an address") };
+    assert!(Config::init().err().unwrap().starts_with("TICKET_MAIL_TO"));
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::set_var("TICKET_MAIL_TO", "tickets@test.com") };
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::set_var("SLA_CHECK_MINUTES", "often") };
     assert_eq!(
-        client.delete(api).send().await.unwrap().status(),
-        StatusCode::UNAUTHORIZED
+        Config::init().err().unwrap(),
+        "SLA_CHECK_MINUTES must be a number of minutes, not `often`"
     );
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::remove_var("SLA_CHECK_MINUTES") };
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::set_var("MAIL_MAX_ATTEMPTS", "0") };
     assert_eq!(
-        client
-            .delete(api)
-            .headers(user_header.clone())
-            .send()
-            .await
-            .unwrap()
-            .status(),
-        StatusCode::FORBIDDEN
+        Config::init().err().unwrap(),
+        "MAIL_MAX_ATTEMPTS must be a number of at least 1, not `0`"
     );
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::set_var("MAIL_MAX_ATTEMPTS", "1") };
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::set_var("MAIL_RETRY_SECONDS", "0") };
     assert_eq!(
-        client
-            .delete(api)
-            .headers(admin_header.clone())
-            .send()
-            .await
-            .unwrap()
-            .status(),
-        StatusCode::OK
+        Config::init().err().unwrap(),
+        "MAIL_RETRY_SECONDS must be a number of seconds of at least 1, not `0`"
+    );
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::remove_var("MAIL_RETRY_SECONDS") };
+    let target = r#"{"response_hours": 1, "resolution_hours": 8}"#;
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe {
+        env::set_var(
+            "SLA_TARGETS",
+            format!(
+                r#"{{"low": {target}, "normal": {target}, "high": {target}, "urgent": {{"response_hours": -1, "resolution_hours": 8}}}}"#
+            ),
+        )
+    };
+    assert_eq!(
+        Config::init().err().unwrap(),
+        "SLA_TARGETS must be numbers of hours of at least 0, not `-1`"
     );
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::remove_var("SLA_TARGETS") };
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe {
+        env::set_var(
+            "TICKET_TRANSITIONS",
+            r#"[{"from": "new", "to": "closed", "template": "missing_ticket"}]"#,
+        )
+    };
     assert_eq!(
+        Config::init().err().unwrap(),
+        "TICKET_TRANSITIONS must name existing mail templates, not `missing_ticket`"
+    );
+    // TODO: Audit that the environment access only happens in single-threaded code.
+    unsafe { env::remove_var("TICKET_TRANSITIONS") };
+}
+
+async fn test_outbox(base: &str, client: &reqwest::Client) {
+    let (admin_header, user_header) = headers();
+    let api = &format!("{base}/api/outbox");
+    let asset_id = client
+        .get(format!("{base}/api/assets"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<i32>>()
+        .await
+        .unwrap()[0];
+
+    // Create a ticket whose creator mail cannot be delivered, and close it so that a mail is queued for the creator
+    let ticket = InTicket {
+        title: "Unreachable".to_string(),
+        creator: "Unreachable creator".to_string(),
+        creator_mail: "unreachable@test.invalid".to_string(),
+        creator_phone: "01020304".to_string(),
+        description: "Unreachable".to_string(),
+        asset_id,
+        priority: Priority::Normal,
+    };
+    let ticket = client
+        .post(format!("{base}/api/tickets"))
+        .headers(user_header.clone())
+        .json(&ticket)
+        .send()
+        .await
+        .unwrap()
+        .json::<Ticket>()
+        .await
+        .unwrap();
+    let response = client
+        .post(format!("{base}/api/tickets/{}/close", ticket.id))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    // Changing the closed ticket, or closing it again, does not send the mail again
+    let response = client
+        .patch(format!("{base}/api/tickets/{}", ticket.id))
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({"title": "Still unreachable"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client
+        .post(format!("{base}/api/tickets/{}/close", ticket.id))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // The mail cannot be sent, and as a single attempt is allowed by the test configuration, it fails
+    let failed_mails = || async {
         client
-            .get(api)
-            .headers(user_header.clone())
+            .get(format!("{api}?status=failed"))
+            .headers(admin_header.clone())
             .send()
             .await
             .unwrap()
-            .json::<Option<Vec<i64>>>()
+            .json::<Vec<OutboxMail>>()
             .await
-            .unwrap(),
-        Some(vec![])
+            .unwrap()
+    };
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let failed = failed_mails().await;
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].recipients, "unreachable@test.invalid");
+    assert_eq!(failed[0].attempts, 1);
+    assert!(
+        failed[0]
+            .last_error
+            .as_ref()
+            .unwrap()
+            .contains("unknown domain")
     );
 
-    // Add some random assets, ensure they're listable and readable.
-    for i in 1..=N {
-        let title = format!("My Asset - {}", i);
-        let description = format!("Once upon a time, at {}'o clock...", i);
-        let asset = InAsset {
-            title: title.clone(),
-            description: description.clone(),
-        };
+    // The other mails were sent
+    let sent = client
+        .get(format!("{api}?status=sent"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<OutboxMail>>()
+        .await
+        .unwrap();
+    assert!(!sent.is_empty());
+    assert!(sent.iter().all(|m| m.sent_at.is_some()));
 
-        // Create a new asset.
-        assert_eq!(
-            client.post(api).json(&asset).send().await.unwrap().status(),
-            StatusCode::UNAUTHORIZED
-        );
+    // Only admins can see the outbox
+    let response = client.get(api).headers(user_header).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
 
-        let response = client
-            .post(api)
+    // Retry the failed mail, which fails again
+    let response = client
+        .post(format!("{api}/{}/retry", failed[0].id))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let failed = failed_mails().await;
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].status, MailStatus::Failed);
+
+    // Only failed mails can be retried
+    let response = client
+        .post(format!("{api}/{}/retry", sent[0].id))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Retry all the failed mails
+    let response = client
+        .post(format!("{api}/retry"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.json::<usize>().await.unwrap(), 1);
+}
+
+async fn test_backup(base: &str, client: &reqwest::Client) {
+    let (admin_header, user_header) = headers();
+    let api = &format!("{base}/api/admin");
+
+    // Only admins can backup the data
+    let response = client
+        .get(format!("{api}/backup"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // The archive holds the database and the files
+    let response = client
+        .get(format!("{api}/backup"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/zip");
+    let backup = response.bytes().await.unwrap();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(backup.clone())).unwrap();
+    assert!(archive.by_name("db.sqlite").is_ok());
+    assert!(
+        archive
+            .file_names()
+            .all(|f| f == "db.sqlite" || f.starts_with("photos/") || f.starts_with("attachments/"))
+    );
+
+    // Create an asset after the backup, which is gone once the backup is restored
+    let asset = client
+        .post(format!("{base}/api/assets"))
+        .headers(admin_header.clone())
+        .json(&InAsset {
+            title: "Asset after backup".to_string(),
+            description: "Asset after backup".to_string(),
+            parent_id: None,
+            ..Default::default()
+        })
+        .send()
+        .await
+        .unwrap()
+        .json::<Asset>()
+        .await
+        .unwrap();
+    // Create a ticket after the backup too, whose mail is kept in the outbox once the backup is restored
+    let response = client
+        .post(format!("{base}/api/tickets"))
+        .headers(user_header.clone())
+        .json(&InTicket {
+            title: "Ticket after backup".to_string(),
+            creator: "Ticket after backup".to_string(),
+            creator_mail: "after@test.com".to_string(),
+            creator_phone: "01020304".to_string(),
+            description: "Ticket after backup".to_string(),
+            asset_id: asset.id,
+            priority: Priority::Normal,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let outbox_ids = || async {
+        let mut ids: Vec<i32> = client
+            .get(format!("{base}/api/outbox"))
             .headers(admin_header.clone())
-            .json(&asset)
             .send()
             .await
             .unwrap()
-            .json::<Asset>()
-            .await
-            .unwrap();
-        assert_eq!(response, asset);
-
-        // Ensure the index shows one more asset.
-        let list = client
-            .get(api)
-            .headers(user_header.clone())
-            .send()
+            .json::<Vec<OutboxMail>>()
             .await
             .unwrap()
-            .json::<Vec<i64>>()
-            .await
-            .unwrap();
-        assert_eq!(list.len(), i);
+            .iter()
+            .map(|m| m.id)
+            .collect();
+        ids.sort();
+        ids
+    };
+    let outbox = outbox_ids().await;
+    let response = client
+        .post(format!("{api}/restore"))
+        .headers(admin_header.clone())
+        .body(backup.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    // The digest of the open tickets may have queued more mails in the meantime
+    let restored = outbox_ids().await;
+    assert!(outbox.iter().all(|id| restored.contains(id)));
+    let response = client
+        .get(format!("{base}/api/assets/{}", asset.id))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
-        // The last in the index is the new one; ensure contents match.
-        let last = list.last().unwrap();
-        let response = client
-            .get(format!("{}/{}", api, last))
-            .headers(user_header.clone())
-            .send()
-            .await
-            .unwrap()
-            .json::<Asset>()
-            .await
-            .unwrap();
-        assert_eq!(response, asset);
-    }
+    // The search index is rebuilt from the restored tickets
+    let hits = client
+        .get(format!("{base}/api/search?q=unreachable"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<SearchHit>>()
+        .await
+        .unwrap();
+    assert!(!hits.is_empty());
 
-    // Patch the assets
-    for _ in 1..=N {
+    // Only admins can restore the data
+    let response = client
+        .post(format!("{api}/restore"))
+        .headers(user_header.clone())
+        .body(backup.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Something that is not an archive, or an archive without database, is refused
+    let response = client
+        .post(format!("{api}/restore"))
+        .headers(admin_header.clone())
+        .body("not an archive")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    zip.start_file("photos/1.jpg", zip::write::SimpleFileOptions::default())
+        .unwrap();
+    let no_database = zip.finish().unwrap().into_inner();
+    let response = client
+        .post(format!("{api}/restore"))
+        .headers(admin_header.clone())
+        .body(no_database)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // An archive larger than the maximum size is refused, as well as one whose files are, once extracted
+    let response = client
+        .post(format!("{api}/restore"))
+        .headers(admin_header.clone())
+        .body(vec![0u8; 2 * 1024 * 1024 + 1])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    zip.start_file("db.sqlite", zip::write::SimpleFileOptions::default())
+        .unwrap();
+    std::io::Write::write_all(&mut zip, &vec![0u8; 3 * 1024 * 1024]).unwrap();
+    let bomb = zip.finish().unwrap().into_inner();
+    assert!(bomb.len() < 1024 * 1024);
+    let response = client
+        .post(format!("{api}/restore"))
+        .headers(admin_header.clone())
+        .body(bomb)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // A backup made by a newer version of the application is refused
+    let newer_path = &format!("{}/newer_backup.sqlite", data_dir());
+    std::io::copy(
+        &mut archive.by_name("db.sqlite").unwrap(),
+        &mut fs::File::create(newer_path).unwrap(),
+    )
+    .unwrap();
+    {
+        use diesel::{Connection, RunQueryDsl, SqliteConnection};
+        let mut conn = SqliteConnection::establish(newer_path).unwrap();
+        diesel::sql_query("INSERT INTO __diesel_schema_migrations (version) VALUES ('99.0.0')")
+            .execute(&mut conn)
+            .unwrap();
+    }
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    zip.start_file("db.sqlite", zip::write::SimpleFileOptions::default())
+        .unwrap();
+    std::io::copy(&mut fs::File::open(newer_path).unwrap(), &mut zip).unwrap();
+    let newer = zip.finish().unwrap().into_inner();
+    fs::remove_file(newer_path).unwrap();
+    let response = client
+        .post(format!("{api}/restore"))
+        .headers(admin_header.clone())
+        .body(newer)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // A disabled built-in account stays disabled once the data is restored
+    let user = client
+        .get(format!("{base}/api/users"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<User>>()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|u| u.name == "user")
+        .unwrap();
+    let response = client
+        .patch(format!("{base}/api/users/{}", user.id))
+        .headers(admin_header.clone())
+        .json(&User {
+            enabled: false,
+            ..user.clone()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let backup = client
+        .get(format!("{api}/backup"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    let response = client
+        .post(format!("{api}/restore"))
+        .headers(admin_header.clone())
+        .body(backup)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client
+        .get(format!("{base}/api/users/me"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .patch(format!("{base}/api/users/{}", user.id))
+        .headers(admin_header.clone())
+        .json(&user)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+async fn test_title(base: &str, client: &reqwest::Client) {
+    let resp = client
+        .get(format!("{base}/api/app-title"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.text().await.unwrap(), "Tiny Tickets");
+}
+
+fn test_schedules() {
+    let weekdays = |expression: &str| {
+        let mut days: Vec<Weekday> = parse_schedule(expression)
+            .unwrap()
+            .upcoming(Local)
+            .take(21)
+            .map(|t| t.weekday())
+            .collect();
+        days.sort_by_key(Weekday::num_days_from_monday);
+        days.dedup();
+        days
+    };
+    // The standard expressions number the weekdays from 0 for Sunday
+    let working_days = vec![
+        Weekday::Mon,
+        Weekday::Tue,
+        Weekday::Wed,
+        Weekday::Thu,
+        Weekday::Fri,
+    ];
+    assert_eq!(weekdays("0 8 * * 1-5"), working_days);
+    assert_eq!(weekdays("0 8 * * Mon-Fri"), working_days);
+    assert_eq!(weekdays("0 8 * * 0"), vec![Weekday::Sun]);
+    assert_eq!(weekdays("0 8 * * 7"), vec![Weekday::Sun]);
+    assert_eq!(
+        weekdays("0 8 * * 5-7"),
+        vec![Weekday::Fri, Weekday::Sat, Weekday::Sun]
+    );
+    assert_eq!(
+        weekdays("0 8 * * 1,3/2"),
+        vec![Weekday::Mon, Weekday::Wed, Weekday::Fri]
+    );
+    assert_eq!(
+        weekdays("0 8 * * */3"),
+        vec![Weekday::Wed, Weekday::Sat, Weekday::Sun]
+    );
+    // The expressions with seconds keep the numbering of the cron crate, from 1 for Sunday
+    assert_eq!(weekdays("0 0 8 * * 1"), vec![Weekday::Sun]);
+    assert!(parse_schedule("0 8 * * 8").is_err());
+    assert!(parse_schedule("0 8 * * Mon-5").is_err());
+}
+
+async fn test_assets(base: &str, client: &reqwest::Client) {
+    // Number of assets we're going to create/read/delete.
+    const N: usize = 20;
+    let (admin_header, user_header) = headers();
+
+    let api = &format!("{base}/api/assets");
+
+    // Clear everything from the database.
+    assert_eq!(
+        client.delete(api).send().await.unwrap().status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        client
+            .delete(api)
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        client
+            .delete(format!("{api}?confirm=true"))
+            .headers(admin_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        client
+            .get(api)
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .json::<Option<Vec<i64>>>()
+            .await
+            .unwrap(),
+        Some(vec![])
+    );
+
+    // Add some random assets, ensure they're listable and readable.
+    for i in 1..=N {
+        let title = format!("My Asset - {}", i);
+        let description = format!("Once upon a time, at {}'o clock...", i);
+        let asset = InAsset {
+            title: title.clone(),
+            description: description.clone(),
+            parent_id: None,
+            ..Default::default()
+        };
+
+        // Create a new asset.
+        assert_eq!(
+            client.post(api).json(&asset).send().await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let response = client
+            .post(api)
+            .headers(admin_header.clone())
+            .json(&asset)
+            .send()
+            .await
+            .unwrap()
+            .json::<Asset>()
+            .await
+            .unwrap();
+        assert_eq!(response, asset);
+
+        // Ensure the index shows one more asset.
+        let list = client
+            .get(api)
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .json::<Vec<i64>>()
+            .await
+            .unwrap();
+        assert_eq!(list.len(), i);
+
+        // The last in the index is the new one; ensure contents match.
+        let last = list.last().unwrap();
+        let response = client
+            .get(format!("{}/{}", api, last))
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .json::<Asset>()
+            .await
+            .unwrap();
+        assert_eq!(response, asset);
+    }
+
+    // Patch the assets
+    for _ in 1..=N {
         // Get a valid ID from the index.
         let list = client
             .get(api)
@@ -186,13 +802,28 @@ async fn test_assets(base: &str, client: &reqwest::Client) {
             .json::<Vec<i64>>()
             .await
             .unwrap();
-        let id = list.get(0).expect("have asset");
+        let id = list.first().expect("have asset");
+        let response = client
+            .get(format!("{}/{}", api, id))
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap();
+        let etag = response.headers()["ETag"].clone();
+        let original = response.json::<Asset>().await.unwrap();
+        assert_eq!(etag, format!("\"{}\"", original.version));
 
         // Patch that asset.
         let asset = Asset {
             id: i32::try_from(*id).unwrap(),
             title: "patched title".to_string(),
             description: format!("Once upon a time, at {}'o clock...", id),
+            version: original.version + 1,
+            parent_id: None,
+            category_id: None,
+            serial_number: String::new(),
+            fields: Default::default(),
+            archived: false,
         };
         assert_eq!(
             client
@@ -207,11 +838,28 @@ async fn test_assets(base: &str, client: &reqwest::Client) {
         let response = client
             .patch(format!("{}/{}", api, id))
             .headers(admin_header.clone())
+            .header("If-Match", etag.clone())
             .json(&asset)
             .send()
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let new_etag = response.headers()["ETag"].clone();
+        assert_eq!(new_etag, format!("\"{}\"", asset.version));
+        // Changes made from a stale version are refused
+        let response = client
+            .patch(format!("{}/{}", api, id))
+            .headers(admin_header.clone())
+            .header("If-Match", etag)
+            .json(&serde_json::json!({"title": "stale title"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(
+            response.json::<Problem>().await.unwrap().code,
+            "precondition_failed"
+        );
         // Check that asset is patched
         let response = client
             .get(format!("{}/{}", api, id))
@@ -223,6 +871,33 @@ async fn test_assets(base: &str, client: &reqwest::Client) {
             .await
             .unwrap();
         assert_eq!(response, asset);
+        // Patch only its description
+        let response = client
+            .patch(format!("{}/{}", api, id))
+            .headers(admin_header.clone())
+            .header("If-Match", format!("\"0\", {}", new_etag.to_str().unwrap()))
+            .json(&serde_json::json!({"description": "partially patched"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = client
+            .get(format!("{}/{}", api, id))
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .json::<Asset>()
+            .await
+            .unwrap();
+        assert_eq!(
+            response,
+            Asset {
+                description: "partially patched".to_string(),
+                version: asset.version + 1,
+                ..asset
+            }
+        );
     }
 
     // Now delete all of the assets.
@@ -237,7 +912,7 @@ async fn test_assets(base: &str, client: &reqwest::Client) {
             .json::<Vec<i64>>()
             .await
             .unwrap();
-        let id = list.get(0).expect("have asset");
+        let id = list.first().expect("have asset");
 
         // Delete that asset.
         assert_eq!(
@@ -251,7 +926,7 @@ async fn test_assets(base: &str, client: &reqwest::Client) {
         );
         assert_eq!(
             client
-                .delete(format!("{}/{}", api, id))
+                .delete(format!("{}/{}?confirm=true", api, id))
                 .headers(admin_header.clone())
                 .send()
                 .await
@@ -275,7 +950,7 @@ async fn test_assets(base: &str, client: &reqwest::Client) {
 
     // Trying to delete should now 404.
     let response = client
-        .delete(format!("{}/{}", api, 1))
+        .delete(format!("{}/{}?confirm=true", api, 1))
         .headers(admin_header.clone())
         .send()
         .await
@@ -283,237 +958,2250 @@ async fn test_assets(base: &str, client: &reqwest::Client) {
     assert_eq!(response.status(), StatusCode::NOT_FOUND);
 }
 
-fn headers() -> (HeaderMap, HeaderMap) {
-    let mut admin_header = HeaderMap::new();
-    admin_header.insert("X-TOKEN", "$ADMIN$development_admin_token".parse().unwrap());
-    let mut user_header = HeaderMap::new();
-    user_header.insert("X-TOKEN", "$USER$development_user_token".parse().unwrap());
-    (admin_header, user_header)
-}
-
-async fn test_tickets(base: &str, client: &reqwest::Client) {
-    // Number of tickets we're going to create/read/delete.
-    const N: usize = 20;
+async fn test_users(base: &str, client: &reqwest::Client) {
     let (admin_header, user_header) = headers();
 
-    let api = &format!("{base}/api/tickets");
+    let api = &format!("{base}/api/users");
 
-    // Clear everything from the database.
+    // The built-in users are resolved from the configured tokens
+    let builtin_user = client
+        .get(format!("{api}/me"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<User>()
+        .await
+        .unwrap();
+    assert_eq!(builtin_user.name, "user");
+    assert_eq!(builtin_user.role, Role::User);
+    assert!(builtin_user.builtin);
+    let admin = client
+        .get(format!("{api}/me"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<User>()
+        .await
+        .unwrap();
+    assert_eq!(admin.name, "admin");
+    assert_eq!(admin.role, Role::Admin);
+    assert!(admin.builtin);
+
+    // Only admins can manage users
+    let user = InUser {
+        name: "John Doe".to_string(),
+        role: Role::User,
+        mail: "john.doe@test.com".to_string(),
+    };
+    let response = client.post(api).json(&user).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     assert_eq!(
-        client.delete(api).send().await.unwrap().status(),
-        StatusCode::UNAUTHORIZED
+        response.headers()["content-type"],
+        "application/problem+json"
     );
     assert_eq!(
-        client
-            .delete(api)
+        response.json::<Problem>().await.unwrap(),
+        Problem {
+            status: 401,
+            code: "unauthorized".to_string(),
+            message: "`X-TOKEN` header is missing".to_string(),
+            errors: Vec::new(),
+        }
+    );
+    assert_eq!(
+        client
+            .post(api)
             .headers(user_header.clone())
+            .json(&user)
             .send()
             .await
             .unwrap()
             .status(),
         StatusCode::FORBIDDEN
     );
+
+    // Create a user and use its token
+    let response = client
+        .post(api)
+        .headers(admin_header.clone())
+        .json(&user)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created = response.json::<UserWithToken>().await.unwrap();
+    assert_eq!(created.user, user);
+    assert!(created.user.enabled);
+    assert!(!created.user.builtin);
+    assert!(created.token.starts_with("$USER$"));
+    let mut john_header = HeaderMap::new();
+    john_header.insert("X-TOKEN", created.token.parse().unwrap());
+
+    // User names are unique
+    let response = client
+        .post(api)
+        .headers(admin_header.clone())
+        .json(&user)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(response.json::<Problem>().await.unwrap().code, "conflict");
+    let response = client
+        .get(format!("{api}/me"))
+        .headers(john_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<User>()
+        .await
+        .unwrap();
+    assert_eq!(response, created.user);
     assert_eq!(
         client
-            .delete(api)
-            .headers(admin_header.clone())
+            .get(api)
+            .headers(john_header.clone())
             .send()
             .await
             .unwrap()
             .status(),
-        StatusCode::OK
+        StatusCode::FORBIDDEN
     );
+
+    // Disable the user: its token must not be accepted anymore
+    let mut disabled = created.user.clone();
+    disabled.enabled = false;
+    let response = client
+        .patch(format!("{api}/{}", disabled.id))
+        .headers(admin_header.clone())
+        .json(&disabled)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
     assert_eq!(
         client
-            .get(api)
-            .headers(user_header.clone())
+            .get(format!("{api}/me"))
+            .headers(john_header.clone())
             .send()
             .await
             .unwrap()
-            .json::<Option<Vec<i64>>>()
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+
+    // Re-enable the user and regenerate its token: only the new token is valid
+    // A user cannot be made built-in, which would hide it as the creator of its tickets
+    let response = client
+        .patch(format!("{api}/{}", disabled.id))
+        .headers(admin_header.clone())
+        .json(&User {
+            builtin: true,
+            ..created.user.clone()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let regenerated = client
+        .post(format!("{api}/{}/token", created.user.id))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<UserWithToken>()
+        .await
+        .unwrap();
+    assert_ne!(regenerated.token, created.token);
+    assert_eq!(
+        client
+            .get(format!("{api}/me"))
+            .headers(john_header.clone())
+            .send()
             .await
-            .unwrap(),
-        Some(vec![])
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+    assert!(!regenerated.user.builtin);
+    let mut john_header = HeaderMap::new();
+    john_header.insert("X-TOKEN", regenerated.token.parse().unwrap());
+    assert_eq!(
+        client
+            .get(format!("{api}/me"))
+            .headers(john_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::OK
     );
 
-    // Add a ticket to a non existing asset (must fail)
-    let ticket = InTicket {
-        title: "test".to_string(),
-        creator: "test".to_string(),
-        creator_mail: "test@test.com".to_string(),
-        creator_phone: "0102030405".to_string(),
-        description: "test".to_string(),
-        time: NaiveDateTime::parse_from_str("2021-08-12T20:00:00", "%Y-%m-%dT%H:%M:%S").unwrap(),
-        asset_id: 1,
-        is_closed: false,
-    };
-    // Create a new ticket.
+    // An admin cannot lock itself out
+    let mut demoted = admin.clone();
+    demoted.enabled = false;
     let response = client
-        .post(api)
-        .headers(user_header.clone())
-        .json(&ticket)
+        .patch(format!("{api}/{}", admin.id))
+        .headers(admin_header.clone())
+        .json(&demoted)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // The built-in accounts keep their name and role, and a missing user cannot be updated
+    for changed in [
+        User {
+            name: "renamed".to_string(),
+            ..admin.clone()
+        },
+        User {
+            role: Role::Admin,
+            ..builtin_user.clone()
+        },
+    ] {
+        let response = client
+            .patch(format!("{api}/{}", changed.id))
+            .headers(admin_header.clone())
+            .json(&changed)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+    let response = client
+        .patch(format!("{api}/9999"))
+        .headers(admin_header.clone())
+        .json(&User {
+            id: 9999,
+            ..created.user.clone()
+        })
         .send()
         .await
         .unwrap();
     assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
-    // Add an asset
-    let asset = InAsset {
-        title: "MyAsset".to_string(),
-        description: "MyAssetDescription".to_string(),
-    };
-    let response = client
-        .post(format!("{base}/api/assets"))
+    // List and delete the user
+    let list = client
+        .get(api)
         .headers(admin_header.clone())
-        .json(&asset)
         .send()
         .await
         .unwrap()
-        .json::<InAsset>()
+        .json::<Vec<User>>()
         .await
         .unwrap();
-    assert_eq!(response, asset);
+    assert_eq!(list.len(), 3);
+    let response = client
+        .delete(format!("{api}/{}", created.user.id))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .delete(format!("{api}/{}", created.user.id))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
 
-    // Get a valid asset id
-    let asset_id = client
-        .get(format!("{base}/api/assets"))
+/// Directory of the test database and files, so that the tests do not touch the ones of a development server,
+/// named after the process so that several runs do not share it
+fn test_dir() -> PathBuf {
+    env::temp_dir().join(format!("tinytickets_endtoend_{}", std::process::id()))
+}
+
+fn data_dir() -> String {
+    test_dir().join("data").to_string_lossy().to_string()
+}
+
+fn headers() -> (HeaderMap, HeaderMap) {
+    let mut admin_header = HeaderMap::new();
+    admin_header.insert("X-TOKEN", "$ADMIN$development_admin_token".parse().unwrap());
+    let mut user_header = HeaderMap::new();
+    user_header.insert("X-TOKEN", "$USER$development_user_token".parse().unwrap());
+    (admin_header, user_header)
+}
+
+async fn test_asset_tree(base: &str, client: &reqwest::Client) {
+    let (admin_header, user_header) = headers();
+    let api = &format!("{base}/api/assets");
+
+    // Build a site, with a building, a room and a machine
+    let mut parent_id = None;
+    let mut ids = Vec::new();
+    for title in ["Site", "Building", "Room", "Machine"] {
+        let asset = client
+            .post(api)
+            .headers(admin_header.clone())
+            .json(&InAsset {
+                title: title.to_string(),
+                description: format!("{title} description"),
+                parent_id,
+                ..Default::default()
+            })
+            .send()
+            .await
+            .unwrap()
+            .json::<Asset>()
+            .await
+            .unwrap();
+        assert_eq!(asset.parent_id, parent_id);
+        parent_id = Some(asset.id);
+        ids.push(asset.id);
+    }
+    let (site, building, room, machine) = (ids[0], ids[1], ids[2], ids[3]);
+    // The parent must exist
+    let response = client
+        .post(api)
+        .headers(admin_header.clone())
+        .json(&InAsset {
+            title: "Orphan".to_string(),
+            description: "Orphan".to_string(),
+            parent_id: Some(9999),
+            ..Default::default()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    // The description is optional
+    let response = client
+        .patch(format!("{api}/{machine}"))
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({"description": ""}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // Get the subtree and the ancestors
+    let tree = client
+        .get(format!("{api}/{building}/subtree"))
         .headers(user_header.clone())
         .send()
         .await
         .unwrap()
-        .json::<Vec<i32>>()
+        .json::<AssetTree>()
         .await
-        .unwrap()[0];
-
-    // Add some random tickets, ensure they're listable and readable.
-    for i in 1..=N {
-        let title = format!("My Ticket - {}", i);
-        let creator = format!("My Ticket Creator - {}", i);
-        let creator_mail = format!("testmail{}@test.com", i);
-        let creator_phone = format!("000000{}", i);
-        let description = format!("Once upon a time, at {}'o clock...", i);
-        let ticket = InTicket {
-            title: title.clone(),
-            creator: creator.clone(),
-            creator_mail: creator_mail.clone(),
-            creator_phone: creator_phone.clone(),
-            description: description.clone(),
-            time: NaiveDateTime::parse_from_str("2021-08-12T20:00:00", "%Y-%m-%dT%H:%M:%S")
-                .unwrap(),
-            asset_id: asset_id,
-            is_closed: false,
-        };
-
-        assert_eq!(
-            client
-                .post(api)
-                .json(&ticket)
-                .send()
-                .await
-                .unwrap()
-                .status(),
-            StatusCode::UNAUTHORIZED
-        );
+        .unwrap();
+    assert_eq!(tree.asset.id, building);
+    assert_eq!(tree.children.len(), 1);
+    assert_eq!(tree.children[0].asset.id, room);
+    assert_eq!(tree.children[0].children[0].asset.id, machine);
+    assert!(tree.children[0].children[0].children.is_empty());
+    let ancestors = client
+        .get(format!("{api}/{machine}/ancestors"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<Asset>>()
+        .await
+        .unwrap();
+    assert_eq!(
+        ancestors.iter().map(|a| a.id).collect::<Vec<_>>(),
+        vec![site, building, room]
+    );
+    let response = client
+        .get(format!("{api}/9999/ancestors"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
-        // Create a new ticket.
+    // An asset cannot be moved below itself, but can be moved elsewhere
+    let response = client
+        .patch(format!("{api}/{building}"))
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({ "parent_id": machine }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        response.json::<Problem>().await.unwrap().errors[0].field,
+        "parent_id"
+    );
+    for parent_id in [serde_json::Value::Null, serde_json::json!(site)] {
         let response = client
-            .post(api)
-            .json(&ticket)
+            .patch(format!("{api}/{building}"))
+            .headers(admin_header.clone())
+            .json(&serde_json::json!({ "parent_id": parent_id }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let asset = client
+            .get(format!("{api}/{building}"))
             .headers(user_header.clone())
             .send()
             .await
             .unwrap()
-            .json::<Ticket>()
-            .await;
-        assert_eq!(response.unwrap(), ticket);
+            .json::<Asset>()
+            .await
+            .unwrap();
+        assert_eq!(serde_json::json!(asset.parent_id), parent_id);
+    }
 
-        // Ensure the index shows one more ticket.
-        let list = client
-            .get(api)
+    // Tickets can be listed for an asset and all the assets below it
+    let mut tickets = Vec::new();
+    for asset_id in [building, machine] {
+        let ticket = client
+            .post(format!("{base}/api/tickets"))
             .headers(user_header.clone())
+            .json(&InTicket {
+                title: "Broken".to_string(),
+                creator: "Tree creator".to_string(),
+                creator_mail: String::new(),
+                creator_phone: String::new(),
+                description: "Broken".to_string(),
+                asset_id,
+                priority: Priority::Normal,
+            })
             .send()
             .await
             .unwrap()
-            .json::<Vec<i64>>()
+            .json::<Ticket>()
             .await
             .unwrap();
-        assert_eq!(list.len(), i);
-
-        // The last in the index is the new one; ensure contents match.
-        let last = list.last().unwrap();
+        tickets.push(ticket.id);
+    }
+    for (asset_id, count) in [(site, "2"), (building, "2"), (room, "1"), (machine, "1")] {
         let response = client
-            .get(format!("{}/{}", api, last))
+            .get(format!("{base}/api/tickets/all?within_asset_id={asset_id}"))
             .headers(user_header.clone())
             .send()
             .await
             .unwrap();
-        assert_eq!(response.json::<Ticket>().await.unwrap(), ticket);
+        assert_eq!(response.headers()["X-Total-Count"], count);
     }
 
-    // Test that the admin can list tickets as well
-    let list = client
-        .get(api)
-        .headers(admin_header.clone())
+    // The path of the asset comes along with the ticket and in the export
+    let ticket = client
+        .get(format!("{base}/api/tickets/{}", tickets[1]))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(ticket["asset_path"], "Site / Building / Room / Machine");
+    let exported = client
+        .get(format!(
+            "{base}/api/tickets/export?format=json&within_asset_id={room}"
+        ))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<ExportedTicket>>()
+        .await
+        .unwrap();
+    assert_eq!(exported.len(), 1);
+    assert_eq!(
+        exported[0].ticket.asset_path,
+        "Site / Building / Room / Machine"
+    );
+
+    // The QR codes of the assets open the new ticket form with the asset selected
+    let response = client
+        .get(format!("{api}/{machine}/qrcode"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client
+        .get(format!("{api}/{machine}/qrcode"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "image/png");
+    assert!(response.bytes().await.unwrap().starts_with(b"\x89PNG"));
+    let response = client
+        .get(format!("{api}/{machine}/qrcode?format=svg"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["content-type"], "image/svg+xml");
+    assert!(response.text().await.unwrap().contains("<svg"));
+    let response = client
+        .get(format!("{api}/9999/qrcode"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let labels = client
+        .get(format!("{api}/labels?ids={room},{machine}"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(labels.matches("<svg").count(), 2);
+    assert!(labels.contains("Site / Building / Room / Machine"));
+    // The URLs are HTML escaped
+    assert!(labels.contains(&format!("{PUBLIC_URL}/?asset_id&#x3D;{machine}")));
+    assert!(!labels.contains(&format!("{PUBLIC_URL}/?asset_id&#x3D;{site}")));
+    let labels = client
+        .get(format!("{api}/labels?within_asset_id={site}"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(labels.matches("<svg").count(), 4);
+    let response = client
+        .get(format!("{api}/labels?ids=1,two"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Archiving an asset archives the assets below it, which are no longer offered for new tickets
+    let response = client
+        .post(format!("{api}/{room}/archive"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .post(format!("{api}/{room}/archive"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let listed = |query: &'static str| {
+        let (client, user_header) = (client.clone(), user_header.clone());
+        async move {
+            client
+                .get(format!("{api}/all{query}"))
+                .headers(user_header)
+                .send()
+                .await
+                .unwrap()
+                .json::<Vec<Asset>>()
+                .await
+                .unwrap()
+        }
+    };
+    let active = listed("").await;
+    assert!(active.iter().any(|a| a.id == building));
+    assert!(!active.iter().any(|a| a.id == room || a.id == machine));
+    let all = listed("?include_archived=true").await;
+    assert!(
+        all.iter()
+            .filter(|a| a.id == room || a.id == machine)
+            .all(|a| a.archived)
+    );
+    assert_eq!(all.len(), active.len() + 2);
+    // Their tickets are still readable, but no ticket can be created for them
+    let response = client
+        .get(format!("{base}/api/tickets/{}", tickets[1]))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .post(format!("{base}/api/tickets"))
+        .headers(user_header.clone())
+        .json(&InTicket {
+            title: "Broken again".to_string(),
+            creator: "Tree creator".to_string(),
+            creator_mail: String::new(),
+            creator_phone: String::new(),
+            description: "Broken again".to_string(),
+            asset_id: machine,
+            priority: Priority::Normal,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    // Nor can a ticket be moved to them, while their own tickets can still be changed
+    let response = client
+        .patch(format!("{base}/api/tickets/{}", tickets[0]))
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({ "asset_id": machine }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = client
+        .patch(format!("{base}/api/tickets/{}", tickets[1]))
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({ "asset_id": machine, "title": "Broken machine" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client
+        .post(format!("{api}/{room}/unarchive"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(listed("").await.len(), all.len());
+
+    // Deleting an asset requires a confirmation, and reports what is deleted along with it
+    let response = client
+        .post(format!("{base}/api/comments"))
+        .headers(user_header.clone())
+        .json(&InComment {
+            ticket_id: tickets[1],
+            creator: "Tree creator".to_string(),
+            content: "Still broken".to_string(),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = client
+        .post(format!("{base}/api/tickets/photos/{}", tickets[1]))
+        .body(fs::read("test_img.jpg").unwrap())
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .post(format!("{base}/api/maintenance"))
+        .headers(admin_header.clone())
+        .json(&InMaintenancePlan {
+            asset_id: machine,
+            recurrence: "0 8 1 * *".to_string(),
+            title: "Service {{asset.title}}".to_string(),
+            description: "Monthly check".to_string(),
+            priority: Priority::Normal,
+            next_due: None,
+            enabled: true,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let report = client
+        .get(format!("{api}/{site}/deletion"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<DeletionReport>()
+        .await
+        .unwrap();
+    assert_eq!(
+        report,
+        DeletionReport {
+            assets: 4,
+            tickets: 2,
+            comments: 1,
+            attachments: 0,
+            maintenance_plans: 1
+        }
+    );
+    let response = client
+        .delete(format!("{api}/{site}"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Deleting an asset deletes the assets below it, and their tickets
+    let response = client
+        .delete(format!("{api}/{site}?confirm=true"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.json::<DeletionReport>().await.unwrap(), report);
+    for id in ids {
+        let response = client
+            .get(format!("{api}/{id}"))
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+    let response = client
+        .get(format!("{base}/api/tickets/{}", tickets[0]))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    // Along with their photos
+    let response = client
+        .get(format!("{base}/api/tickets/photos/{}", tickets[1]))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+async fn test_asset_categories(base: &str, client: &reqwest::Client) {
+    let (admin_header, user_header) = headers();
+    let api = &format!("{base}/api/categories");
+    let field = |name: &str, kind: FieldKind, required: bool, options: &[&str]| FieldDefinition {
+        name: name.to_string(),
+        kind,
+        required,
+        options: options.iter().map(|o| o.to_string()).collect(),
+    };
+    let printer = InCategory {
+        name: "Printer".to_string(),
+        fields: FieldDefinitions(vec![
+            field("vendor", FieldKind::Text, true, &[]),
+            field("pages", FieldKind::Number, false, &[]),
+            field("warranty_end", FieldKind::Date, false, &[]),
+            field("color", FieldKind::Enum, false, &["black", "color"]),
+        ]),
+    };
+
+    // Only admins can define the categories
+    let response = client
+        .post(api)
+        .headers(user_header.clone())
+        .json(&printer)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let category = client
+        .post(api)
+        .headers(admin_header.clone())
+        .json(&printer)
+        .send()
+        .await
+        .unwrap()
+        .json::<Category>()
+        .await
+        .unwrap();
+    assert_eq!(category, printer);
+    let response = client
+        .post(api)
+        .headers(admin_header.clone())
+        .json(&printer)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = client
+        .post(api)
+        .headers(admin_header.clone())
+        .json(&InCategory {
+            name: "Invalid".to_string(),
+            fields: FieldDefinitions(vec![
+                field("Bad name", FieldKind::Text, false, &[]),
+                field("level", FieldKind::Enum, false, &[]),
+                field("level", FieldKind::Number, false, &["high"]),
+            ]),
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let fields: Vec<String> = response
+        .json::<Problem>()
+        .await
+        .unwrap()
+        .errors
+        .into_iter()
+        .map(|e| e.field)
+        .collect();
+    assert_eq!(
+        fields,
+        vec![
+            "fields.0.name",
+            "fields.1.options",
+            "fields.2.name",
+            "fields.2.options"
+        ]
+    );
+    let categories = client
+        .get(api)
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<Category>>()
+        .await
+        .unwrap();
+    assert_eq!(categories, vec![category.clone()]);
+
+    // The custom fields of the assets are checked against their category
+    let values = |json: serde_json::Value| match json {
+        serde_json::Value::Object(map) => FieldValues(map),
+        _ => panic!("values must be an object"),
+    };
+    let assets_api = &format!("{base}/api/assets");
+    let mut assets = Vec::new();
+    for (serial_number, fields) in [
+        (
+            "SN-1",
+            serde_json::json!({"vendor": "HP", "pages": 1200, "warranty_end": "2030-06-30", "color": "black"}),
+        ),
+        ("SN-2", serde_json::json!({"vendor": "Canon", "pages": 3})),
+    ] {
+        let asset = InAsset {
+            title: format!("Printer {serial_number}"),
+            description: "Office printer".to_string(),
+            category_id: Some(category.id),
+            serial_number: serial_number.to_string(),
+            fields: values(fields),
+            ..Default::default()
+        };
+        let created = client
+            .post(assets_api)
+            .headers(admin_header.clone())
+            .json(&asset)
+            .send()
+            .await
+            .unwrap()
+            .json::<Asset>()
+            .await
+            .unwrap();
+        assert_eq!(created, asset);
+        assets.push(created);
+    }
+    for (asset, errors) in [
+        (
+            InAsset {
+                category_id: Some(category.id),
+                fields: values(serde_json::json!({
+                    "pages": "many",
+                    "warranty_end": "31/12/2030",
+                    "color": "red",
+                    "size": 3
+                })),
+                ..Default::default()
+            },
+            vec![
+                "fields.size",
+                "fields.vendor",
+                "fields.pages",
+                "fields.warranty_end",
+                "fields.color",
+            ],
+        ),
+        (
+            InAsset {
+                fields: values(serde_json::json!({"vendor": "HP"})),
+                ..Default::default()
+            },
+            vec!["fields"],
+        ),
+        (
+            InAsset {
+                category_id: Some(9999),
+                ..Default::default()
+            },
+            vec!["category_id"],
+        ),
+    ] {
+        let response = client
+            .post(assets_api)
+            .headers(admin_header.clone())
+            .json(&InAsset {
+                title: "Invalid printer".to_string(),
+                description: "Invalid printer".to_string(),
+                ..asset
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let fields: Vec<String> = response
+            .json::<Problem>()
+            .await
+            .unwrap()
+            .errors
+            .into_iter()
+            .map(|e| e.field)
+            .collect();
+        assert_eq!(fields, errors);
+    }
+
+    // The assets can be filtered by category, serial number and custom fields
+    for (query, count) in [
+        (format!("category_id={}", category.id), 2),
+        ("serial_number=SN-2".to_string(), 1),
+        ("field.vendor=Canon".to_string(), 1),
+        ("field.pages=1200&field.color=black".to_string(), 1),
+        ("field.pages=1200.0".to_string(), 1),
+        ("field.pages=1.2e3".to_string(), 1),
+        ("field.pages=1201".to_string(), 0),
+        ("field.color=color".to_string(), 0),
+    ] {
+        let listed = client
+            .get(format!("{assets_api}/all?{query}"))
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .json::<Vec<Asset>>()
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), count, "{query}");
+    }
+    for query in ["category_id=printer", "field.vendor%22=Canon"] {
+        let response = client
+            .get(format!("{assets_api}/all?{query}"))
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+    }
+
+    // The custom fields are part of the exports
+    let ticket = client
+        .post(format!("{base}/api/tickets"))
+        .headers(user_header.clone())
+        .json(&InTicket {
+            title: "Paper jam".to_string(),
+            creator: "Printer user".to_string(),
+            creator_mail: String::new(),
+            creator_phone: String::new(),
+            description: "Paper jam".to_string(),
+            asset_id: assets[1].id,
+            priority: Priority::Normal,
+        })
+        .send()
+        .await
+        .unwrap()
+        .json::<Ticket>()
+        .await
+        .unwrap();
+    let exported = client
+        .get(format!(
+            "{base}/api/tickets/export?format=json&asset_id={}",
+            assets[1].id
+        ))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<ExportedTicket>>()
+        .await
+        .unwrap();
+    assert_eq!(exported[0].ticket.ticket.id, ticket.id);
+    assert_eq!(exported[0].category.as_ref().unwrap().name, "Printer");
+    assert_eq!(exported[0].asset.fields.0["vendor"], "Canon");
+    let csv = client
+        .get(format!(
+            "{base}/api/tickets/export?format=csv&asset_id={}",
+            assets[1].id
+        ))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(csv.contains("Printer,SN-2,\"pages: 3\nvendor: Canon\""));
+
+    // The category of an asset can only be removed along with its custom fields
+    let asset_api = format!("{assets_api}/{}", assets[0].id);
+    let response = client
+        .patch(&asset_api)
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({"fields": {"vendor": "HP", "pages": 1500}}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client
+        .patch(&asset_api)
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({"category_id": null}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let response = client
+        .patch(&asset_api)
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({"category_id": null, "fields": {}}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let asset = client
+        .get(&asset_api)
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Asset>()
+        .await
+        .unwrap();
+    assert_eq!(asset.category_id, None);
+    assert_eq!(asset.fields, FieldValues::default());
+
+    // The fields of a category can only be changed as long as its assets match them
+    let response = client
+        .patch(format!("{api}/{}", category.id))
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({"fields": [
+            field("vendor", FieldKind::Text, true, &[]),
+            field("pages", FieldKind::Enum, false, &["1", "2"]),
+            field("location", FieldKind::Text, true, &[]),
+        ]}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let fields: Vec<String> = response
+        .json::<Problem>()
+        .await
+        .unwrap()
+        .errors
+        .into_iter()
+        .map(|e| e.field)
+        .collect();
+    assert_eq!(
+        fields,
+        vec![
+            format!("assets.{}.fields.pages", assets[1].id),
+            format!("assets.{}.fields.location", assets[1].id),
+        ]
+    );
+    let changed = client
+        .patch(format!("{api}/{}", category.id))
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({"fields": [
+            field("vendor", FieldKind::Text, true, &[]),
+            field("pages", FieldKind::Number, false, &[]),
+            field("location", FieldKind::Text, false, &[]),
+        ]}))
+        .send()
+        .await
+        .unwrap()
+        .json::<Category>()
+        .await
+        .unwrap();
+    assert_eq!(changed.fields.0.len(), 3);
+
+    // A category cannot be deleted while assets use it
+    let response = client
+        .delete(format!("{api}/{}", category.id))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    for asset in &assets {
+        let response = client
+            .delete(format!("{assets_api}/{}?confirm=true", asset.id))
+            .headers(admin_header.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let response = client
+        .delete(format!("{api}/{}", category.id))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = client
+        .get(format!("{api}/{}", category.id))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+async fn test_maintenance(base: &str, client: &reqwest::Client) {
+    let (admin_header, user_header) = headers();
+    let api = &format!("{base}/api/maintenance");
+    let asset = client
+        .post(format!("{base}/api/assets"))
+        .headers(admin_header.clone())
+        .json(&InAsset {
+            title: "Boiler".to_string(),
+            description: "Basement boiler".to_string(),
+            ..Default::default()
+        })
+        .send()
+        .await
+        .unwrap()
+        .json::<Asset>()
+        .await
+        .unwrap();
+    let now = Local::now().naive_local();
+    let due = (now - TimeDelta::days(1)).with_nanosecond(0).unwrap();
+    let plan = InMaintenancePlan {
+        asset_id: asset.id,
+        recurrence: "0 8 1 * *".to_string(),
+        title: "Service {{asset.title}} ({{due}})".to_string(),
+        description: "Monthly check of {{asset_path}}".to_string(),
+        priority: Priority::High,
+        next_due: Some(due),
+        enabled: true,
+    };
+
+    // Only admins can plan the maintenance, with valid recurrences and templates
+    let response = client
+        .post(api)
+        .headers(user_header.clone())
+        .json(&plan)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .post(api)
+        .headers(admin_header.clone())
+        .json(&InMaintenancePlan {
+            recurrence: "every month".to_string(),
+            title: "Service {{#if}}".to_string(),
+            ..plan.clone()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let fields: Vec<String> = response
+        .json::<Problem>()
+        .await
+        .unwrap()
+        .errors
+        .into_iter()
+        .map(|e| e.field)
+        .collect();
+    assert_eq!(fields, vec!["recurrence", "title"]);
+    let due_plan = client
+        .post(api)
+        .headers(admin_header.clone())
+        .json(&plan)
+        .send()
+        .await
+        .unwrap()
+        .json::<MaintenancePlan>()
+        .await
+        .unwrap();
+    assert_eq!(due_plan, plan);
+    // Without a due date, the plan is first due on the next date of its recurrence
+    let later_plan = client
+        .post(api)
+        .headers(admin_header.clone())
+        .json(&InMaintenancePlan {
+            next_due: None,
+            ..plan.clone()
+        })
+        .send()
+        .await
+        .unwrap()
+        .json::<MaintenancePlan>()
+        .await
+        .unwrap();
+    assert!(later_plan.next_due > now);
+
+    // The plans come due open a ticket, once
+    let opened = client
+        .post(format!("{api}/generate"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<Ticket>>()
+        .await
+        .unwrap();
+    assert_eq!(opened.len(), 1);
+    assert_eq!(
+        opened[0].title,
+        format!("Service Boiler ({})", due.format("%Y-%m-%d"))
+    );
+    assert_eq!(opened[0].description, "Monthly check of Boiler");
+    assert_eq!(opened[0].creator, "Preventive maintenance");
+    assert_eq!(opened[0].priority, Priority::High);
+    assert_eq!(opened[0].asset_id, asset.id);
+    let updated = client
+        .get(format!("{api}/{}", due_plan.id))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<MaintenancePlan>()
+        .await
+        .unwrap();
+    assert!(updated.next_due > now);
+    let opened_again = client
+        .post(format!("{api}/generate"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<Ticket>>()
+        .await
+        .unwrap();
+    assert!(opened_again.is_empty());
+
+    // The plan keeps the history of its tickets, even once they are deleted
+    let generated = client
+        .get(format!("{api}/{}/tickets", due_plan.id))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<MaintenanceTicket>>()
+        .await
+        .unwrap();
+    assert_eq!(generated.len(), 1);
+    assert_eq!(generated[0].ticket_id, Some(opened[0].id));
+    assert_eq!(generated[0].due, due);
+    let response = client
+        .delete(format!("{base}/api/tickets/{}", opened[0].id))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let generated = client
+        .get(format!("{api}/{}/tickets", due_plan.id))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<MaintenanceTicket>>()
+        .await
+        .unwrap();
+    assert_eq!(generated[0].ticket_id, None);
+
+    // The disabled plans open no ticket
+    let response = client
+        .patch(format!("{api}/{}", later_plan.id))
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({ "next_due": due, "enabled": false }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let opened = client
+        .post(format!("{api}/generate"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<Ticket>>()
+        .await
+        .unwrap();
+    assert!(opened.is_empty());
+
+    // Deleting the asset deletes its plans
+    let response = client
+        .delete(format!("{base}/api/assets/{}?confirm=true", asset.id))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let plans = client
+        .get(api)
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<MaintenancePlan>>()
+        .await
+        .unwrap();
+    assert!(plans.is_empty());
+    let response = client
+        .get(format!("{api}/{}/tickets", due_plan.id))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+async fn test_tickets(base: &str, client: &reqwest::Client) {
+    // Number of tickets we're going to create/read/delete.
+    const N: usize = 20;
+    let (admin_header, user_header) = headers();
+
+    let api = &format!("{base}/api/tickets");
+
+    // Clear everything from the database.
+    assert_eq!(
+        client.delete(api).send().await.unwrap().status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        client
+            .delete(api)
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        client
+            .delete(api)
+            .headers(admin_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::OK
+    );
+    assert_eq!(
+        client
+            .get(api)
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .json::<Option<Vec<i64>>>()
+            .await
+            .unwrap(),
+        Some(vec![])
+    );
+
+    // Add a ticket to a non existing asset (must fail)
+    let ticket = InTicket {
+        title: "test".to_string(),
+        creator: "test".to_string(),
+        creator_mail: "test@test.com".to_string(),
+        creator_phone: "0102030405".to_string(),
+        description: "test".to_string(),
+        asset_id: 1,
+        priority: Priority::Normal,
+    };
+    // Create a new ticket.
+    let response = client
+        .post(api)
+        .headers(user_header.clone())
+        .json(&ticket)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Add an asset
+    let asset = InAsset {
+        title: "MyAsset".to_string(),
+        description: "MyAssetDescription".to_string(),
+        parent_id: None,
+        ..Default::default()
+    };
+    let response = client
+        .post(format!("{base}/api/assets"))
+        .headers(admin_header.clone())
+        .json(&asset)
+        .send()
+        .await
+        .unwrap()
+        .json::<InAsset>()
+        .await
+        .unwrap();
+    assert_eq!(response, asset);
+
+    // Get a valid asset id
+    let asset_id = client
+        .get(format!("{base}/api/assets"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<i32>>()
+        .await
+        .unwrap()[0];
+
+    // Invalid tickets are refused, with the errors of every field
+    let response = client
+        .post(api)
+        .headers(user_header.clone())
+        .json(&InTicket {
+            title: "".to_string(),
+            creator_mail: "not an address".to_string(),
+            creator_phone: "call me".to_string(),
+            asset_id,
+            ..ticket.clone()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let problem = response.json::<Problem>().await.unwrap();
+    assert_eq!(problem.code, "validation_failed");
+    assert_eq!(
+        problem
+            .errors
+            .iter()
+            .map(|e| e.field.as_str())
+            .collect::<Vec<_>>(),
+        vec!["title", "creator_mail", "creator_phone"]
+    );
+    let response = client
+        .post(format!("{base}/api/assets"))
+        .headers(admin_header.clone())
+        .json(&InAsset {
+            title: "x".repeat(201),
+            description: "Too long title".to_string(),
+            parent_id: None,
+            ..Default::default()
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        response.json::<Problem>().await.unwrap().errors,
+        vec![FieldError {
+            field: "title".to_string(),
+            message: "must be at most 200 characters long".to_string()
+        }]
+    );
+
+    // Missing fields and malformed bodies are reported as problems too
+    let response = client
+        .post(api)
+        .headers(user_header.clone())
+        .json(&serde_json::json!({ "asset_id": asset_id }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        response.json::<Problem>().await.unwrap().errors,
+        vec![FieldError {
+            field: "title".to_string(),
+            message: "is required".to_string()
+        }]
+    );
+    let response = client
+        .post(api)
+        .headers(user_header.clone())
+        .header("Content-Type", "application/json")
+        .body("{")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Add some random tickets, ensure they're listable and readable.
+    for i in 1..=N {
+        let title = format!("My Ticket - {}", i);
+        let creator = format!("My Ticket Creator - {}", i);
+        let creator_mail = format!("testmail{}@test.com", i);
+        let creator_phone = format!("000000{}", i);
+        let description = format!("Once upon a time, at {}'o clock...", i);
+        let ticket = InTicket {
+            title: title.clone(),
+            creator: creator.clone(),
+            creator_mail: creator_mail.clone(),
+            creator_phone: creator_phone.clone(),
+            description: description.clone(),
+            asset_id,
+            priority: Priority::Normal,
+        };
+
+        assert_eq!(
+            client
+                .post(api)
+                .json(&ticket)
+                .send()
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Create a new ticket.
+        let response = client
+            .post(api)
+            .json(&ticket)
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .json::<Ticket>()
+            .await;
+        assert_eq!(response.unwrap(), ticket);
+
+        // Ensure the index shows one more ticket.
+        let list = client
+            .get(api)
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .json::<Vec<i64>>()
+            .await
+            .unwrap();
+        assert_eq!(list.len(), i);
+
+        // The last in the index is the new one; ensure contents match.
+        let last = list.last().unwrap();
+        let response = client
+            .get(format!("{}/{}", api, last))
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.json::<Ticket>().await.unwrap(), ticket);
+    }
+
+    // Test that the admin can list tickets as well
+    let list = client
+        .get(api)
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<i64>>()
+        .await
+        .unwrap();
+    assert_eq!(list.len(), N);
+
+    // Filter, sort and paginate the tickets
+    let response = client
+        .get(format!(
+            "{api}/all?limit=5&offset=5&sort=title&direction=asc"
+        ))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["X-Total-Count"], N.to_string());
+    let page = response.json::<Vec<Ticket>>().await.unwrap();
+    assert_eq!(
+        page.iter().map(|t| t.title.as_str()).collect::<Vec<&str>>(),
+        vec![
+            "My Ticket - 14",
+            "My Ticket - 15",
+            "My Ticket - 16",
+            "My Ticket - 17",
+            "My Ticket - 18"
+        ]
+    );
+    let response = client
+        .get(format!(
+            "{api}/all?creator=My%20Ticket%20Creator%20-%203&asset_id={asset_id}&is_open=true"
+        ))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["X-Total-Count"], "1");
+    assert_eq!(
+        response.json::<Vec<Ticket>>().await.unwrap()[0].title,
+        "My Ticket - 3"
+    );
+    let response = client
+        .get(format!(
+            "{api}/all?from={}",
+            (Local::now() + TimeDelta::days(1)).format("%Y-%m-%dT%H:%M:%S")
+        ))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["X-Total-Count"], "0");
+    let response = client
+        .get(format!("{api}/all?sort=unknown"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Patch the tickets
+    for _ in 1..=N {
+        // Get a valid ID from the index.
+        let list = client
+            .get(api)
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .json::<Vec<i64>>()
+            .await
+            .unwrap();
+        let id = list.first().expect("have ticket");
+
+        let original = client
+            .get(format!("{}/{}", api, id))
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .json::<Ticket>()
+            .await
+            .unwrap();
+
+        // Patch that ticket.
+        let ticket = Ticket {
+            id: i32::try_from(*id).unwrap(),
+            title: "patched title".to_string(),
+            creator: "patched creator".to_string(),
+            creator_mail: "patchedmail@test.com".to_string(),
+            creator_phone: "010203040506".to_string(),
+            description: format!("Once upon a time, at {}'o clock...", id),
+            time: NaiveDateTime::parse_from_str("2021-08-12T20:00:00", "%Y-%m-%dT%H:%M:%S")
+                .unwrap(),
+            asset_id,
+            status: TicketStatus::Closed,
+            assignee_id: None,
+            priority: Priority::High,
+            // Patching the ticket again with the same values changes nothing, not even its version
+            version: original.version + i32::from(original.title != "patched title"),
+        };
+        let response = client
+            .patch(format!("{}/{}", api, id))
+            .json(&ticket)
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        // The ticket cannot refer to a missing asset
+        let response = client
+            .patch(format!("{}/{}", api, id))
+            .headers(admin_header.clone())
+            .json(&Ticket {
+                asset_id: 9999,
+                ..ticket.clone()
+            })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            response.json::<Problem>().await.unwrap().code,
+            "validation_failed"
+        );
+        let response = client
+            .patch(format!("{}/{}", api, id))
+            .headers(admin_header.clone())
+            .json(&ticket)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        // Check that ticket is patched, but keeps the time it was created at
+        let response = client
+            .get(format!("{}/{}", api, id))
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.json::<Ticket>().await.unwrap(),
+            Ticket {
+                time: original.time,
+                ..ticket
+            }
+        );
+    }
+
+    // New tickets always start the workflow, whatever status is sent
+    let mut body = serde_json::json!(InTicket {
+        asset_id,
+        ..ticket.clone()
+    });
+    body["status"] = serde_json::json!("closed");
+    let created = client
+        .post(api)
+        .headers(user_header.clone())
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+        .json::<Ticket>()
+        .await
+        .unwrap();
+    assert_eq!(created.status, TicketStatus::New);
+    let response = client
+        .delete(format!("{api}/{}", created.id))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Status changes must follow the workflow
+    let workflow = client
+        .get(format!("{api}/workflow"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(workflow, serde_json::to_value(Workflow::default()).unwrap());
+    let id = client
+        .get(api)
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<i32>>()
+        .await
+        .unwrap()[0];
+    let mut ticket = client
+        .get(format!("{api}/{id}"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Ticket>()
+        .await
+        .unwrap();
+    assert_eq!(ticket.status, TicketStatus::Closed);
+    ticket.status = TicketStatus::Resolved;
+    let response = client
+        .patch(format!("{api}/{id}"))
+        .headers(admin_header.clone())
+        .json(&ticket)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    for status in [
+        TicketStatus::New,
+        TicketStatus::InProgress,
+        TicketStatus::Resolved,
+    ] {
+        ticket.status = status;
+        let response = client
+            .patch(format!("{api}/{id}"))
+            .headers(admin_header.clone())
+            .json(&ticket)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+    let response = client
+        .get(format!("{api}/all?is_open=false"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["X-Total-Count"], "1");
+    let response = client
+        .get(format!("{api}/all?status=resolved"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.json::<Vec<Ticket>>().await.unwrap()[0].id, id);
+
+    // Only the given fields are patched
+    let response = client
+        .patch(format!("{api}/{id}"))
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({"priority": "low", "creator_phone": ""}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let etag = response.headers()["ETag"].clone();
+    let patched = client
+        .get(format!("{api}/{id}"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Ticket>()
+        .await
+        .unwrap();
+    assert_eq!(
+        patched,
+        Ticket {
+            priority: Priority::Low,
+            creator_phone: String::new(),
+            version: patched.version,
+            ..ticket.clone()
+        }
+    );
+    assert_eq!(etag, format!("\"{}\"", patched.version));
+    let response = client
+        .patch(format!("{api}/{id}"))
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({"title": " "}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let response = client
+        .patch(format!("{api}/{id}"))
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // Tickets are closed and reopened with dedicated actions, which honour the If-Match header too
+    let response = client
+        .post(format!("{api}/{id}/close"))
+        .headers(admin_header.clone())
+        .header("If-Match", "\"0\"")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    let response = client
+        .post(format!("{api}/{id}/close"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .post(format!("{api}/{id}/reopen"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    for (action, status) in [
+        ("close", TicketStatus::Closed),
+        ("close", TicketStatus::Closed),
+        ("reopen", TicketStatus::New),
+    ] {
+        let response = client
+            .post(format!("{api}/{id}/{action}"))
+            .headers(admin_header.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = client
+            .get(format!("{api}/{id}"))
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .json::<Ticket>()
+            .await
+            .unwrap();
+        assert_eq!(response.status, status);
+    }
+    // Closing a closed ticket changes nothing, so that the version held by the other clients stays valid
+    let mut etags = Vec::new();
+    for action in ["close", "close", "reopen"] {
+        let response = client
+            .post(format!("{api}/{id}/{action}"))
+            .headers(admin_header.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        etags.push(response.headers()["etag"].clone());
+    }
+    assert_eq!(etags[0], etags[1]);
+    assert_ne!(etags[1], etags[2]);
+    for status in ["in_progress", "resolved"] {
+        let response = client
+            .patch(format!("{api}/{id}"))
+            .headers(admin_header.clone())
+            .json(&serde_json::json!({ "status": status }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    // Assign the ticket to a desk member
+    let desk_member = client
+        .post(format!("{base}/api/users"))
+        .headers(admin_header.clone())
+        .json(&InUser {
+            name: "Desk Member".to_string(),
+            role: Role::Admin,
+            mail: "desk@test.com".to_string(),
+        })
+        .send()
+        .await
+        .unwrap()
+        .json::<UserWithToken>()
+        .await
+        .unwrap();
+    let mut desk_header = HeaderMap::new();
+    desk_header.insert("X-TOKEN", desk_member.token.parse().unwrap());
+    let desk_member = desk_member.user;
+    let response = client
+        .post(format!("{api}/{id}/assign"))
+        .headers(user_header.clone())
+        .json(&Assignment {
+            assignee_id: desk_member.id,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .post(format!("{api}/{id}/assign"))
+        .headers(admin_header.clone())
+        .json(&serde_json::json!({"assignee_id": "someone"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/problem+json"
+    );
+    let response = client
+        .post(format!("{api}/{id}/assign"))
+        .headers(admin_header.clone())
+        .json(&Assignment {
+            assignee_id: desk_member.id,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client
+        .get(format!("{api}/all?assignee_id={}", desk_member.id))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<Ticket>>()
+        .await
+        .unwrap();
+    assert_eq!(response.len(), 1);
+    assert_eq!(response[0].id, id);
+    assert_eq!(response[0].assignee_id, Some(desk_member.id));
+    // Patching the ticket does not change its assignee
+    let mut patched = response[0].clone();
+    patched.assignee_id = None;
+    let response = client
+        .patch(format!("{api}/{id}"))
+        .headers(admin_header.clone())
+        .json(&patched)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client
+        .get(format!("{api}/all?is_assigned=true"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["X-Total-Count"], "1");
+    // Only desk members can be assigned
+    let requester = client
+        .get(format!("{base}/api/users/me"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<User>()
+        .await
+        .unwrap();
+    let response = client
+        .post(format!("{api}/{id}/assign"))
+        .headers(admin_header.clone())
+        .json(&Assignment {
+            assignee_id: requester.id,
+        })
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    // Self assign, then unassign
+    let response = client
+        .post(format!("{api}/{id}/self_assign"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let admin = client
+        .get(format!("{base}/api/users/me"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<User>()
+        .await
+        .unwrap();
+    let response = client
+        .get(format!("{api}/all?assignee_id={}", admin.id))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["X-Total-Count"], "1");
+    let response = client
+        .post(format!("{api}/{id}/unassign"))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client
+        .get(format!("{api}/all?is_assigned=false"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["X-Total-Count"], N.to_string());
+
+    // The server records the creation time, and the creator when the token is personal
+    let mut before = Local::now().naive_local();
+    let own = client
+        .post(api)
+        .headers(desk_header.clone())
+        .json(&InTicket {
+            title: "Own ticket".to_string(),
+            creator: "Someone else".to_string(),
+            creator_mail: String::new(),
+            creator_phone: String::new(),
+            description: String::new(),
+            asset_id: patched.asset_id,
+            priority: Priority::Normal,
+        })
+        .send()
+        .await
+        .unwrap()
+        .json::<Ticket>()
+        .await
+        .unwrap();
+    assert_eq!(own.creator, "Desk Member");
+    assert!(own.time >= before && own.time <= Local::now().naive_local());
+    before = Local::now().naive_local();
+    let comment = client
+        .post(format!("{base}/api/comments"))
+        .headers(desk_header.clone())
+        .json(&serde_json::json!({"ticket_id": own.id, "content": "Own comment"}))
+        .send()
+        .await
+        .unwrap()
+        .json::<Comment>()
+        .await
+        .unwrap();
+    assert_eq!(comment.creator, "Desk Member");
+    assert!(comment.time >= before && comment.time <= Local::now().naive_local());
+    // The shared tokens may leave the creator of the comments empty, as the one of the tickets
+    let response = client
+        .post(format!("{base}/api/comments"))
+        .headers(user_header.clone())
+        .json(&serde_json::json!({"ticket_id": own.id, "content": "Anonymous comment"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let anonymous = response.json::<Comment>().await.unwrap();
+    assert_eq!(anonymous.creator, "");
+    let response = client
+        .delete(format!("{api}/{}", own.id))
+        .headers(admin_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // The deletion of its comments is recorded along with the one of the ticket
+    let history = client
+        .get(format!("{api}/{}/history", own.id))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<TicketEvent>>()
+        .await
+        .unwrap();
+    let deletions: Vec<(EventEntity, i32)> = history
+        .iter()
+        .filter(|e| e.action == EventAction::Delete)
+        .map(|e| (e.entity, e.entity_id))
+        .collect();
+    assert_eq!(
+        deletions,
+        vec![
+            (EventEntity::Comment, comment.id),
+            (EventEntity::Comment, anonymous.id),
+            (EventEntity::Ticket, own.id)
+        ]
+    );
+
+    // Every change of the ticket is recorded in its history
+    let history = client
+        .get(format!("{api}/{id}/history"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<TicketEvent>>()
+        .await
+        .unwrap();
+    // The events of the asset of the ticket come along
+    assert_eq!(history[0].entity, EventEntity::Asset);
+    assert_eq!(history[0].action, EventAction::Create);
+    let created = history
+        .iter()
+        .find(|e| e.entity == EventEntity::Ticket)
+        .unwrap();
+    assert_eq!(created.action, EventAction::Create);
+    assert_eq!(created.actor, "user");
+    let close = history
+        .iter()
+        .find(|e| e.action == EventAction::Close)
+        .expect("ticket closing is recorded");
+    assert_eq!(close.actor, "admin");
+    assert!(close.changes.0.contains(&FieldChange {
+        field: "status".to_string(),
+        old: serde_json::json!("new"),
+        new: serde_json::json!("closed"),
+    }));
+    assert!(close.changes.0.iter().any(|c| c.field == "title"));
+    assert!(history.iter().any(|e| e.changes.0.contains(&FieldChange {
+        field: "assignee_id".to_string(),
+        old: serde_json::Value::Null,
+        new: serde_json::json!(desk_member.id),
+    })));
+    let last = history.last().unwrap();
+    assert_eq!(last.action, EventAction::Update);
+    assert_eq!(
+        last.changes.0,
+        vec![FieldChange {
+            field: "assignee_id".to_string(),
+            old: serde_json::json!(admin.id),
+            new: serde_json::Value::Null,
+        }]
+    );
+    let response = client
+        .get(format!("{api}/{}/history", id + 1000))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    // The history is part of the export
+    let export = client
+        .get(format!("{api}/export"))
+        .headers(user_header.clone())
         .send()
         .await
         .unwrap()
-        .json::<Vec<i64>>()
+        .text()
         .await
         .unwrap();
-    assert_eq!(list.len(), N);
-
-    // Patch the tickets
-    for _ in 1..=N {
-        // Get a valid ID from the index.
-        let list = client
-            .get(api)
-            .headers(user_header.clone())
-            .send()
-            .await
-            .unwrap()
-            .json::<Vec<i64>>()
-            .await
-            .unwrap();
-        let id = list.get(0).expect("have ticket");
-
-        // Patch that ticket.
-        let ticket = Ticket {
-            id: i32::try_from(*id).unwrap(),
-            title: "patched title".to_string(),
-            creator: "patched creator".to_string(),
-            creator_mail: "patchedmail@test.com".to_string(),
-            creator_phone: "010203040506".to_string(),
-            description: format!("Once upon a time, at {}'o clock...", id),
-            time: NaiveDateTime::parse_from_str("2021-08-12T20:00:00", "%Y-%m-%dT%H:%M:%S")
-                .unwrap(),
-            asset_id: asset_id,
-            is_closed: true,
-        };
-        let response = client
-            .patch(format!("{}/{}", api, id))
-            .json(&ticket)
-            .headers(user_header.clone())
-            .send()
-            .await
-            .unwrap();
-        assert_eq!(response.status(), StatusCode::FORBIDDEN);
-        let response = client
-            .patch(format!("{}/{}", api, id))
-            .headers(admin_header.clone())
-            .json(&ticket)
-            .send()
-            .await
-            .unwrap();
-        assert_eq!(response.status(), StatusCode::NO_CONTENT);
-        // Check that ticket is patched
-        let response = client
-            .get(format!("{}/{}", api, id))
-            .headers(user_header.clone())
-            .send()
-            .await
-            .unwrap();
-        assert_eq!(response.json::<Ticket>().await.unwrap(), ticket);
-    }
+    assert!(export.contains("admin - close ticket"));
+    assert!(export.contains("status : new → closed"));
+    // The export is available as JSON and CSV as well, with the same filters
+    let exported = client
+        .get(format!("{api}/export?format=json&status=resolved"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<ExportedTicket>>()
+        .await
+        .unwrap();
+    assert_eq!(exported.len(), 1);
+    assert_eq!(exported[0].ticket.ticket.id, id);
+    assert_eq!(exported[0].asset.id, exported[0].ticket.ticket.asset_id);
+    assert!(!exported[0].history.is_empty());
+    let response = client
+        .get(format!("{api}/export?format=csv&status=resolved"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+    let csv = response.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("id,time,title,description,creator,"));
+    assert!(
+        lines[0]
+            .ends_with(",asset_id,asset_title,asset_description,asset_path,asset_category,asset_serial_number,asset_fields,comment_count,comments")
+    );
+    assert!(lines[1].starts_with(&format!(
+        "{id},{},patched title,",
+        exported[0].ticket.ticket.time.format("%Y-%m-%d %H:%M")
+    )));
+    let exported = client
+        .get(format!(
+            "{api}/export?format=json&asset_id={}&from={}&to={}",
+            exported[0].asset.id,
+            (Local::now() - TimeDelta::days(1)).format("%Y-%m-%dT%H:%M:%S"),
+            (Local::now() + TimeDelta::days(1)).format("%Y-%m-%dT%H:%M:%S")
+        ))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<ExportedTicket>>()
+        .await
+        .unwrap();
+    assert_eq!(exported.len(), N);
+    let exported = client
+        .get(format!("{api}/export?format=json&from=2030-01-01T00:00:00"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<ExportedTicket>>()
+        .await
+        .unwrap();
+    assert!(exported.is_empty());
+    let response = client
+        .get(format!("{api}/export?format=xlsx&is_open=true"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+    );
+    let xlsx = response.bytes().await.unwrap();
+    assert!(xlsx.starts_with(b"PK"));
+    // The workbook has a sheet for the tickets, the comments and the assets
+    let names = String::from_utf8_lossy(&xlsx);
+    assert!(names.contains("xl/worksheets/sheet3.xml"));
+    assert!(!names.contains("xl/worksheets/sheet4.xml"));
+    let response = client
+        .get(format!("{api}/export?format=pdf"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
 
     // Test a photo upload without a user token
     let response = client
@@ -543,6 +3231,112 @@ async fn test_tickets(base: &str, client: &reqwest::Client) {
         .unwrap();
     assert_eq!(response.bytes().await.unwrap(), img_body);
 
+    // Attach several files to a ticket: images are resized and stored as JPEG...
+    let response = client
+        .post(format!("{api}/{id}/attachments?filename=photo.jpeg"))
+        .headers(user_header.clone())
+        .body(img_body.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let photo = response.json::<Attachment>().await.unwrap();
+    assert_eq!(photo.ticket_id, id);
+    assert_eq!(photo.filename, "photo.jpg");
+    assert_eq!(photo.mime_type, "image/jpeg");
+    // ...while other files are stored as is
+    let pdf_body = b"%PDF-1.4 invoice".to_vec();
+    let response = client
+        .post(format!("{api}/{id}/attachments?filename=facture été.pdf"))
+        .headers(user_header.clone())
+        .header("Content-Type", "application/pdf")
+        .body(pdf_body.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let pdf = response.json::<Attachment>().await.unwrap();
+    assert_eq!(pdf.size, pdf_body.len() as i64);
+    let attachments = client
+        .get(format!("{api}/{id}/attachments"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<Attachment>>()
+        .await
+        .unwrap();
+    assert_eq!(attachments, vec![photo.clone(), pdf.clone()]);
+    let response = client
+        .get(format!("{api}/{id}/attachments/{}", pdf.id))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["Content-Type"], "application/pdf");
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        "attachment; filename=\"facture _t_.pdf\"; filename*=UTF-8''facture%20%C3%A9t%C3%A9.pdf"
+    );
+    assert_eq!(response.bytes().await.unwrap(), pdf_body);
+    // Files are attached to existing tickets and comments, within the size limit
+    let response = client
+        .post(format!("{api}/{}/attachments?filename=a.pdf", id + 1000))
+        .headers(user_header.clone())
+        .body(pdf_body.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client
+        .post(format!(
+            "{api}/{id}/attachments?filename=a.pdf&comment_id=1000"
+        ))
+        .headers(user_header.clone())
+        .body(pdf_body.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = client
+        .post(format!("{api}/{id}/attachments?filename=big.bin"))
+        .headers(user_header.clone())
+        .body(vec![0u8; 1024 * 1024 + 1])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    // The file name is required, and limited as the other names
+    for filename in [String::new(), "a".repeat(101)] {
+        let response = client
+            .post(format!("{api}/{id}/attachments?filename={filename}"))
+            .headers(user_header.clone())
+            .body(pdf_body.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            response.json::<Problem>().await.unwrap().errors[0].field,
+            "filename"
+        );
+    }
+    // Delete an attachment
+    let response = client
+        .delete(format!("{api}/{id}/attachments/{}", photo.id))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client
+        .get(format!("{api}/{id}/attachments/{}", photo.id))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
     // Now delete all of the tickets.
     for _ in 1..=N {
         // Get a valid ID from the index.
@@ -555,7 +3349,7 @@ async fn test_tickets(base: &str, client: &reqwest::Client) {
             .json::<Vec<i64>>()
             .await
             .unwrap();
-        let id = list.get(0).expect("have ticket");
+        let id = list.first().expect("have ticket");
 
         // Delete that ticket.
         let response = client
@@ -586,6 +3380,31 @@ async fn test_tickets(base: &str, client: &reqwest::Client) {
         .unwrap();
     assert!(list.is_empty());
 
+    // Check that the attachments are gone too
+    assert!(!Path::new(&format!("{}/tickets/attachments/{}", data_dir(), pdf.id)).exists());
+    // The attachments are recorded in the history, whether deleted alone or along with their ticket
+    let history = client
+        .get(format!("{api}/{id}/history"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<TicketEvent>>()
+        .await
+        .unwrap();
+    let attachment_events = |action| {
+        history
+            .iter()
+            .filter(|e| e.entity == EventEntity::Attachment && e.action == action)
+            .map(|e| e.entity_id)
+            .collect::<Vec<i32>>()
+    };
+    assert!(attachment_events(EventAction::Create).contains(&pdf.id));
+    assert_eq!(
+        attachment_events(EventAction::Delete),
+        vec![photo.id, pdf.id]
+    );
+
     // Check that the photo is gone too
     let response = client
         .get(format!("{}/photos/{}", api, 1))
@@ -654,7 +3473,6 @@ async fn test_comments(base: &str, client: &reqwest::Client) {
     let comment = InComment {
         creator: "test".to_string(),
         content: "test".to_string(),
-        time: NaiveDateTime::parse_from_str("2021-08-12T20:00:00", "%Y-%m-%dT%H:%M:%S").unwrap(),
         ticket_id: 1,
     };
     // Create a new comment.
@@ -671,6 +3489,8 @@ async fn test_comments(base: &str, client: &reqwest::Client) {
     let asset = InAsset {
         title: "MyAsset".to_string(),
         description: "MyAssetDescription".to_string(),
+        parent_id: None,
+        ..Default::default()
     };
     let response = client
         .post(format!("{base}/api/assets"))
@@ -702,21 +3522,20 @@ async fn test_comments(base: &str, client: &reqwest::Client) {
         creator_mail: "test@test.com".to_string(),
         creator_phone: "01020304".to_string(),
         description: "MyDescription".to_string(),
-        time: NaiveDateTime::parse_from_str("2021-08-12T20:00:00", "%Y-%m-%dT%H:%M:%S").unwrap(),
-        asset_id: asset_id,
-        is_closed: false,
+        asset_id,
+        priority: Priority::Urgent,
     };
-    let response = client
+    let created = client
         .post(format!("{base}/api/tickets"))
         .headers(user_header.clone())
         .json(&ticket)
         .send()
         .await
         .unwrap()
-        .json::<InTicket>()
+        .json::<Ticket>()
         .await
         .unwrap();
-    assert_eq!(response, ticket);
+    assert_eq!(created, ticket);
 
     // Get a valid ticket id
     let ticket_id = client
@@ -729,14 +3548,77 @@ async fn test_comments(base: &str, client: &reqwest::Client) {
         .await
         .unwrap()[0];
 
+    // The service level dues are computed from the ticket priority
+    let response = client
+        .get(format!("{base}/api/tickets/{ticket_id}"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(response["priority"], "urgent");
+    assert_eq!(
+        serde_json::from_value::<NaiveDateTime>(response["response_due"].clone()).unwrap(),
+        created.time + TimeDelta::hours(1)
+    );
+    assert_eq!(
+        serde_json::from_value::<NaiveDateTime>(response["resolution_due"].clone()).unwrap(),
+        created.time + TimeDelta::hours(8)
+    );
+
+    // The ticket is about to breach its response target: it is notified once to the desk
+    let alerts = client
+        .get(format!("{base}/api/tickets/mail_sla"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<SlaAlert>>()
+        .await
+        .unwrap();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].kind, SlaAlertKind::ResponseWarning);
+    assert_eq!(alerts[0].ticket.id, ticket_id);
+    let alerts = client
+        .get(format!("{base}/api/tickets/mail_sla"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<SlaAlert>>()
+        .await
+        .unwrap();
+    assert!(alerts.is_empty());
+    // It is notified again once closed and reopened
+    for action in ["close", "reopen"] {
+        let response = client
+            .post(format!("{base}/api/tickets/{ticket_id}/{action}"))
+            .headers(admin_header.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+    let alerts = client
+        .get(format!("{base}/api/tickets/mail_sla"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<SlaAlert>>()
+        .await
+        .unwrap();
+    assert_eq!(alerts.len(), 1);
+    assert_eq!(alerts[0].ticket.id, ticket_id);
+
     // Add some random comments, ensure they're listable and readable.
     for i in 1..=N {
         let comment = InComment {
-            ticket_id: ticket_id,
+            ticket_id,
             creator: format!("My Comment Creator - {}", i),
             content: format!("My Comment - {}", i),
-            time: NaiveDateTime::parse_from_str("2021-08-12T20:00:00", "%Y-%m-%dT%H:%M:%S")
-                .unwrap(),
         };
 
         assert_eq!(
@@ -789,6 +3671,51 @@ async fn test_comments(base: &str, client: &reqwest::Client) {
         assert_eq!(response, comment);
     }
 
+    // Search the ticket by its description and by its comments
+    let search_api = &format!("{base}/api/search");
+    assert_eq!(
+        client
+            .get(format!("{search_api}?q=mydesc"))
+            .send()
+            .await
+            .unwrap()
+            .status(),
+        StatusCode::UNAUTHORIZED
+    );
+    let hits = client
+        .get(format!("{search_api}?q=mydesc"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<SearchHit>>()
+        .await
+        .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].ticket.id, ticket_id);
+    assert_eq!(hits[0].snippet, "<mark>MyDescription</mark>");
+    let hits = client
+        .get(format!("{search_api}?q=comment%207"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<SearchHit>>()
+        .await
+        .unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].snippet, "My <mark>Comment</mark> - <mark>7</mark>");
+    let hits = client
+        .get(format!("{search_api}?q=%22unknown%20OR"))
+        .headers(user_header.clone())
+        .send()
+        .await
+        .unwrap()
+        .json::<Vec<SearchHit>>()
+        .await
+        .unwrap();
+    assert!(hits.is_empty());
+
     // Patch the comments
     for _ in 1..=N {
         // Get a valid ID from the index.
@@ -801,7 +3728,16 @@ async fn test_comments(base: &str, client: &reqwest::Client) {
             .json::<Vec<i64>>()
             .await
             .unwrap();
-        let id = list.get(0).expect("have comment");
+        let id = list.first().expect("have comment");
+        let original = client
+            .get(format!("{}/{}", api, id))
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .json::<Comment>()
+            .await
+            .unwrap();
 
         // Patch that comment.
         let comment = Comment {
@@ -810,7 +3746,8 @@ async fn test_comments(base: &str, client: &reqwest::Client) {
             content: "patched content".to_string(),
             time: NaiveDateTime::parse_from_str("2021-08-12T20:00:00", "%Y-%m-%dT%H:%M:%S")
                 .unwrap(),
-            ticket_id: ticket_id,
+            ticket_id,
+            version: original.version + 1,
         };
         let response = client
             .patch(format!("{}/{}", api, id))
@@ -836,7 +3773,7 @@ async fn test_comments(base: &str, client: &reqwest::Client) {
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::NO_CONTENT);
-        // Check that comment is patched
+        // Check that comment is patched, but keeps the time it was created at
         let response = client
             .get(format!("{}/{}", api, id))
             .headers(user_header.clone())
@@ -846,7 +3783,33 @@ async fn test_comments(base: &str, client: &reqwest::Client) {
             .json::<Comment>()
             .await
             .unwrap();
-        assert_eq!(response, comment);
+        assert_eq!(
+            response,
+            Comment {
+                time: original.time,
+                ..comment.clone()
+            }
+        );
+        // Patch only its content
+        let response = client
+            .patch(format!("{}/{}", api, id))
+            .headers(admin_header.clone())
+            .json(&serde_json::json!({"content": "partially patched"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = client
+            .get(format!("{}/{}", api, id))
+            .headers(user_header.clone())
+            .send()
+            .await
+            .unwrap()
+            .json::<Comment>()
+            .await
+            .unwrap();
+        assert_eq!(response.content, "partially patched");
+        assert_eq!(response.creator, comment.creator);
     }
 
     // Now delete all of the comments.
@@ -861,7 +3824,7 @@ async fn test_comments(base: &str, client: &reqwest::Client) {
             .json::<Vec<i64>>()
             .await
             .unwrap();
-        let id = list.get(0).expect("have comment");
+        let id = list.first().expect("have comment");
 
         let response = client
             .delete(format!("{}/{}", api, id))
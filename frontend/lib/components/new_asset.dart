@@ -3,6 +3,7 @@ import 'package:tinytickets/models/crud.dart';
 import 'package:tinytickets/models/asset.dart';
 
 import '../i18n.dart';
+import 'delete_dialog.dart';
 
 class NewEditAsset extends StatefulWidget {
   final Crud crud;
@@ -28,13 +29,41 @@ class _NewEditAssetState extends State<NewEditAsset> {
           actions: widget.asset.id > 0
               ? [
                   IconButton(
-                      icon: const Icon(Icons.delete_forever),
+                      icon: Icon(widget.asset.archived
+                          ? Icons.unarchive
+                          : Icons.archive),
+                      tooltip: MyLocalizations.of(context)!.tr(
+                          widget.asset.archived ? "unarchive" : "archive"),
                       onPressed: () async {
-                        await widget.crud.Delete(widget.asset.id);
+                        var msg = MyLocalizations.of(context)!.tr(
+                            widget.asset.archived
+                                ? "asset_unarchived"
+                                : "asset_archived");
+                        try {
+                          await widget.crud.Action(widget.asset.id,
+                              widget.asset.archived ? "unarchive" : "archive");
+                        } catch (e) {
+                          msg = e.toString();
+                        }
                         Navigator.pop(context);
-                        ScaffoldMessenger.of(context).showSnackBar(SnackBar(
-                            content: Text(MyLocalizations.of(context)!
-                                .tr("asset_deleted"))));
+                        ScaffoldMessenger.of(context)
+                            .showSnackBar(SnackBar(content: Text(msg)));
+                      }),
+                  IconButton(
+                      icon: const Icon(Icons.delete_forever),
+                      onPressed: () async {
+                        var confirmed = await showDialog<bool>(
+                          context: context,
+                          builder: (context) => DeleteDialog(),
+                        );
+                        if (confirmed!) {
+                          await widget.crud
+                              .Delete(widget.asset.id, confirm: true);
+                          Navigator.pop(context);
+                          ScaffoldMessenger.of(context).showSnackBar(SnackBar(
+                              content: Text(MyLocalizations.of(context)!
+                                  .tr("asset_deleted"))));
+                        }
                       })
                 ]
               : null,
@@ -99,7 +99,7 @@ class _NewEditTicketState extends State<NewEditTicket> {
           headers: <String, String>{'X-TOKEN': token},
           body: img);
       if (response.statusCode != 200) {
-        throw Exception(response.body.toString());
+        throw Exception(errorMessage(response));
       }
     } else {
       await http.delete(
@@ -201,6 +201,8 @@ class _NewEditTicketState extends State<NewEditTicket> {
                   crud: widget.assetsCrud,
                   callback: (val) => widget.ticket.assetId = val,
                   initialIndex: widget.ticket.assetId,
+                  // An existing ticket may be on an archived asset
+                  includeArchived: isExisting,
                 ),
                 SizedBox(height: 10),
                 TextFormField(
@@ -519,11 +521,13 @@ class AssetsDropDown extends StatefulWidget {
   final IntCallback callback;
   final Crud crud;
   final int initialIndex;
+  final bool includeArchived;
   const AssetsDropDown({
     Key? key,
     required this.crud,
     required this.callback,
     required this.initialIndex,
+    this.includeArchived = false,
   }) : super(key: key);
 
   @override
@@ -537,7 +541,9 @@ class _AssetsDropDownState extends State<AssetsDropDown> {
   @override
   void initState() {
     super.initState();
-    assets = widget.crud.ReadAll();
+    assets = widget.crud.ReadAll(
+        queryParameters:
+            widget.includeArchived ? {"include_archived": "true"} : null);
     _index = widget.initialIndex;
   }
 
@@ -572,7 +578,9 @@ class _AssetsDropDownState extends State<AssetsDropDown> {
                       child: SizedBox(
                         width: max(MediaQuery.of(context).size.width / 2, 150),
                         child: new Text(
-                          a.title,
+                          a.archived
+                              ? "${a.title} (${MyLocalizations.of(context)!.tr("archived")})"
+                              : a.title,
                           overflow: TextOverflow.ellipsis,
                         ),
                       ),
@@ -24,7 +24,7 @@ class _SettingsState extends State<Settings> {
   @override
   void initState() {
     super.initState();
-    if (App().role == Role.admin) assets = widget.crud.ReadAll();
+    if (App().role == Role.admin) assets = _readAssets();
   }
 
   @override
@@ -81,8 +81,12 @@ class _SettingsState extends State<Settings> {
                                       mainAxisSize: MainAxisSize.min,
                                       children: <Widget>[
                                         ListTile(
-                                          leading: Icon(Icons.apartment),
-                                          title: Text(a.title),
+                                          leading: Icon(a.archived
+                                              ? Icons.archive
+                                              : Icons.apartment),
+                                          title: Text(a.archived
+                                              ? "${a.title} (${MyLocalizations.of(context)!.tr("archived")})"
+                                              : a.title),
                                           subtitle: Text(a.description),
                                         ),
                                       ],
@@ -120,9 +124,15 @@ class _SettingsState extends State<Settings> {
       return NewEditAsset(crud: APICrud<Asset>(), asset: a);
     }));
     setState(() {
-      assets = widget.crud.ReadAll();
+      assets = _readAssets();
     });
   }
+
+  // The archived assets are listed too, so that they can be restored
+  Future<List<Asset>> _readAssets() async {
+    return await widget.crud
+        .ReadAll(queryParameters: {"include_archived": "true"});
+  }
 }
 
 class settingsField extends StatelessWidget {
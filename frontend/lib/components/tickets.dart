@@ -43,12 +43,34 @@ class _TicketsState extends State<Tickets> {
         .then((value) => value.statusCode == 200 ? value.body : "Tiny Tickets");
     if (App().role != Role.unknown) {
       tickets = widget.crud.ReadAll();
+      WidgetsBinding.instance.addPostFrameCallback(openLinkedAsset);
     } else {
       WidgetsBinding.instance.addPostFrameCallback(openSettings);
     }
     ;
   }
 
+  void openLinkedAsset(Duration _) {
+    final assetId = App().linkedAssetId;
+    if (assetId == null) return;
+    App().linkedAssetId = null;
+    _edit(_newTicket(assetId));
+  }
+
+  Ticket _newTicket(int assetId) {
+    return Ticket(
+      id: 0,
+      title: "",
+      creator: App().prefs.getString("creator") ?? "",
+      creatorMail: "",
+      creatorPhone: "",
+      description: "",
+      assetId: assetId,
+      status: "new",
+      time: DateTime.now(),
+    );
+  }
+
   void openSettings(Duration _) async {
     await showDialog<String>(
       context: context,
@@ -74,6 +96,7 @@ class _TicketsState extends State<Tickets> {
   void hasRoleOrOpenSettings() {
     if (App().role != Role.unknown) {
       tickets = widget.crud.ReadAll();
+      WidgetsBinding.instance.addPostFrameCallback(openLinkedAsset);
     } else {
       openSettings(Duration(seconds: 0));
     }
@@ -213,17 +236,7 @@ class _TicketsState extends State<Tickets> {
                   child: IconButton(
                       icon: const Icon(Icons.add),
                       onPressed: () {
-                        _edit(Ticket(
-                          id: 0,
-                          title: "",
-                          creator: App().prefs.getString("creator") ?? "",
-                          creatorMail: "",
-                          creatorPhone: "",
-                          description: "",
-                          assetId: 1,
-                          isClosed: false,
-                          time: DateTime.now(),
-                        ));
+                        _edit(_newTicket(1));
                       }),
                 ),
                 Text(MyLocalizations.of(context)!.tr("create_ticket")),
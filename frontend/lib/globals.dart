@@ -3,6 +3,8 @@ import 'package:shared_preferences/shared_preferences.dart';
 
 class App {
   late SharedPreferences prefs;
+  // Asset given by the link of a QR code, for which a new ticket is opened
+  int? linkedAssetId;
   App._privateConstructor();
 
   static final App _instance = App._privateConstructor();
@@ -18,6 +20,7 @@ class App {
       if (token != null) {
         App().prefs.setString("token", token);
       }
+      linkedAssetId = int.tryParse(Uri.base.queryParameters['asset_id'] ?? "");
     }
   }
 
@@ -15,7 +15,11 @@ class MyLocalizations {
   static Map<String, Map<String, String>> _localizedValues = {
     'en': {
       'asset_created': 'Asset created created or altered successfully.',
+      'archive': 'Archive',
+      'archived': 'archived',
+      'asset_archived': 'Asset archived successfully.',
       'asset_deleted': 'Asset deleted successfully.',
+      'asset_unarchived': 'Asset restored successfully.',
       'asset': 'Asset',
       'assets': 'Assets',
       'cancel': 'Cancel',
@@ -52,11 +56,16 @@ class MyLocalizations {
       'ticket_deleted': 'Ticket deleted successfully.',
       'title': 'Title',
       'token': 'Token',
+      'unarchive': 'Restore',
       'try_new_token': 'Error accessing data, please check your access token.'
     },
     'fr': {
       'asset_created': 'Actif créé ou modifié avec succès.',
+      'archive': 'Archiver',
+      'archived': 'archivé',
+      'asset_archived': 'Actif archivé avec succès.',
       'asset_deleted': 'Actif supprimé avec succès.',
+      'asset_unarchived': 'Actif restauré avec succès.',
       'asset': 'Élément concerné',
       'assets': 'Actifs',
       'cancel': 'Annuler',
@@ -94,6 +103,7 @@ class MyLocalizations {
       'ticket_deleted': 'Ticket supprimé avec succès.',
       'title': 'Titre',
       'token': 'Jeton de sécurité',
+      'unarchive': 'Restaurer',
       'try_new_token':
           'Erreur d\'accès aux données, veuillez vérifier votre jeton de sécurité.'
     },
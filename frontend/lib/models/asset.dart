@@ -4,11 +4,13 @@ class Asset extends Serialisable {
   int id;
   String title;
   String description;
+  bool archived;
 
   Asset({
     required this.id,
     required this.title,
     required this.description,
+    this.archived = false,
   });
 
   Map<String, dynamic> toJson() {
@@ -16,6 +18,7 @@ class Asset extends Serialisable {
       'id': id,
       'title': title,
       'description': description,
+      'archived': archived,
     };
   }
 
@@ -24,6 +27,7 @@ class Asset extends Serialisable {
       id: data['id'],
       title: data['title'],
       description: data['description'],
+      archived: data['archived'] ?? false,
     );
   }
 
@@ -34,11 +38,12 @@ class Asset extends Serialisable {
     return other is Asset &&
         other.id == id &&
         other.title == title &&
-        other.description == description;
+        other.description == description &&
+        other.archived == archived;
   }
 
   @override
   int get hashCode {
-    return Object.hash(id, title, description);
+    return Object.hash(id, title, description, archived);
   }
 }
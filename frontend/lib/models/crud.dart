@@ -34,6 +34,20 @@ String routeByType(Type t) {
   }
 }
 
+/// Gets the message of an error response, with the errors on each field if any
+String errorMessage(Response response) {
+  try {
+    final problem = json.decode(utf8.decode(response.bodyBytes));
+    final List errors = problem["errors"] ?? [];
+    return [
+      problem["message"],
+      ...errors.map((e) => "${e["field"]}: ${e["message"]}")
+    ].join("\n");
+  } catch (_) {
+    return response.body.toString();
+  }
+}
+
 abstract class Serialisable {
   Serialisable() {}
   fromJson(Map<String, dynamic> json) {}
@@ -46,11 +60,13 @@ abstract class Crud<T extends Serialisable> {
 
   Read(int id) {}
 
-  ReadAll() {}
+  ReadAll({Map<String, String>? queryParameters}) {}
 
   Update(T val) {}
 
-  Delete(int id) {}
+  Delete(int id, {bool confirm = false}) {}
+
+  Action(int id, String action) {}
 }
 
 class APICrud<T extends Serialisable> extends Crud<T> {
@@ -79,7 +95,7 @@ class APICrud<T extends Serialisable> extends Crud<T> {
       body: jsonEncode(val),
     );
     if (response.statusCode != 201) {
-      throw Exception(response.body.toString());
+      throw Exception(errorMessage(response));
     } else {
       return fromJSONbyType(T, json.decode(utf8.decode(response.bodyBytes)));
     }
@@ -100,9 +116,9 @@ class APICrud<T extends Serialisable> extends Crud<T> {
     }
   }
 
-  Future<List<T>> ReadAll() async {
+  Future<List<T>> ReadAll({Map<String, String>? queryParameters}) async {
     final response = await client.get(
-      Uri.parse('$base/$route/all'),
+      Uri.parse('$base/$route/all').replace(queryParameters: queryParameters),
       headers: <String, String>{'X-TOKEN': token},
     );
     if (response.statusCode == 200) {
@@ -124,17 +140,29 @@ class APICrud<T extends Serialisable> extends Crud<T> {
       body: jsonEncode(val),
     );
     if (response.statusCode != 204) {
-      throw Exception(response.body.toString());
+      throw Exception(errorMessage(response));
+    }
+  }
+
+  /// Runs an action on an object, such as archiving an asset
+  Action(int id, String action) async {
+    final response = await client.post(
+      Uri.parse('$base/$route/${id}/$action'),
+      headers: <String, String>{'X-TOKEN': token},
+    );
+    if (response.statusCode != 204) {
+      throw Exception(errorMessage(response));
     }
   }
 
-  Delete(int id) async {
+  /// Deletes an object, the assets requiring a confirmation as their tickets are deleted along with them
+  Delete(int id, {bool confirm = false}) async {
     final response = await client.delete(
-      Uri.parse('$base/$route/${id}'),
+      Uri.parse('$base/$route/${id}${confirm ? "?confirm=true" : ""}'),
       headers: <String, String>{'X-TOKEN': token},
     );
     if (response.statusCode != 200) {
-      throw Exception(response.body.toString());
+      throw Exception(errorMessage(response));
     }
   }
 }
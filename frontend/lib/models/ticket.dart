@@ -11,7 +11,8 @@ class Ticket extends Serialisable {
   String creatorMail;
   String creatorPhone;
   String description;
-  bool isClosed;
+  String status;
+  String priority;
   List<Comment> comments = [];
 
   Ticket(
@@ -23,7 +24,8 @@ class Ticket extends Serialisable {
       required this.creatorMail,
       required this.creatorPhone,
       required this.description,
-      required this.isClosed,
+      required this.status,
+      this.priority = "normal",
       this.comments = const []});
 
   Map<String, dynamic> toJson() {
@@ -36,10 +38,15 @@ class Ticket extends Serialisable {
       'creator_mail': creatorMail,
       'creator_phone': creatorPhone,
       'description': description,
-      'is_closed': isClosed
+      'status': status,
+      'priority': priority
     };
   }
 
+  bool get isClosed => status == "closed";
+
+  set isClosed(bool closed) => status = closed ? "closed" : "new";
+
   factory Ticket.fromJson(Map<String, dynamic> json) {
     return Ticket(
         id: json['id'],
@@ -52,7 +59,8 @@ class Ticket extends Serialisable {
         creatorMail: json['creator_mail'],
         creatorPhone: json['creator_phone'],
         description: json['description'],
-        isClosed: json['is_closed'],
+        status: json['status'],
+        priority: json['priority'] ?? "normal",
         comments: json['comments'] == null
             ? []
             : (json['comments'] as List)
@@ -73,7 +81,8 @@ class Ticket extends Serialisable {
         other.creatorMail == creatorMail &&
         other.creatorPhone == creatorPhone &&
         other.description == description &&
-        other.isClosed == isClosed;
+        other.status == status &&
+        other.priority == priority;
   }
 
   @override
@@ -87,7 +96,8 @@ class Ticket extends Serialisable {
       creatorMail,
       creatorPhone,
       description,
-      isClosed,
+      status,
+      priority,
     );
   }
 }
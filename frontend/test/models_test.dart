@@ -30,7 +30,7 @@ void main() {
           creatorMail: "a mail",
           creatorPhone: "a tel",
           description: "a description",
-          isClosed: false);
+          status: "new");
       final a1Json = jsonEncode(t1.toJson());
       final t2 = Ticket.fromJson(json.decode(a1Json));
       expect(t1, t2);